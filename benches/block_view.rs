@@ -0,0 +1,112 @@
+//! Compare scanning a serialized block through `BlockView` with
+//! decoding it into a `Block`.
+//!
+//! Run with `cargo bench --bench block_view`.
+
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::hashes::Hash;
+use bitcoin::{
+    Amount, Block, Network, OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use bitcoin_explorer::BlockView;
+use std::time::{Duration, Instant};
+
+/// Smaller when run by `cargo test --all-targets`, as a smoke test.
+const TX_COUNT: usize = if cfg!(debug_assertions) { 200 } else { 3000 };
+const ROUNDS: usize = 20;
+
+/// Best time of `ROUNDS` runs of `f`.
+fn best_of<F: FnMut() -> u64>(mut f: F) -> (Duration, u64) {
+    let mut best = Duration::MAX;
+    let mut checksum = 0;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        checksum = f();
+        best = best.min(start.elapsed());
+    }
+    (best, checksum)
+}
+
+/// A full block of 2-input, 2-output segwit transactions.
+fn block() -> Vec<u8> {
+    let mut block = genesis_block(Network::Bitcoin);
+    for i in 0..TX_COUNT {
+        let input = |vout: u32| TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([i as u8; 32]), vout),
+            witness: Witness::from_slice(&[vec![1u8; 72], vec![2u8; 33]]),
+            ..TxIn::default()
+        };
+        let output = |n: u64| TxOut {
+            value: Amount::from_sat(i as u64 * 1000 + n),
+            script_pubkey: ScriptBuf::from_bytes(vec![0x51; 22]),
+        };
+        block.txdata.push(Transaction {
+            input: vec![input(0), input(1)],
+            output: vec![output(0), output(1)],
+            ..block.txdata[0].clone()
+        });
+    }
+    serialize(&block)
+}
+
+fn report(name: &str, decode: (Duration, u64), view: (Duration, u64)) {
+    assert_eq!(
+        decode.1, view.1,
+        "{name}: BlockView returned different data"
+    );
+    println!(
+        "{name:>8}: decode {:>9.2?}, BlockView {:>9.2?} ({:.2}x faster)",
+        decode.0,
+        view.0,
+        decode.0.as_secs_f64() / view.0.as_secs_f64()
+    );
+}
+
+fn main() {
+    let raw = block();
+    let decoded = || deserialize::<Block>(&raw).unwrap();
+    let view = || BlockView::new(&raw).unwrap();
+
+    report(
+        "values",
+        best_of(|| {
+            let block = decoded();
+            let outputs = block.txdata.iter().flat_map(|tx| tx.output.iter());
+            outputs.map(|o| o.value.to_sat()).sum()
+        }),
+        best_of(|| {
+            let txs = view().transactions().map(|tx| tx.unwrap());
+            txs.flat_map(|tx| tx.outputs())
+                .map(|o| o.value.to_sat())
+                .sum()
+        }),
+    );
+    report(
+        "outpoints",
+        best_of(|| {
+            let block = decoded();
+            let inputs = block.txdata.iter().flat_map(|tx| tx.input.iter());
+            inputs.map(|i| i.previous_output.vout as u64).sum()
+        }),
+        best_of(|| {
+            let txs = view().transactions().map(|tx| tx.unwrap());
+            txs.flat_map(|tx| tx.inputs())
+                .map(|i| i.previous_output.vout as u64)
+                .sum()
+        }),
+    );
+    report(
+        "txids",
+        best_of(|| {
+            let block = decoded();
+            let txids = block.txdata.iter().map(|tx| tx.compute_txid());
+            txids.map(|txid| txid.to_byte_array()[0] as u64).sum()
+        }),
+        best_of(|| {
+            let txs = view().transactions().map(|tx| tx.unwrap());
+            let txids = txs.map(|tx| tx.compute_txid());
+            txids.map(|txid| txid.to_byte_array()[0] as u64).sum()
+        }),
+    );
+}
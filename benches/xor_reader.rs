@@ -0,0 +1,126 @@
+//! Compare reading an obfuscated blk file through `XorReader` with
+//! reading a plain file through `BufReader`.
+//!
+//! Run with `cargo bench --bench xor_reader`.
+
+use bitcoin_explorer::parser::xor::{xor_in_place, XorReader, XOR_MASK_LEN};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Smaller when run by `cargo test --all-targets`, as a smoke test.
+const FILE_SIZE: usize = if cfg!(debug_assertions) {
+    8 << 20
+} else {
+    128 << 20
+};
+const MASK: [u8; XOR_MASK_LEN] = [0x5a, 0x13, 0xc7, 0x88, 0x01, 0xfe, 0x42, 0x9d];
+const ROUNDS: usize = 5;
+
+/// Best time of `ROUNDS` runs of `f`.
+fn best_of<F: FnMut() -> u64>(mut f: F) -> (Duration, u64) {
+    let mut best = Duration::MAX;
+    let mut checksum = 0;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        checksum = f();
+        best = best.min(start.elapsed());
+    }
+    (best, checksum)
+}
+
+/// Read the whole file in 1 MB chunks, as blocks are read.
+fn read_large<R: Read>(mut r: R) -> u64 {
+    let mut buf = vec![0u8; 1 << 20];
+    let mut sum = 0u64;
+    loop {
+        let n = r.read(&mut buf).unwrap();
+        if n == 0 {
+            return sum;
+        }
+        sum = buf[..n].iter().fold(sum, |s, x| s.wrapping_add(*x as u64));
+    }
+}
+
+/// Read 4 then 28 bytes repeatedly, as fields are decoded.
+fn read_small<R: Read>(mut r: R) -> u64 {
+    let (mut a, mut b) = ([0u8; 4], [0u8; 28]);
+    let mut sum = 0u64;
+    for _ in 0..FILE_SIZE / 32 {
+        r.read_exact(&mut a).unwrap();
+        r.read_exact(&mut b).unwrap();
+        sum = sum.wrapping_add(a[0] as u64 + b[27] as u64);
+    }
+    sum
+}
+
+/// Seek to 4096 positions and read 300 bytes, as transactions are read.
+fn read_random<R: Read + Seek>(mut r: R) -> u64 {
+    let mut buf = [0u8; 300];
+    let mut sum = 0u64;
+    let mut pos = 17u64;
+    for _ in 0..4096 {
+        pos = pos
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407)
+            % (FILE_SIZE as u64 - 300);
+        r.seek(SeekFrom::Start(pos)).unwrap();
+        r.read_exact(&mut buf).unwrap();
+        sum = sum.wrapping_add(buf[0] as u64);
+    }
+    sum
+}
+
+fn report(name: &str, plain: (Duration, u64), xor: (Duration, u64)) {
+    assert_eq!(plain.1, xor.1, "{name}: XorReader returned different data");
+    println!(
+        "{name:>8}: BufReader {:>9.2?}, XorReader {:>9.2?} ({:.2}x)",
+        plain.0,
+        xor.0,
+        xor.0.as_secs_f64() / plain.0.as_secs_f64()
+    );
+}
+
+fn main() {
+    let dir = std::env::temp_dir();
+    let plain_path = dir.join(format!("xor-bench-plain-{}.dat", std::process::id()));
+    let xor_path = dir.join(format!("xor-bench-xor-{}.dat", std::process::id()));
+    let mut data: Vec<u8> = (0..FILE_SIZE).map(|i| (i * 31 + i / 4093) as u8).collect();
+    std::fs::write(&plain_path, &data).unwrap();
+    xor_in_place(&mut data, &MASK, 0);
+    std::fs::write(&xor_path, &data).unwrap();
+
+    let open = |path: &Path| File::open(path).unwrap();
+    let xor_reader = |path: &Path| XorReader::new(open(path), Some(MASK));
+
+    let (plain, xor) = (plain_path.as_path(), xor_path.as_path());
+    report(
+        "large",
+        best_of(|| read_large(BufReader::new(open(plain)))),
+        best_of(|| read_large(xor_reader(xor))),
+    );
+    report(
+        "small",
+        best_of(|| read_small(BufReader::new(open(plain)))),
+        best_of(|| read_small(xor_reader(xor))),
+    );
+    report(
+        "random",
+        best_of(|| read_random(BufReader::new(open(plain)))),
+        best_of(|| read_random(xor_reader(xor))),
+    );
+
+    let mut buf = data;
+    let (xor_time, _) = best_of(|| {
+        xor_in_place(&mut buf, &MASK, 3);
+        buf[0] as u64
+    });
+    println!(
+        "xor_in_place: {:.0} MB/s",
+        FILE_SIZE as f64 / xor_time.as_secs_f64() / 1e6
+    );
+
+    std::fs::remove_file(plain_path).unwrap();
+    std::fs::remove_file(xor_path).unwrap();
+}
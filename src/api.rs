@@ -18,27 +18,65 @@
 //! let db = BitcoinDB::new(path, true).unwrap();
 //! ```
 
+use crate::iter::block_stats_iter::block_stats;
+use crate::iter::block_verify_iter::verify_block;
+#[cfg(feature = "verify-scripts")]
+use crate::iter::script_verify_iter::verify_block_scripts;
+use crate::iter::utxo_set_hash_iter::hash_serialized_3;
 use crate::parser::blk_file::BlkFile;
+use crate::parser::block_types::connected_block::connect_input;
 use crate::parser::error::{Error, Result};
+use crate::parser::mempool::mempool_outputs;
 use crate::parser::script::{evaluate_script, ScriptInfo};
 use crate::parser::tx_index::TxDB;
+use rayon::prelude::*;
 use std::ops::Deref;
 use std::path::Path;
 use std::sync::Arc;
 
 // re-exports
-pub use crate::iter::{BlockIter, ConnectedBlockIter};
+pub use crate::iter::{
+    check_block, duplicate_coinbases, BlockError, BlockIter, BlockStats, BlockStatsIter,
+    BlockVerification, BlockVerifyIter, ConnectedBlockIter, ConnectedIterOptions,
+    DuplicateCoinbase, InMemoryUtxo, TryBlockIter, TryConnectedBlockIter, UtxoBackend,
+    UtxoBackendKind, UtxoSetHashIter, UtxoSetInfo,
+};
+#[cfg(feature = "verify-scripts")]
+pub use crate::iter::{
+    script_flags, verify_tx_scripts, ScriptFailure, ScriptVerification, ScriptVerifyIter,
+};
+#[cfg(feature = "on-disk-utxo")]
+pub use crate::iter::{HybridUtxo, OnDiskUtxo};
+pub use crate::parser::asm::{script_instructions, script_to_asm, ScriptInstruction};
 pub use crate::parser::block_index::{BlockIndex, BlockIndexRecord};
 pub use crate::parser::block_types::compact_block::{
     CompactBlock, CompactBlockHeader, CompactTransaction, CompactTxOut,
 };
 pub use crate::parser::block_types::connected_block::{
-    CompactConnectedBlock, CompactConnectedTransaction, ConnectedBlock, ConnectedTx,
-    FullConnectedBlock, FullConnectedTransaction,
+    CompactConnectedBlock, CompactConnectedTransaction, CompactConnectedTxIn, ConnectedBlock,
+    ConnectedTx, FullConnectedBlock, FullConnectedTransaction, FullConnectedTxIn,
 };
 pub use crate::parser::block_types::full_block::{
-    FullBlock, FullBlockHeader, FullTransaction, FullTxOut,
+    FullBlock, FullBlockHeader, FullTransaction, FullTxIn, FullTxOut,
+};
+pub use crate::parser::block_types::projection::{BlockContext, BlockProjection};
+pub use crate::parser::block_types::view::{BlockView, TxInView, TxOutView, TxView, TxViews};
+pub use crate::parser::fee_estimates::{FeeEstimator, SmartFeeEstimate, TxConfirmStats};
+pub use crate::parser::header_chain::{
+    HeaderChainReport, HeaderChainValidator, HeaderError, HeaderViolation,
 };
+pub use crate::parser::input::{InputType, RevealedScript, RevealedScripts, TapScriptPath};
+pub use crate::parser::mempool::{ConnectedMempoolEntry, MempoolEntry, MempoolFile};
+pub use crate::parser::muhash::{MuHash3072, MuHashDigest};
+pub use crate::parser::policy::{
+    check_standard, check_standard_input, check_standard_tx, legacy_sigop_count, sigop_cost,
+    sigop_count, NonStandardReason, TxoutType,
+};
+pub use crate::parser::rpc::{
+    RpcBlock, RpcPrevout, RpcScriptPubKey, RpcScriptSig, RpcTransaction, RpcVin, RpcVout,
+    TxVerbosity,
+};
+pub use crate::parser::undo::{BlockUndo, Coin, TxUndo};
 pub use bitcoin::blockdata::block::Header as BlockHeader;
 pub use bitcoin::hashes::hex::FromHex;
 pub use bitcoin::{Address, Block, BlockHash, Network, Script, ScriptBuf, Transaction, Txid};
@@ -101,6 +139,35 @@ impl BitcoinDB {
     /// let db = BitcoinDB::new(path, true).unwrap();
     /// ```
     pub fn new(data_dir: &Path, tx_index: bool) -> Result<Self> {
+        Ok(Self(Arc::new(Self::open(data_dir, tx_index)?)))
+    }
+
+    /// Same as [`BitcoinDB::new`], but blk files are memory-mapped, keeping at
+    /// most `max_mapped_files` of the most recently used ones mapped.
+    ///
+    /// Random reads (`get_transaction`, connecting inputs through the txindex)
+    /// then copy from memory instead of opening and reading a file each time.
+    /// The datadir must not be pruned while it is read.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bitcoin_explorer::BitcoinDB;
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // keep up to 64 blk files (128 MB each) mapped
+    /// let db = BitcoinDB::new_mmap(path, true, 64).unwrap();
+    /// ```
+    #[cfg(feature = "mmap")]
+    pub fn new_mmap(data_dir: &Path, tx_index: bool, max_mapped_files: usize) -> Result<Self> {
+        let mut inner = Self::open(data_dir, tx_index)?;
+        inner.blk_file = inner.blk_file.with_mmap_pool(max_mapped_files);
+        Ok(Self(Arc::new(inner)))
+    }
+
+    fn open(data_dir: &Path, tx_index: bool) -> Result<InnerDB> {
         if !data_dir.exists() {
             return Err(Error::BitcoinDataDirDoesNotExist(data_dir.to_path_buf()));
         }
@@ -115,13 +182,11 @@ impl BitcoinDB {
             None
         };
 
-        let inner = InnerDB {
+        Ok(InnerDB {
             block_index,
             blk_file: BlkFile::new(blk_path.as_path())?,
             tx_db,
-        };
-
-        Ok(Self(Arc::new(inner)))
+        })
     }
 
     /// Get the maximum height found in block index.
@@ -204,7 +269,7 @@ impl BitcoinDB {
             .ok_or(Error::BlockHashNotFound(*hash))
     }
 
-    /// Get a raw block as bytes
+    /// Get a raw block as bytes, see [`BlockView`] to read it without decoding.
     pub fn get_raw_block(&self, height: usize) -> Result<Vec<u8>> {
         let index = self
             .block_index
@@ -242,6 +307,227 @@ impl BitcoinDB {
             .map(Into::into)
     }
 
+    /// Get a user-defined [`BlockProjection`] of a block, built from the borrowed block.
+    ///
+    /// The prevouts of [`BlockContext`] are `None`, see
+    /// [`BitcoinDB::connected_block_projection_iter`] for connected projections.
+    pub fn get_block_projection<P: BlockProjection>(&self, height: usize) -> Result<P> {
+        let block: Block = self.get_block(height)?;
+        let context = BlockContext {
+            height,
+            block_hash: block.block_hash(),
+            prevouts: None,
+        };
+        Ok(P::project(&block, &context))
+    }
+
+    /// Get the undo data of a block, i.e., the outputs spent by its transactions.
+    ///
+    /// The genesis block has no undo data.
+    pub fn get_block_undo(&self, height: usize) -> Result<BlockUndo> {
+        let index = self
+            .block_index
+            .records
+            .get(height)
+            .ok_or(Error::BlockIndexRecordNotFound(height))?;
+        if index.n_undo_pos == u32::MAX {
+            return Err(Error::BlockUndoNotFound(height));
+        }
+        self.blk_file.read_undo(index.n_file, index.n_undo_pos)
+    }
+
+    /// Get the statistics of a block, equivalent to `getblockstats` of Bitcoin Core.
+    ///
+    /// Spent outputs are read from undo data, so this does NOT require `txindex=true`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use bitcoin_explorer::BitcoinDB;
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// let stats = db.get_block_stats(600000).unwrap();
+    /// println!("median fee rate: {} sat/vB", stats.feerate_percentiles[2]);
+    /// ```
+    pub fn get_block_stats(&self, height: usize) -> Result<BlockStats> {
+        block_stats(self, height)
+    }
+
+    /// Iterate through the statistics of blocks from `start` to `end` (excluded),
+    /// see [`BitcoinDB::get_block_stats`].
+    ///
+    /// Blocks are processed in parallel, and yielded in order.
+    ///
+    /// # Example
+    /// ```rust
+    /// use bitcoin_explorer::BitcoinDB;
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// for stats in db.block_stats_iter(600000, 700000) {
+    ///     println!("{}: {} sat", stats.height, stats.totalfee);
+    /// }
+    /// ```
+    pub fn block_stats_iter(&self, start: usize, end: usize) -> BlockStatsIter {
+        BlockStatsIter::new(self, start, end)
+    }
+
+    /// Read a block again from its blk file and check it against the consensus
+    /// rules that do not need the UTXO set, see [`check_block`].
+    ///
+    /// The coinbase amount is checked against the fees computed from undo data.
+    /// Read failures are reported as [`BlockError::Unreadable`].
+    ///
+    /// # Example
+    /// ```rust
+    /// use bitcoin_explorer::BitcoinDB;
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// let result = db.verify_block(600000).unwrap();
+    /// assert!(result.is_valid());
+    /// ```
+    pub fn verify_block(&self, height: usize) -> Result<BlockVerification> {
+        verify_block(self, height)
+    }
+
+    /// Check blocks from `start` to `end` (excluded), see [`BitcoinDB::verify_block`].
+    ///
+    /// Blocks are checked in parallel, and yielded in order.
+    ///
+    /// # Example
+    /// ```rust
+    /// use bitcoin_explorer::BitcoinDB;
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// for result in db.verify_block_iter(0, 700000) {
+    ///     for e in result.errors {
+    ///         println!("block {}: {}", result.height, e);
+    ///     }
+    /// }
+    /// ```
+    pub fn verify_block_iter(&self, start: usize, end: usize) -> BlockVerifyIter {
+        BlockVerifyIter::new(self, start, end)
+    }
+
+    /// Check the proof of work, difficulty retargeting, timestamps and
+    /// versions of all headers in the block index, see [`HeaderChainValidator`].
+    ///
+    /// # Example
+    /// ```rust
+    /// use bitcoin_explorer::BitcoinDB;
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// let report = db.validate_header_chain();
+    /// assert!(report.is_valid());
+    /// ```
+    pub fn validate_header_chain(&self) -> HeaderChainReport {
+        HeaderChainValidator::new(&self.block_index).validate()
+    }
+
+    /// Verify the scripts of all inputs of a block with libbitcoinconsensus,
+    /// using the flags enforced at its height (see [`script_flags`]).
+    ///
+    /// Spent outputs are read from undo data, so this does NOT require `txindex=true`.
+    /// Requires the `verify-scripts` feature.
+    ///
+    /// # Example
+    /// ```rust
+    /// use bitcoin_explorer::BitcoinDB;
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// let result = db.verify_scripts(600000).unwrap();
+    /// for failure in result.failures {
+    ///     println!("{}:{} {}", failure.txid, failure.input, failure.error);
+    /// }
+    /// ```
+    #[cfg(feature = "verify-scripts")]
+    pub fn verify_scripts(&self, height: usize) -> Result<ScriptVerification> {
+        verify_block_scripts(self, height)
+    }
+
+    /// Verify the scripts of blocks from `start` to `end` (excluded),
+    /// see [`BitcoinDB::verify_scripts`].
+    ///
+    /// Blocks are processed in parallel, and yielded in order.
+    #[cfg(feature = "verify-scripts")]
+    pub fn verify_scripts_iter(&self, start: usize, end: usize) -> ScriptVerifyIter {
+        ScriptVerifyIter::new(self, start, end)
+    }
+
+    /// Get a block in the JSON format of `getblock <hash> 2`, or `getblock <hash> 3`
+    /// with [`TxVerbosity::ShowDetailsAndPrevout`].
+    ///
+    /// Fees and prevouts are read from undo data, so this does NOT require `txindex=true`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use bitcoin_explorer::{BitcoinDB, TxVerbosity};
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// let block = db.get_block_json(600000, TxVerbosity::ShowDetailsAndPrevout).unwrap();
+    /// println!("{} confirmations", block.confirmations);
+    /// ```
+    pub fn get_block_json(&self, height: usize, verbosity: TxVerbosity) -> Result<RpcBlock> {
+        let block = self.get_block::<Block>(height)?;
+        let undo = match self.get_block_undo(height) {
+            Ok(undo) => Some(undo),
+            Err(Error::BlockUndoNotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(RpcBlock::new(
+            &block,
+            height,
+            &self.block_index,
+            undo.as_ref(),
+            verbosity,
+        ))
+    }
+
+    /// Get a transaction in the JSON format of `getrawtransaction <txid> true`.
+    ///
+    /// This function requires `txindex` to be set to `true` for `BitcoinDB`,
+    /// and requires that flag `txindex=1` has been enabled when
+    /// running Bitcoin Core.
+    pub fn get_transaction_json(&self, txid: Txid) -> Result<RpcTransaction> {
+        let tx = self.get_transaction::<Transaction>(txid)?;
+        let height = self.get_block_height(txid)?;
+        Ok(RpcTransaction::new(&tx, None, TxVerbosity::ShowDetails)
+            .with_block(height, &self.block_index))
+    }
+
     /// Get a transaction by providing txid.
     ///
     /// This function requires `txindex` to be set to `true` for `BitcoinDB`,
@@ -355,6 +641,106 @@ impl BitcoinDB {
         BlockIter::from_range(self, start, end)
     }
 
+    /// Same as [`BitcoinDB::block_iter`], but yields `Err` with the failing
+    /// height and cause instead of silently stopping at the first error.
+    ///
+    /// # Example
+    /// ```rust
+    /// use bitcoin_explorer::{BitcoinDB, Block};
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// for block in db.try_block_iter::<Block>(600000, 700000) {
+    ///     match block {
+    ///         Ok(block) => println!("{}", block.block_hash()),
+    ///         Err(e) => panic!("iteration failed: {}", e),
+    ///     }
+    /// }
+    /// ```
+    pub fn try_block_iter<B>(&self, start: usize, end: usize) -> TryBlockIter<B>
+    where
+        B: From<Block> + Send + 'static,
+    {
+        TryBlockIter::from_range(self, start, end)
+    }
+
+    /// Iterate through blocks from `start` to `end` (excluded), building a
+    /// user-defined [`BlockProjection`] of each block in the worker threads.
+    ///
+    /// Projections borrow the decoded block, so no script is cloned or
+    /// evaluated unless the projection does it.
+    ///
+    /// # Example
+    /// ```rust
+    /// use bitcoin_explorer::{BitcoinDB, Block, BlockContext, BlockProjection};
+    /// use std::path::Path;
+    ///
+    /// /// number of OP_RETURN outputs of a block
+    /// struct OpReturnCount(usize);
+    ///
+    /// impl BlockProjection for OpReturnCount {
+    ///     fn project(block: &Block, _context: &BlockContext) -> Self {
+    ///         let outputs = block.txdata.iter().flat_map(|tx| tx.output.iter());
+    ///         OpReturnCount(outputs.filter(|o| o.script_pubkey.is_op_return()).count())
+    ///     }
+    /// }
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// for count in db.block_projection_iter::<OpReturnCount>(600000, 700000) {
+    ///     println!("{}", count.expect("iteration failed").0);
+    /// }
+    /// ```
+    pub fn block_projection_iter<P>(&self, start: usize, end: usize) -> TryBlockIter<P>
+    where
+        P: BlockProjection + Send + 'static,
+    {
+        TryBlockIter::projected(self, start, end)
+    }
+
+    /// Iterate through blocks from `start` to `end` (excluded) as borrowed
+    /// [`BlockView`]s, keeping what `f` returns for each block.
+    ///
+    /// Blocks are not decoded: reading a few fields of each block with
+    /// `f` is much faster than [`BitcoinDB::block_iter`].
+    ///
+    /// # Example
+    /// ```rust
+    /// use bitcoin_explorer::BitcoinDB;
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// // count the OP_RETURN outputs of each block
+    /// let counts = db.block_view_iter(600000, 700000, |_height, block| {
+    ///     let mut count = 0;
+    ///     for tx in block.transactions() {
+    ///         count += tx?.outputs().filter(|o| o.script_pubkey.is_op_return()).count();
+    ///     }
+    ///     Ok(count)
+    /// });
+    /// for count in counts {
+    ///     println!("{}", count.expect("iteration failed"));
+    /// }
+    /// ```
+    pub fn block_view_iter<T, F>(&self, start: usize, end: usize, f: F) -> TryBlockIter<T>
+    where
+        T: Send + 'static,
+        F: Fn(usize, &BlockView) -> Result<T> + Send + Sync + 'static,
+    {
+        TryBlockIter::viewed(self, start, end, f)
+    }
+
     /// Iterate through all blocks of given list of heights.
     ///
     /// Formats: `Block` / `FullBlock` / `CompactBlock`.
@@ -445,6 +831,55 @@ impl BitcoinDB {
         T::connect(tx, tx_db, &self.block_index, &self.blk_file)
     }
 
+    /// Connect the inputs of transactions read from `mempool.dat`.
+    ///
+    /// Inputs spending unconfirmed outputs are connected using the other
+    /// transactions of `mempool`. Inputs spending confirmed outputs require
+    /// `txindex` to be set to `true` for `BitcoinDB`, otherwise they are
+    /// left unconnected and the fee of the transaction is unknown.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bitcoin_explorer::{BitcoinDB, MempoolFile};
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// let db = BitcoinDB::new(path, true).unwrap();
+    /// let mempool = MempoolFile::read(&path.join("mempool.dat")).unwrap();
+    ///
+    /// for entry in db.connect_mempool(mempool) {
+    ///     if let Some(fee_rate) = entry.fee_rate() {
+    ///         println!("{}: {:.2} sat/vB", entry.txid, fee_rate);
+    ///     }
+    /// }
+    /// ```
+    pub fn connect_mempool(&self, mempool: MempoolFile) -> Vec<ConnectedMempoolEntry> {
+        let unconfirmed = mempool_outputs(&mempool.entries);
+        mempool
+            .entries
+            .into_par_iter()
+            .map(|entry| {
+                let prevouts = entry
+                    .tx
+                    .input
+                    .iter()
+                    .map(|input| {
+                        let outpoint = input.previous_output;
+                        if let Some(out) = unconfirmed.get(&(outpoint.txid, outpoint.vout)) {
+                            return Some(out.clone());
+                        }
+                        let tx_db = self.tx_db.as_ref()?;
+                        connect_input(input, tx_db, &self.block_index, &self.blk_file)
+                            .map(|coin| coin.out)
+                    })
+                    .collect();
+                ConnectedMempoolEntry::new(entry, prevouts)
+            })
+            .collect()
+    }
+
     /// Returns [`ConnectedBlockIter`] for iterating through all blocks for a given heights (excluded).
     ///
     /// Format: `full (FullConnectedBlock)` / `simple (CompactConnectedBlock)`.
@@ -456,16 +891,22 @@ impl BitcoinDB {
     ///
     /// This does NOT require `txindex=true`.
     ///
+    /// The coinbases repeated at heights 91842 and 91880 overwrite the unspent
+    /// outputs of the identical coinbases at 91812 and 91722, as in Bitcoin Core
+    /// (see [`duplicate_coinbases`]): these outpoints can only be spent once.
+    ///
     /// # Performance
     ///
-    /// ## Using default feature:
+    /// ## Using the on-disk backend (default with feature `on-disk-utxo`):
     ///
     /// Requires 4 GB memory, finishes in 2.5 hours from 0-700000 block.
     ///
-    /// ## Using non-default feature
+    /// ## Using the in-memory backend
     ///
     /// Requires 32 GB memory, finished in 30 minutes from 0-700000 block.
     ///
+    /// See [`BitcoinDB::connected_block_iter_with_options`] to select the backend at runtime.
+    ///
     /// # Example
     ///
     /// ```rust
@@ -490,4 +931,288 @@ impl BitcoinDB {
     {
         ConnectedBlockIter::new(self, end)
     }
+
+    /// Same as [`BitcoinDB::connected_block_iter`], but yields `Err` with the
+    /// failing height and cause (e.g. a missing blk file or previous output)
+    /// instead of silently stopping at the first error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bitcoin_explorer::{BitcoinDB, CompactConnectedBlock};
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// for block in db.try_connected_block_iter::<CompactConnectedBlock>(700000) {
+    ///     let block = block.expect("iteration failed");
+    ///     println!("{}", block.txdata.len());
+    /// }
+    /// ```
+    pub fn try_connected_block_iter<B>(&self, end: usize) -> TryConnectedBlockIter<B>
+    where
+        B: ConnectedBlock + Send + 'static,
+    {
+        TryConnectedBlockIter::new(self, end)
+    }
+
+    /// Same as [`BitcoinDB::try_connected_block_iter`], with the UTXO backend
+    /// selected at runtime in `options`.
+    ///
+    /// - [`UtxoBackendKind::InMemory`]: fastest, about 32 GB of memory on mainnet.
+    /// - [`UtxoBackendKind::OnDisk`]: a temporary rocksdb cache, about 4 GB of memory.
+    /// - [`UtxoBackendKind::Hybrid`]: recent outputs are kept in memory up to about
+    ///   `memory_budget` bytes, the oldest ones are spilled to a temporary rocksdb
+    ///   cache. Since most outputs are spent soon after being created, this gets
+    ///   most of the speed of the in-memory cache with a fraction of its memory.
+    ///
+    /// The default is `OnDisk` with the `on-disk-utxo` feature, which is
+    /// required by the rocksdb backends, and `InMemory` otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bitcoin_explorer::{BitcoinDB, CompactConnectedBlock, ConnectedIterOptions, UtxoBackendKind};
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// // keep about 8 GB of outputs in memory
+    /// let options = ConnectedIterOptions::with_backend(UtxoBackendKind::Hybrid { memory_budget: 8 << 30 });
+    /// for block in db.try_connected_block_iter_with_options::<CompactConnectedBlock>(700000, &options) {
+    ///     let block = block.expect("iteration failed");
+    ///     println!("{}", block.txdata.len());
+    /// }
+    /// ```
+    pub fn try_connected_block_iter_with_options<B>(
+        &self,
+        end: usize,
+        options: &ConnectedIterOptions,
+    ) -> TryConnectedBlockIter<B>
+    where
+        B: ConnectedBlock + Send + 'static,
+    {
+        TryConnectedBlockIter::with_options(self, end, options)
+    }
+
+    /// Same as [`BitcoinDB::connected_block_iter`], with the UTXO backend
+    /// selected at runtime in `options`, see
+    /// [`BitcoinDB::try_connected_block_iter_with_options`].
+    pub fn connected_block_iter_with_options<B>(
+        &self,
+        end: usize,
+        options: &ConnectedIterOptions,
+    ) -> ConnectedBlockIter<B>
+    where
+        B: ConnectedBlock + Send + 'static,
+    {
+        ConnectedBlockIter::with_options(self, end, options)
+    }
+
+    /// Same as [`BitcoinDB::try_connected_block_iter_with_options`], building a
+    /// user-defined [`BlockProjection`] of each block in the worker threads.
+    ///
+    /// [`BlockContext::prevouts`] holds the outputs spent by the block, with
+    /// the height and coinbase flag of the transactions that created them.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bitcoin_explorer::{BitcoinDB, Block, BlockContext, BlockProjection, ConnectedIterOptions};
+    /// use std::path::Path;
+    ///
+    /// /// total value of the coinbase outputs spent by a block
+    /// struct CoinbaseSpent(u64);
+    ///
+    /// impl BlockProjection for CoinbaseSpent {
+    ///     fn project(_block: &Block, context: &BlockContext) -> Self {
+    ///         let prevouts = context.prevouts.unwrap_or_default().iter();
+    ///         let coinbase = prevouts.filter(|coin| coin.is_coinbase);
+    ///         CoinbaseSpent(coinbase.map(|coin| coin.out.value.to_sat()).sum())
+    ///     }
+    /// }
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// let options = ConnectedIterOptions::default();
+    /// for spent in db.connected_block_projection_iter::<CoinbaseSpent>(700000, &options) {
+    ///     println!("{}", spent.expect("iteration failed").0);
+    /// }
+    /// ```
+    pub fn connected_block_projection_iter<P>(
+        &self,
+        end: usize,
+        options: &ConnectedIterOptions,
+    ) -> TryConnectedBlockIter<P>
+    where
+        P: BlockProjection + Send + 'static,
+    {
+        TryConnectedBlockIter::projected(self, end, options)
+    }
+
+    /// Same as [`BitcoinDB::try_connected_block_iter`], keeping unspent outputs
+    /// in a user-provided [`UtxoBackend`] (e.g. LMDB or sled), which must be empty.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bitcoin_explorer::{BitcoinDB, CompactConnectedBlock, InMemoryUtxo};
+    /// use std::path::Path;
+    /// use std::sync::Arc;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// let backend = Arc::new(InMemoryUtxo::new());
+    /// for block in db.try_connected_block_iter_with_backend::<CompactConnectedBlock, _>(700000, backend) {
+    ///     let block = block.expect("iteration failed");
+    ///     println!("{}", block.txdata.len());
+    /// }
+    /// ```
+    pub fn try_connected_block_iter_with_backend<B, U>(
+        &self,
+        end: usize,
+        backend: Arc<U>,
+    ) -> TryConnectedBlockIter<B>
+    where
+        B: ConnectedBlock + Send + 'static,
+        U: UtxoBackend<<B::Tx as ConnectedTx>::TxOut> + ?Sized + 'static,
+    {
+        TryConnectedBlockIter::with_backend(self, end, backend)
+    }
+
+    /// Same as [`BitcoinDB::try_connected_block_iter`], but keeps the UTXO cache
+    /// in `dir` instead of a temporary directory, and checkpoints it every
+    /// `interval` blocks.
+    ///
+    /// Blocks are connected in segments of `interval` blocks, and a checkpoint
+    /// is written when a segment is done. If the process stops, resume with
+    /// [`BitcoinDB::connected_block_iter_from_checkpoint`].
+    ///
+    /// Any previous cache in `dir` is removed. Requires the `on-disk-utxo` feature.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bitcoin_explorer::{BitcoinDB, CompactConnectedBlock};
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    /// let cache = Path::new("/Users/me/utxo_cache");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// for block in db.connected_block_iter_with_checkpoints::<CompactConnectedBlock>(cache, 800000, 10000) {
+    ///     let block = block.expect("iteration failed");
+    ///     println!("{}", block.txdata.len());
+    /// }
+    /// ```
+    #[cfg(feature = "on-disk-utxo")]
+    pub fn connected_block_iter_with_checkpoints<B>(
+        &self,
+        dir: &Path,
+        end: usize,
+        interval: usize,
+    ) -> TryConnectedBlockIter<B>
+    where
+        B: ConnectedBlock + Send + 'static,
+    {
+        TryConnectedBlockIter::with_checkpoints(self, dir, end, interval)
+    }
+
+    /// Resume connected iteration from the last checkpoint written in `dir` by
+    /// [`BitcoinDB::connected_block_iter_with_checkpoints`], up to `end` (excluded).
+    ///
+    /// The first block yielded is at [`TryConnectedBlockIter::start_height`].
+    /// Checkpoints keep being written with the same interval.
+    /// A checkpoint written by a version with another cache format yields
+    /// [`Error::IncompatibleCheckpoint`].
+    ///
+    /// Requires the `on-disk-utxo` feature.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bitcoin_explorer::{BitcoinDB, CompactConnectedBlock};
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    /// let cache = Path::new("/Users/me/utxo_cache");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// let iter = db.connected_block_iter_from_checkpoint::<CompactConnectedBlock>(cache, 800000);
+    /// println!("resuming from {}", iter.start_height());
+    /// for block in iter {
+    ///     let block = block.expect("iteration failed");
+    ///     println!("{}", block.txdata.len());
+    /// }
+    /// ```
+    #[cfg(feature = "on-disk-utxo")]
+    pub fn connected_block_iter_from_checkpoint<B>(
+        &self,
+        dir: &Path,
+        end: usize,
+    ) -> TryConnectedBlockIter<B>
+    where
+        B: ConnectedBlock + Send + 'static,
+    {
+        TryConnectedBlockIter::from_checkpoint(self, dir, end)
+    }
+
+    /// Iterate through blocks from 0 to `end` (excluded), yielding the
+    /// UTXO set summary (`txouts`, `total_amount` and `muhash`) after each block.
+    ///
+    /// The values match `gettxoutsetinfo muhash` of Bitcoin Core at that height.
+    ///
+    /// This reads the undo data (`rev*.dat`) to find spent outputs,
+    /// so it does NOT require `txindex=true`, and it does not keep
+    /// the UTXO set in memory.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use bitcoin_explorer::BitcoinDB;
+    /// use std::path::Path;
+    ///
+    /// let path = Path::new("/Users/me/bitcoin");
+    ///
+    /// // launch without reading txindex
+    /// let db = BitcoinDB::new(path, false).unwrap();
+    ///
+    /// for info in db.utxo_set_hash_iter(700000) {
+    ///     if info.height % 10000 == 0 {
+    ///         println!("{}: {}", info.height, info.muhash.finalize());
+    ///     }
+    /// }
+    /// ```
+    pub fn utxo_set_hash_iter(&self, end: usize) -> UtxoSetHashIter {
+        UtxoSetHashIter::new(self, end)
+    }
+
+    /// Compute `hash_serialized_3` of the UTXO set after the block at `height`.
+    ///
+    /// The value matches `gettxoutsetinfo hash_serialized_3` of Bitcoin Core.
+    ///
+    /// # Performance Warning
+    ///
+    /// This hash covers the UTXO set sorted by outpoint, so all blocks
+    /// up to `height` are replayed into an in-memory UTXO set.
+    /// For mainnet, this requires 32 GB+ memory.
+    pub fn get_hash_serialized_3(&self, height: usize) -> Result<bitcoin::hashes::sha256d::Hash> {
+        hash_serialized_3(self, height)
+    }
 }
@@ -0,0 +1,99 @@
+//! Coinbase transactions with duplicate txids (BIP30).
+//!
+//! Before BIP34 required the height in the coinbase, two coinbase
+//! transactions were mined twice: the coinbases of blocks 91842 and
+//! 91880 are identical to those of blocks 91812 and 91722, while the
+//! earlier outputs were still unspent. Bitcoin Core keeps a single
+//! entry per outpoint, so the later coinbase replaced the earlier one
+//! in its UTXO set: the outputs created at 91812 and 91722 can never
+//! be spent, their 100 BTC are lost.
+//!
+//! BIP30 forbids any other duplicate of an unspent transaction.
+//! Connected iteration follows Core: the repeated coinbase overwrites
+//! the cached outputs, and each of these outpoints can be spent only once.
+
+use bitcoin::{OutPoint, Txid};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// `(height, height of the overwritten coinbase, txid)`.
+pub(crate) const BIP30_DUPLICATE_COINBASES: [(usize, usize, &str); 2] = [
+    (
+        91842,
+        91812,
+        "d5d27987d2a3dfc724e359870c6644b40e497bdc0589a033220fe15429d88599",
+    ),
+    (
+        91880,
+        91722,
+        "e3bf3d07d4b0375638d5f1db5255fe07ba2c4cb067cd81b84ee974b6585fb468",
+    ),
+];
+
+/// A coinbase transaction that repeated the txid of an unspent earlier coinbase.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DuplicateCoinbase {
+    /// Height of the repeated coinbase, whose outputs are in the UTXO set.
+    pub height: usize,
+    /// Height of the earlier coinbase, whose outputs became unspendable.
+    pub overwritten_height: usize,
+    pub txid: Txid,
+}
+
+impl DuplicateCoinbase {
+    /// The outpoint shared by both coinbases, each of them has a single output.
+    ///
+    /// It was created twice but can only be spent once.
+    pub fn outpoint(&self) -> OutPoint {
+        OutPoint::new(self.txid, 0)
+    }
+}
+
+/// The coinbase transactions that overwrote unspent outputs on mainnet, ordered by height.
+pub fn duplicate_coinbases() -> Vec<DuplicateCoinbase> {
+    BIP30_DUPLICATE_COINBASES
+        .iter()
+        .map(|(height, overwritten_height, txid)| DuplicateCoinbase {
+            height: *height,
+            overwritten_height: *overwritten_height,
+            txid: Txid::from_str(txid).unwrap(),
+        })
+        .collect()
+}
+
+/// Whether the coinbase `txid` at `height` is one of the duplicated coinbases.
+///
+/// Both heights are accepted, as blocks may be cached out of order.
+pub(crate) fn is_duplicate_coinbase(height: usize, txid: &Txid) -> bool {
+    BIP30_DUPLICATE_COINBASES.iter().any(|(h, overwritten, t)| {
+        (*h == height || *overwritten == height) && txid.to_string().as_str() == *t
+    })
+}
+
+/// Height of the coinbase overwritten by the block at `height`, if any.
+pub(crate) fn overwritten_coinbase(height: usize) -> Option<usize> {
+    BIP30_DUPLICATE_COINBASES
+        .iter()
+        .find(|(h, _, _)| *h == height)
+        .map(|(_, overwritten, _)| *overwritten)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicate_coinbases() {
+        let duplicates = duplicate_coinbases();
+        assert_eq!(duplicates.len(), 2);
+        for d in duplicates {
+            assert!(d.overwritten_height < d.height);
+            assert!(is_duplicate_coinbase(d.height, &d.txid));
+            assert!(is_duplicate_coinbase(d.overwritten_height, &d.txid));
+            assert!(!is_duplicate_coinbase(d.height + 1, &d.txid));
+            assert_eq!(overwritten_coinbase(d.height), Some(d.overwritten_height));
+            assert_eq!(d.outpoint().txid, d.txid);
+        }
+        assert_eq!(overwritten_coinbase(91812), None);
+    }
+}
@@ -2,12 +2,44 @@
 //! details of block_iter.rs, which follows similar principles.
 
 use crate::api::BitcoinDB;
+use crate::parser::block_types::projection::BlockProjection;
+use crate::parser::block_types::view::BlockView;
+use crate::parser::error::{Error, Result};
 use bitcoin::Block;
 use par_iter_sync::{IntoParallelIteratorSync, ParIterSync};
+use std::sync::Arc;
 
-pub struct BlockIter<B>(ParIterSync<B>);
+/// Iterate through blocks.
+///
+/// Iteration stops at the first error, which is only logged.
+/// Use [`TryBlockIter`] to receive the error.
+pub struct BlockIter<B>(TryBlockIter<B>);
+
+/// Iterate through blocks, yielding the error that stops the iteration.
+///
+/// After an error is returned, the iterator only yields `None`.
+pub struct TryBlockIter<B>(Option<ParIterSync<Result<B>>>);
 
 impl<B> BlockIter<B>
+where
+    B: From<Block> + Send + 'static,
+{
+    /// the worker threads are dispatched in this `new` constructor!
+    pub fn new<T>(db: &BitcoinDB, heights: T) -> Self
+    where
+        T: IntoIterator<Item = usize> + Send + 'static,
+        <T as IntoIterator>::IntoIter: Send + 'static,
+    {
+        Self(TryBlockIter::new(db, heights))
+    }
+
+    /// the worker threads are dispatched in this `new` constructor!
+    pub fn from_range(db: &BitcoinDB, start: usize, end: usize) -> Self {
+        Self(TryBlockIter::from_range(db, start, end))
+    }
+}
+
+impl<B> TryBlockIter<B>
 where
     B: From<Block> + Send + 'static,
 {
@@ -18,7 +50,10 @@ where
         <T as IntoIterator>::IntoIter: Send + 'static,
     {
         let db = db.clone();
-        Self(heights.into_par_iter_sync(move |h| db.get_block::<B>(h).map_err(|_| ())))
+        // errors are passed down as items, so that they reach the consumer.
+        Self(Some(heights.into_par_iter_sync(move |h| {
+            Ok(db.get_block::<B>(h).map_err(|e| Error::at_height(h, e)))
+        })))
     }
 
     /// the worker threads are dispatched in this `new` constructor!
@@ -31,10 +66,64 @@ where
     }
 }
 
+impl<P> TryBlockIter<P>
+where
+    P: BlockProjection + Send + 'static,
+{
+    /// Build a [`BlockProjection`] of each block in the worker threads.
+    pub fn projected(db: &BitcoinDB, start: usize, end: usize) -> Self {
+        let db = db.clone();
+        Self(Some((start..end.max(start)).into_par_iter_sync(move |h| {
+            Ok(db
+                .get_block_projection::<P>(h)
+                .map_err(|e| Error::at_height(h, e)))
+        })))
+    }
+}
+
+impl<T> TryBlockIter<T>
+where
+    T: Send + 'static,
+{
+    /// Apply `f` to the height and a [`BlockView`] of each block in the worker threads.
+    pub fn viewed<F>(db: &BitcoinDB, start: usize, end: usize, f: F) -> Self
+    where
+        F: Fn(usize, &BlockView) -> Result<T> + Send + Sync + 'static,
+    {
+        let db = db.clone();
+        let f = Arc::new(f);
+        Self(Some((start..end.max(start)).into_par_iter_sync(move |h| {
+            let item = db
+                .get_raw_block(h)
+                .and_then(|raw| f(h, &BlockView::new(&raw)?));
+            Ok(item.map_err(|e| Error::at_height(h, e)))
+        })))
+    }
+}
+
 impl<B> Iterator for BlockIter<B> {
     type Item = B;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next()
+        match self.0.next()? {
+            Ok(block) => Some(block),
+            Err(e) => {
+                log::error!("block iteration stopped: {e}");
+                None
+            }
+        }
+    }
+}
+
+impl<B> Iterator for TryBlockIter<B> {
+    type Item = Result<B>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.0.as_mut()?.next()?;
+        if item.is_err() {
+            // stop the worker threads.
+            self.0 = None;
+        }
+        Some(item)
     }
 }
@@ -0,0 +1,348 @@
+//! Per-block statistics equivalent to `getblockstats` of Bitcoin Core.
+//!
+//! Spent outputs are read from undo data, so every block is
+//! independent and statistics are computed in parallel.
+//!
+//! Translated from Bitcoin Core:
+//! [blockchain.cpp](https://github.com/bitcoin/bitcoin/blob/master/src/rpc/blockchain.cpp).
+
+use crate::api::BitcoinDB;
+use crate::iter::bip30::overwritten_coinbase;
+use crate::parser::error::{Error, Result};
+use crate::parser::script::is_unspendable;
+use crate::parser::undo::TxUndo;
+use bitcoin::{Block, BlockHash};
+use par_iter_sync::{IntoParallelIteratorSync, ParIterSync};
+use serde::{Deserialize, Serialize};
+
+/// `sizeof(COutPoint) + sizeof(uint32_t) + sizeof(bool)`, memory
+/// counted for each coin on top of its serialized output.
+const PER_UTXO_OVERHEAD: i64 = 36 + 4 + 1;
+const WITNESS_SCALE_FACTOR: u64 = 4;
+const SUBSIDY_HALVING_INTERVAL: usize = 210000;
+
+/// Statistics of a block, as returned by `getblockstats`.
+///
+/// Field names follow the RPC, so that the serialized form matches
+/// its JSON output. Amounts are in satoshi, fee rates in sat/vB.
+/// Except for `outs` and `utxo_*`, the coinbase transaction is excluded.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct BlockStats {
+    pub avgfee: u64,
+    pub avgfeerate: u64,
+    pub avgtxsize: u64,
+    pub blockhash: BlockHash,
+    /// Fee rates at the 10th, 25th, 50th, 75th and 90th percentile weight unit.
+    pub feerate_percentiles: [u64; 5],
+    pub height: usize,
+    pub ins: u64,
+    pub maxfee: u64,
+    pub maxfeerate: u64,
+    pub maxtxsize: u64,
+    pub medianfee: u64,
+    pub mediantime: u32,
+    pub mediantxsize: u64,
+    pub minfee: u64,
+    pub minfeerate: u64,
+    pub mintxsize: u64,
+    pub outs: u64,
+    pub subsidy: u64,
+    pub swtotal_size: u64,
+    pub swtotal_weight: u64,
+    pub swtxs: u64,
+    pub time: u32,
+    pub total_out: u64,
+    pub total_size: u64,
+    pub total_weight: u64,
+    pub totalfee: u64,
+    pub txs: u64,
+    pub utxo_increase: i64,
+    pub utxo_size_inc: i64,
+    pub utxo_increase_actual: i64,
+    pub utxo_size_inc_actual: i64,
+}
+
+/// Block reward excluding fees.
+pub(crate) fn block_subsidy(height: usize) -> u64 {
+    let halvings = height / SUBSIDY_HALVING_INTERVAL;
+    if halvings >= 64 {
+        return 0;
+    }
+    (50 * 100_000_000) >> halvings
+}
+
+/// Median of `scores`, averaging the two middle values if the length is even.
+fn truncated_median(mut scores: Vec<u64>) -> u64 {
+    let size = scores.len();
+    if size == 0 {
+        return 0;
+    }
+    scores.sort_unstable();
+    if size % 2 == 0 {
+        (scores[size / 2 - 1] + scores[size / 2]) / 2
+    } else {
+        scores[size / 2]
+    }
+}
+
+/// Fee rates at the 10th, 25th, 50th, 75th and 90th percentile weight unit.
+///
+/// `scores` are `(fee rate, weight)` of each transaction.
+fn percentiles_by_weight(mut scores: Vec<(u64, u64)>, total_weight: u64) -> [u64; 5] {
+    let mut result = [0; 5];
+    if scores.is_empty() {
+        return result;
+    }
+    scores.sort_unstable();
+    let total_weight = total_weight as f64;
+    let weights = [
+        total_weight / 10.0,
+        total_weight / 4.0,
+        total_weight / 2.0,
+        (total_weight * 3.0) / 4.0,
+        (total_weight * 9.0) / 10.0,
+    ];
+    let mut next_percentile_index = 0;
+    let mut cumulative_weight = 0;
+    for (fee_rate, weight) in scores.iter() {
+        cumulative_weight += weight;
+        while next_percentile_index < result.len()
+            && cumulative_weight as f64 >= weights[next_percentile_index]
+        {
+            result[next_percentile_index] = *fee_rate;
+            next_percentile_index += 1;
+        }
+    }
+    // fill any remaining percentiles with the last value.
+    let last = scores.last().unwrap().0;
+    for r in result.iter_mut().skip(next_percentile_index) {
+        *r = last;
+    }
+    result
+}
+
+/// Read a block with the undo data of each non-coinbase transaction.
+pub(crate) fn block_with_undo(db: &BitcoinDB, height: usize) -> Result<(Block, Vec<TxUndo>)> {
+    let block = db.get_block::<Block>(height)?;
+    // the genesis block has no undo data, and nothing to undo.
+    let undo = if block.txdata.len() > 1 {
+        db.get_block_undo(height)?.txdata
+    } else {
+        Vec::new()
+    };
+    if undo.len() + 1 != block.txdata.len() {
+        return Err(Error::InvalidUndoData(format!(
+            "block {height} has {} transactions, but {} undo records",
+            block.txdata.len(),
+            undo.len()
+        )));
+    }
+    Ok((block, undo))
+}
+
+/// Compute the statistics of the block at `height`.
+pub(crate) fn block_stats(db: &BitcoinDB, height: usize) -> Result<BlockStats> {
+    let (block, undo) = block_with_undo(db, height)?;
+    let is_bip30_repeat = overwritten_coinbase(height).is_some();
+
+    let mut inputs = 0;
+    let mut outputs = 0;
+    let mut utxos = 0;
+    let mut utxo_size_inc = 0;
+    let mut utxo_size_inc_actual = 0;
+    let mut total_out = 0;
+    let mut total_size = 0;
+    let mut total_weight = 0;
+    let mut totalfee = 0;
+    let mut swtxs = 0;
+    let mut swtotal_size = 0;
+    let mut swtotal_weight = 0;
+    let mut maxfee = 0;
+    let mut minfee = u64::MAX;
+    let mut maxfeerate = 0;
+    let mut minfeerate = u64::MAX;
+    let mut maxtxsize = 0;
+    let mut mintxsize = u64::MAX;
+    let mut fee_array = Vec::with_capacity(undo.len());
+    let mut feerate_array = Vec::with_capacity(undo.len());
+    let mut txsize_array = Vec::with_capacity(undo.len());
+
+    for (tx, tx_undo) in block
+        .txdata
+        .iter()
+        .zip(std::iter::once(None).chain(undo.iter().map(Some)))
+    {
+        outputs += tx.output.len() as u64;
+        let mut tx_total_out = 0;
+        for out in tx.output.iter() {
+            tx_total_out += out.value.to_sat();
+            let out_size = out.size() as i64 + PER_UTXO_OVERHEAD;
+            utxo_size_inc += out_size;
+            // the genesis block and the repeated BIP30 coinbases
+            // do not change the UTXO set counts.
+            if height == 0 || (is_bip30_repeat && tx.is_coinbase()) {
+                continue;
+            }
+            if is_unspendable(&out.script_pubkey) {
+                continue;
+            }
+            utxos += 1;
+            utxo_size_inc_actual += out_size;
+        }
+
+        // the coinbase transaction does not spend anything.
+        let tx_undo = match tx_undo {
+            Some(tx_undo) => tx_undo,
+            None => continue,
+        };
+        if tx_undo.prevouts.len() != tx.input.len() {
+            return Err(Error::InvalidUndoData(format!(
+                "transaction {} has {} inputs, but {} undo records",
+                tx.compute_txid(),
+                tx.input.len(),
+                tx_undo.prevouts.len()
+            )));
+        }
+
+        inputs += tx.input.len() as u64;
+        total_out += tx_total_out;
+
+        let tx_size = tx.total_size() as u64;
+        txsize_array.push(tx_size);
+        maxtxsize = maxtxsize.max(tx_size);
+        mintxsize = mintxsize.min(tx_size);
+        total_size += tx_size;
+
+        let weight = tx.weight().to_wu();
+        total_weight += weight;
+
+        if tx.input.iter().any(|i| !i.witness.is_empty()) {
+            swtxs += 1;
+            swtotal_size += tx_size;
+            swtotal_weight += weight;
+        }
+
+        let mut tx_total_in = 0;
+        for coin in tx_undo.prevouts.iter() {
+            tx_total_in += coin.out.value.to_sat();
+            let prevout_size = coin.out.size() as i64 + PER_UTXO_OVERHEAD;
+            utxo_size_inc -= prevout_size;
+            utxo_size_inc_actual -= prevout_size;
+        }
+
+        let txfee = tx_total_in.checked_sub(tx_total_out).ok_or_else(|| {
+            Error::InvalidUndoData(format!(
+                "transaction {} spends more than its inputs",
+                tx.compute_txid()
+            ))
+        })?;
+        fee_array.push(txfee);
+        maxfee = maxfee.max(txfee);
+        minfee = minfee.min(txfee);
+        totalfee += txfee;
+
+        // fee rate in satoshi per virtual byte.
+        let feerate = (txfee * WITNESS_SCALE_FACTOR)
+            .checked_div(weight)
+            .unwrap_or(0);
+        feerate_array.push((feerate, weight));
+        maxfeerate = maxfeerate.max(feerate);
+        minfeerate = minfeerate.min(feerate);
+    }
+
+    let n_tx = block.txdata.len() as u64;
+    Ok(BlockStats {
+        avgfee: if n_tx > 1 { totalfee / (n_tx - 1) } else { 0 },
+        avgfeerate: (totalfee * WITNESS_SCALE_FACTOR)
+            .checked_div(total_weight)
+            .unwrap_or(0),
+        avgtxsize: if n_tx > 1 { total_size / (n_tx - 1) } else { 0 },
+        blockhash: block.block_hash(),
+        feerate_percentiles: percentiles_by_weight(feerate_array, total_weight),
+        height,
+        ins: inputs,
+        maxfee,
+        maxfeerate,
+        maxtxsize,
+        medianfee: truncated_median(fee_array),
+        mediantime: db
+            .block_index
+            .median_time_past(height)
+            .ok_or(Error::BlockIndexRecordNotFound(height))?,
+        mediantxsize: truncated_median(txsize_array),
+        minfee: if minfee == u64::MAX { 0 } else { minfee },
+        minfeerate: if minfeerate == u64::MAX {
+            0
+        } else {
+            minfeerate
+        },
+        mintxsize: if mintxsize == u64::MAX { 0 } else { mintxsize },
+        outs: outputs,
+        subsidy: block_subsidy(height),
+        swtotal_size,
+        swtotal_weight,
+        swtxs,
+        time: block.header.time,
+        total_out,
+        total_size,
+        total_weight,
+        totalfee,
+        txs: n_tx,
+        utxo_increase: outputs as i64 - inputs as i64,
+        utxo_size_inc,
+        utxo_increase_actual: utxos - inputs as i64,
+        utxo_size_inc_actual,
+    })
+}
+
+/// Iterate through blocks, yielding the statistics of each block.
+pub struct BlockStatsIter(ParIterSync<BlockStats>);
+
+impl BlockStatsIter {
+    /// the worker threads are dispatched in this `new` constructor!
+    pub fn new(db: &BitcoinDB, start: usize, end: usize) -> Self {
+        let db = db.clone();
+        Self((start..end.max(start)).into_par_iter_sync(move |height| {
+            block_stats(&db, height).map_err(|e| {
+                log::error!("failed to compute statistics of block {height}: {e}");
+            })
+        }))
+    }
+}
+
+impl Iterator for BlockStatsIter {
+    type Item = BlockStats;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_subsidy() {
+        assert_eq!(block_subsidy(0), 5_000_000_000);
+        assert_eq!(block_subsidy(209999), 5_000_000_000);
+        assert_eq!(block_subsidy(210000), 2_500_000_000);
+        assert_eq!(block_subsidy(840000), 312_500_000);
+        assert_eq!(block_subsidy(64 * 210000), 0);
+    }
+
+    #[test]
+    fn test_truncated_median() {
+        assert_eq!(truncated_median(vec![]), 0);
+        assert_eq!(truncated_median(vec![5, 1, 3]), 3);
+        assert_eq!(truncated_median(vec![4, 1, 3, 2]), 2);
+    }
+
+    #[test]
+    fn test_percentiles_by_weight() {
+        assert_eq!(percentiles_by_weight(vec![], 0), [0; 5]);
+        // the 10th and 25th percentile fall in the first transaction.
+        let scores = vec![(30, 400), (10, 300), (20, 300)];
+        assert_eq!(percentiles_by_weight(scores, 1000), [10, 10, 20, 30, 30]);
+    }
+}
@@ -0,0 +1,387 @@
+//! Block-level consensus checks of historical blocks.
+//!
+//! Blocks are read again from blk files and checked against the rules of
+//! `CheckBlock`, `ContextualCheckBlock` and the coinbase amount rule of
+//! `ConnectBlock`: merkle root, witness commitment, BIP34 coinbase height,
+//! coinbase value, weight limits and duplicate transactions.
+//! This detects corrupted blk files that still deserialize.
+//!
+//! Spent outputs are read from undo data, so every block is independent
+//! and checked in parallel.
+//!
+//! Translated from Bitcoin Core:
+//! [validation.cpp](https://github.com/bitcoin/bitcoin/blob/master/src/validation.cpp).
+
+use crate::api::BitcoinDB;
+use crate::iter::block_stats_iter::block_subsidy;
+use crate::parser::error::{Error, Result};
+use crate::parser::undo::TxUndo;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::{Block, BlockHash, ScriptBuf, Txid, VarInt};
+use par_iter_sync::{IntoParallelIteratorSync, ParIterSync};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+const MAX_BLOCK_WEIGHT: u64 = 4_000_000;
+const WITNESS_SCALE_FACTOR: u64 = 4;
+const BIP34_HEIGHT: usize = 227931;
+const SEGWIT_HEIGHT: usize = 481824;
+/// `OP_RETURN OP_PUSHBYTES_36 0xaa21a9ed`
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+const MINIMUM_WITNESS_COMMITMENT: usize = 38;
+
+/// A consensus rule broken by a block.
+///
+/// Displayed as the reject reasons of Bitcoin Core where one exists.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum BlockError {
+    /// The block or its undo data could not be read.
+    Unreadable(String),
+    /// The header in the blk file does not match the block index.
+    BlockHashMismatch {
+        expected: BlockHash,
+        found: BlockHash,
+    },
+    /// No transactions, or the block is too large without witness data.
+    BadLength,
+    /// The first transaction is not a coinbase.
+    MissingCoinbase,
+    /// A transaction other than the first is a coinbase.
+    MultipleCoinbase { index: usize },
+    /// The merkle root does not commit to the transactions.
+    BadMerkleRoot,
+    /// The merkle tree is mutated by duplicated transactions (CVE-2012-2459).
+    MutatedMerkleTree,
+    /// The same txid appears more than once.
+    DuplicateTxid { txid: Txid },
+    /// The coinbase does not start with the block height (BIP34).
+    BadCoinbaseHeight,
+    /// The coinbase witness is not a single 32-byte reserved value.
+    BadWitnessNonceSize,
+    /// The witness commitment does not match the witness merkle root.
+    BadWitnessCommitment,
+    /// Witness data in a block without witness commitment.
+    UnexpectedWitness,
+    /// The block weight is above 4,000,000.
+    BadWeight { weight: u64 },
+    /// The coinbase claims more than the subsidy plus fees.
+    BadCoinbaseAmount { coinbase_value: u64, limit: u64 },
+}
+
+impl std::fmt::Display for BlockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Unreadable(e) => write!(f, "unreadable: {}", e),
+            Self::BlockHashMismatch { expected, found } => {
+                write!(f, "block hash {} does not match index {}", found, expected)
+            }
+            Self::BadLength => write!(f, "bad-blk-length"),
+            Self::MissingCoinbase => write!(f, "bad-cb-missing"),
+            Self::MultipleCoinbase { .. } => write!(f, "bad-cb-multiple"),
+            Self::BadMerkleRoot => write!(f, "bad-txnmrklroot"),
+            Self::MutatedMerkleTree | Self::DuplicateTxid { .. } => {
+                write!(f, "bad-txns-duplicate")
+            }
+            Self::BadCoinbaseHeight => write!(f, "bad-cb-height"),
+            Self::BadWitnessNonceSize => write!(f, "bad-witness-nonce-size"),
+            Self::BadWitnessCommitment => write!(f, "bad-witness-merkle-match"),
+            Self::UnexpectedWitness => write!(f, "unexpected-witness"),
+            Self::BadWeight { .. } => write!(f, "bad-blk-weight"),
+            Self::BadCoinbaseAmount { .. } => write!(f, "bad-cb-amount"),
+        }
+    }
+}
+
+/// Result of checking a block.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct BlockVerification {
+    pub height: usize,
+    /// Hash recorded in the block index.
+    pub block_hash: BlockHash,
+    pub errors: Vec<BlockError>,
+}
+
+impl BlockVerification {
+    /// Whether the block passed all checks.
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Check a block at `height` against the consensus rules that do not
+/// need the UTXO set.
+///
+/// The coinbase amount is only checked if `fees`, the total fee paid
+/// by the transactions of the block, is given.
+pub fn check_block(block: &Block, height: usize, fees: Option<u64>) -> Vec<BlockError> {
+    let mut errors = Vec::new();
+    let txdata = &block.txdata;
+
+    let (merkle_root, mutated) = merkle_root(txdata.iter().map(|tx| tx.compute_txid()));
+    if merkle_root != Some(block.header.merkle_root.to_byte_array()) {
+        errors.push(BlockError::BadMerkleRoot);
+    }
+    if mutated {
+        errors.push(BlockError::MutatedMerkleTree);
+    }
+    if txdata.is_empty()
+        || txdata.len() as u64 * WITNESS_SCALE_FACTOR > MAX_BLOCK_WEIGHT
+        || stripped_size(block) as u64 * WITNESS_SCALE_FACTOR > MAX_BLOCK_WEIGHT
+    {
+        errors.push(BlockError::BadLength);
+    }
+    let coinbase = match txdata.first() {
+        Some(tx) if tx.is_coinbase() => Some(tx),
+        _ => {
+            errors.push(BlockError::MissingCoinbase);
+            None
+        }
+    };
+    if let Some(index) = txdata.iter().skip(1).position(|tx| tx.is_coinbase()) {
+        errors.push(BlockError::MultipleCoinbase { index: index + 1 });
+    }
+    let mut txids = HashSet::with_capacity(txdata.len());
+    for tx in txdata {
+        let txid = tx.compute_txid();
+        if !txids.insert(txid) {
+            errors.push(BlockError::DuplicateTxid { txid });
+        }
+    }
+
+    if let Some(coinbase) = coinbase {
+        if height >= BIP34_HEIGHT {
+            let expected = ScriptBuf::builder().push_int(height as i64).into_script();
+            if !coinbase.input[0]
+                .script_sig
+                .as_bytes()
+                .starts_with(expected.as_bytes())
+            {
+                errors.push(BlockError::BadCoinbaseHeight);
+            }
+        }
+        match witness_commitment_index(block) {
+            Some(index) if height >= SEGWIT_HEIGHT => {
+                let witness = &coinbase.input[0].witness;
+                let nonce = witness
+                    .nth(0)
+                    .filter(|n| witness.len() == 1 && n.len() == 32);
+                match (nonce, block.witness_root()) {
+                    (Some(nonce), Some(witness_root)) => {
+                        let commitment = Block::compute_witness_commitment(&witness_root, nonce);
+                        let script = coinbase.output[index].script_pubkey.as_bytes();
+                        if script[6..38] != commitment.to_byte_array() {
+                            errors.push(BlockError::BadWitnessCommitment);
+                        }
+                    }
+                    (None, _) => errors.push(BlockError::BadWitnessNonceSize),
+                    (_, None) => errors.push(BlockError::BadWitnessCommitment),
+                }
+            }
+            _ => {
+                let has_witness = txdata
+                    .iter()
+                    .any(|tx| tx.input.iter().any(|i| !i.witness.is_empty()));
+                if has_witness {
+                    errors.push(BlockError::UnexpectedWitness);
+                }
+            }
+        }
+        if let Some(fees) = fees {
+            let coinbase_value = coinbase.output.iter().map(|o| o.value.to_sat()).sum();
+            let limit = block_subsidy(height) + fees;
+            if coinbase_value > limit {
+                errors.push(BlockError::BadCoinbaseAmount {
+                    coinbase_value,
+                    limit,
+                });
+            }
+        }
+    }
+
+    let weight = block.weight().to_wu();
+    if weight > MAX_BLOCK_WEIGHT {
+        errors.push(BlockError::BadWeight { weight });
+    }
+    errors
+}
+
+/// Merkle root of `txids`, and whether the tree is mutated, i.e.
+/// two identical hashes are paired at some level (`ComputeMerkleRoot`).
+fn merkle_root(txids: impl Iterator<Item = Txid>) -> (Option<[u8; 32]>, bool) {
+    let mut hashes: Vec<[u8; 32]> = txids.map(|t| t.to_byte_array()).collect();
+    let mut mutated = false;
+    while hashes.len() > 1 {
+        mutated |= hashes.chunks_exact(2).any(|pair| pair[0] == pair[1]);
+        if hashes.len() % 2 == 1 {
+            hashes.push(hashes[hashes.len() - 1]);
+        }
+        hashes = hashes
+            .chunks_exact(2)
+            .map(|pair| {
+                let mut engine = sha256d::Hash::engine();
+                bitcoin::hashes::HashEngine::input(&mut engine, &pair[0]);
+                bitcoin::hashes::HashEngine::input(&mut engine, &pair[1]);
+                sha256d::Hash::from_engine(engine).to_byte_array()
+            })
+            .collect();
+    }
+    (hashes.first().copied(), mutated)
+}
+
+/// Size of the block serialized without witness data.
+fn stripped_size(block: &Block) -> usize {
+    let txdata = &block.txdata;
+    let header_size = 80 + VarInt::from(txdata.len()).size();
+    header_size + txdata.iter().map(|tx| tx.base_size()).sum::<usize>()
+}
+
+/// Index of the coinbase output holding the witness commitment,
+/// the last one that matches (`GetWitnessCommitmentIndex`).
+fn witness_commitment_index(block: &Block) -> Option<usize> {
+    let coinbase = block.txdata.first()?;
+    coinbase.output.iter().rposition(|o| {
+        let script = o.script_pubkey.as_bytes();
+        script.len() >= MINIMUM_WITNESS_COMMITMENT && script.starts_with(&WITNESS_COMMITMENT_HEADER)
+    })
+}
+
+pub(crate) fn verify_block(db: &BitcoinDB, height: usize) -> Result<BlockVerification> {
+    let record = db
+        .block_index
+        .records
+        .get(height)
+        .ok_or(Error::BlockIndexRecordNotFound(height))?;
+    let block_hash = record.block_header.block_hash();
+    let mut errors = Vec::new();
+    match db.blk_file.read_block(record.n_file, record.n_data_pos) {
+        Ok(block) => {
+            let found = block.block_hash();
+            if found != block_hash {
+                errors.push(BlockError::BlockHashMismatch {
+                    expected: block_hash,
+                    found,
+                });
+            }
+            // the genesis block has no undo data, and pays no fee.
+            let fees = if block.txdata.len() > 1 {
+                match db.get_block_undo(height) {
+                    Ok(undo) => total_fees(&block, &undo.txdata),
+                    Err(e) => {
+                        errors.push(BlockError::Unreadable(e.to_string()));
+                        None
+                    }
+                }
+            } else {
+                Some(0)
+            };
+            errors.extend(check_block(&block, height, fees));
+        }
+        Err(e) => errors.push(BlockError::Unreadable(e.to_string())),
+    }
+    Ok(BlockVerification {
+        height,
+        block_hash,
+        errors,
+    })
+}
+
+/// Total fee of the block, `None` if the undo data does not match.
+fn total_fees(block: &Block, undo: &[TxUndo]) -> Option<u64> {
+    if undo.len() + 1 != block.txdata.len() {
+        return None;
+    }
+    let mut fees = 0u64;
+    for (tx, tx_undo) in block.txdata.iter().skip(1).zip(undo) {
+        let input_value: u64 = tx_undo.prevouts.iter().map(|c| c.out.value.to_sat()).sum();
+        let output_value: u64 = tx.output.iter().map(|o| o.value.to_sat()).sum();
+        fees += input_value.checked_sub(output_value)?;
+    }
+    Some(fees)
+}
+
+/// Iterate through blocks, yielding the consensus check result of each block.
+pub struct BlockVerifyIter(ParIterSync<BlockVerification>);
+
+impl BlockVerifyIter {
+    /// the worker threads are dispatched in this `new` constructor!
+    pub fn new(db: &BitcoinDB, start: usize, end: usize) -> Self {
+        let db = db.clone();
+        Self((start..end.max(start)).into_par_iter_sync(move |height| {
+            verify_block(&db, height).map_err(|e| {
+                log::error!("failed to verify block {height}: {e}");
+            })
+        }))
+    }
+}
+
+impl Iterator for BlockVerifyIter {
+    type Item = BlockVerification;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::Network;
+
+    #[test]
+    fn test_genesis_block() {
+        let block = genesis_block(Network::Bitcoin);
+        assert_eq!(stripped_size(&block), 285);
+        assert_eq!(check_block(&block, 0, Some(0)), vec![]);
+        assert_eq!(
+            check_block(&block, 0, Some(0)),
+            check_block(&block, 0, None)
+        );
+    }
+
+    #[test]
+    fn test_corrupted_block() {
+        let mut block = genesis_block(Network::Bitcoin);
+        block.txdata[0].output[0].value = bitcoin::Amount::from_sat(5_000_000_001);
+        assert_eq!(
+            check_block(&block, 0, Some(0)),
+            vec![
+                BlockError::BadMerkleRoot,
+                BlockError::BadCoinbaseAmount {
+                    coinbase_value: 5_000_000_001,
+                    limit: 5_000_000_000
+                }
+            ]
+        );
+        // no BIP34 height in the genesis coinbase
+        let errors = check_block(&genesis_block(Network::Bitcoin), BIP34_HEIGHT, None);
+        assert_eq!(errors, vec![BlockError::BadCoinbaseHeight]);
+    }
+
+    #[test]
+    fn test_mutated_merkle_tree() {
+        let mut block = genesis_block(Network::Bitcoin);
+        let mut tx = block.txdata[0].clone();
+        tx.input[0].previous_output.vout = 0;
+        block.txdata.push(tx.clone());
+        tx.input[0].previous_output.vout = 1;
+        block.txdata.push(tx.clone());
+        block.txdata.push(tx.clone());
+        let (root, mutated) = merkle_root(block.txdata.iter().map(|tx| tx.compute_txid()));
+        assert!(mutated);
+        block.header.merkle_root = bitcoin::TxMerkleNode::from_byte_array(root.unwrap());
+        let errors = check_block(&block, 0, None);
+        assert_eq!(
+            errors,
+            vec![
+                BlockError::MutatedMerkleTree,
+                BlockError::DuplicateTxid {
+                    txid: tx.compute_txid()
+                }
+            ]
+        );
+        // an odd number of hashes duplicates the last one without mutation
+        let (_, mutated) = merkle_root(block.txdata.iter().take(3).map(|tx| tx.compute_txid()));
+        assert!(!mutated);
+    }
+}
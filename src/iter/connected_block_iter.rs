@@ -1,63 +1,143 @@
 use crate::api::BitcoinDB;
-use crate::iter::fetch_connected_async::{connect_outpoints, update_unspent_cache};
-#[cfg(not(feature = "on-disk-utxo"))]
-use crate::iter::util::VecMap;
-use crate::parser::block_types::connected_block::ConnectedBlock;
-#[cfg(not(feature = "on-disk-utxo"))]
-use crate::parser::block_types::connected_block::ConnectedTx;
+use crate::iter::fetch_connected_async::InMemoryUtxo;
+#[cfg(feature = "on-disk-utxo")]
+use crate::iter::fetch_connected_async::{open_db_read_only, HybridUtxo, OnDiskUtxo};
+use crate::iter::utxo_backend::{
+    connect_block, ConnectedIterOptions, UtxoBackend, UtxoBackendKind,
+};
+#[cfg(feature = "on-disk-utxo")]
+use crate::iter::utxo_checkpoint::{CacheDir, CheckpointInfo};
+use crate::parser::block_types::connected_block::{ConnectedBlock, ConnectedTx};
+use crate::parser::block_types::projection::{BlockContext, BlockProjection};
+use crate::parser::error::{Error, Result};
+use crate::parser::undo::Coin;
+use bitcoin::{Block, TxOut};
 use par_iter_sync::{IntoParallelIteratorSync, ParIterSync};
+use std::ops::Range;
+#[cfg(feature = "on-disk-utxo")]
+use std::path::Path;
 use std::sync::Arc;
-#[cfg(not(feature = "on-disk-utxo"))]
-use std::sync::Mutex;
 
-/// 32 (txid) + 4 (i32 out n)
-#[cfg(feature = "on-disk-utxo")]
-pub(crate) const KEY_LENGTH: u32 = 32 + 4;
+/// Outputs stored in the UTXO cache when connecting blocks of type `B`.
+type TxOutOf<B> = <<B as ConnectedBlock>::Tx as ConnectedTx>::TxOut;
 
-#[cfg(feature = "on-disk-utxo")]
-fn create_db(path: impl AsRef<std::path::Path>) -> Option<rocksdb::DB> {
-    let mut options = rocksdb::Options::default();
-    // create table
-    options.create_if_missing(true);
-    // config to more jobs
-    options.set_max_background_jobs(num_cpus::get() as i32);
-    // configure mem-table to a large value (256 MB)
-    options.set_write_buffer_size(0x10000000);
-    // configure l0 and l1 size, let them have the same size (1 GB)
-    options.set_level_zero_file_num_compaction_trigger(4);
-    options.set_max_bytes_for_level_base(0x40000000);
-    // 256MB file size
-    options.set_target_file_size_base(0x10000000);
-    // use a smaller compaction multiplier
-    options.set_max_bytes_for_level_multiplier(4.0);
-    // use 8-byte prefix (2 ^ 64 is far enough for transaction counts)
-    options.set_prefix_extractor(rocksdb::SliceTransform::create_fixed_prefix(8));
-    match rocksdb::DB::open(&options, path) {
-        Ok(db) => Some(db),
-        Err(e) => {
-            log::error!("failed to create temp rocksDB for UTXO: {}", e);
-            None
-        }
-    }
+/// Build the item yielded for a block at some height, from the outputs it spends.
+type Connect<T, R> = fn(&BitcoinDB, usize, Block, Vec<Coin<T>>) -> Result<R>;
+
+fn connect<B: ConnectedBlock>(
+    db: &BitcoinDB,
+    _height: usize,
+    block: Block,
+    prevouts: Vec<Coin<TxOutOf<B>>>,
+) -> Result<B> {
+    connect_block(block, prevouts, &db.block_index)
+}
+
+fn project<P: BlockProjection>(
+    _db: &BitcoinDB,
+    height: usize,
+    block: Block,
+    prevouts: Vec<Coin>,
+) -> Result<P> {
+    let context = BlockContext {
+        height,
+        block_hash: block.block_hash(),
+        prevouts: Some(&prevouts),
+    };
+    Ok(P::project(&block, &context))
 }
 
 /// iterate through blocks, and connecting outpoints.
-pub struct ConnectedBlockIter<B> {
-    inner: ParIterSync<B>,
+///
+/// Iteration stops at the first error, which is only logged.
+/// Use [`TryConnectedBlockIter`] to receive the error.
+pub struct ConnectedBlockIter<B>(TryConnectedBlockIter<B>);
+
+/// iterate through blocks, and connecting outpoints,
+/// yielding the error that stops the iteration.
+///
+/// After an error is returned, the iterator only yields `None`.
+pub struct TryConnectedBlockIter<B> {
+    inner: Option<ParIterSync<Result<B>>>,
+    /// error raised before iteration starts.
+    error: Option<Error>,
+    /// height of the first block yielded.
+    start: usize,
     #[cfg(feature = "on-disk-utxo")]
-    #[allow(dead_code)]
-    cache: Option<tempdir::TempDir>,
+    checkpoints: Option<Checkpoints<B>>,
+}
+
+/// Connect blocks of `heights` in two stages: add outputs to the cache,
+/// then spend inputs from the cache.
+///
+/// Errors are passed down as items, so that they reach the consumer.
+fn pipeline<R, T, U>(
+    db: &BitcoinDB,
+    unspent: &Arc<U>,
+    heights: Range<usize>,
+    connect: Connect<T, R>,
+) -> ParIterSync<Result<R>>
+where
+    R: Send + 'static,
+    T: 'static,
+    U: UtxoBackend<T> + ?Sized + 'static,
+{
+    let output_iterator = {
+        let db = db.clone();
+        let unspent = unspent.clone();
+
+        heights.into_par_iter_sync(move |height| {
+            let block = db
+                .get_block::<Block>(height)
+                .and_then(|blk| unspent.insert_block(&blk, height).map(|_| (height, blk)));
+            Ok(block.map_err(|e| Error::at_height(height, e)))
+        })
+    };
+    let db = db.clone();
+    let unspent = unspent.clone();
+    output_iterator.into_par_iter_sync(move |blk| {
+        Ok(blk.and_then(|(height, blk)| {
+            unspent
+                .spend_block(&blk)
+                .and_then(|prevouts| connect(&db, height, blk, prevouts))
+                .map_err(|e| Error::at_height(height, e))
+        }))
+    })
+}
+
+/// Iteration over a persistent UTXO cache, split into segments
+/// with a checkpoint after each segment.
+#[cfg(feature = "on-disk-utxo")]
+struct Checkpoints<R> {
+    cache_dir: CacheDir,
+    unspent: Arc<OnDiskUtxo>,
+    /// pipeline connecting a segment.
+    segment: Box<dyn Fn(Range<usize>) -> ParIterSync<Result<R>> + Send>,
+    interval: usize,
+    /// end of the current segment, i.e. the height of the next checkpoint.
+    next: usize,
+    end: usize,
 }
 
-#[cfg(not(feature = "on-disk-utxo"))]
-type InMemoryUtxoCache<B> = Arc<
-    Mutex<
-        hash_hasher::HashedMap<
-            bitcoin::Txid,
-            Arc<Mutex<VecMap<<<B as ConnectedBlock>::Tx as ConnectedTx>::TxOut>>>,
-        >,
-    >,
->;
+#[cfg(feature = "on-disk-utxo")]
+impl<R> Checkpoints<R> {
+    /// Checkpoint the finished segment and start the next one, if any.
+    fn next_segment(&mut self) -> Result<Option<ParIterSync<Result<R>>>> {
+        self.cache_dir.save(
+            self.unspent.db(),
+            CheckpointInfo {
+                height: self.next,
+                interval: self.interval,
+            },
+        )?;
+        if self.next >= self.end {
+            return Ok(None);
+        }
+        let start = self.next;
+        self.next = (start + self.interval).min(self.end);
+        Ok(Some((self.segment)(start..self.next)))
+    }
+}
 
 impl<B> ConnectedBlockIter<B>
 where
@@ -65,64 +145,188 @@ where
 {
     /// the worker threads are dispatched in this `new` constructor!
     pub fn new(db: &BitcoinDB, end: usize) -> Self {
-        #[cfg(feature = "on-disk-utxo")]
-        let (cache_dir, unspent) = {
-            let cache_dir = {
-                match tempdir::TempDir::new("rocks_db") {
-                    Ok(tempdir) => tempdir,
-                    Err(e) => {
-                        log::error!("failed to create rocksDB tempdir for UTXO: {}", e);
-                        return Self::null();
-                    }
-                }
-            };
-            if let Some(db) = create_db(&cache_dir) {
-                (cache_dir, Arc::new(db))
-            } else {
-                return Self::null();
-            }
-        };
+        Self(TryConnectedBlockIter::new(db, end))
+    }
+
+    /// Same as [`Self::new`], with the UTXO backend selected in `options`.
+    pub fn with_options(db: &BitcoinDB, end: usize, options: &ConnectedIterOptions) -> Self {
+        Self(TryConnectedBlockIter::with_options(db, end, options))
+    }
+}
+
+impl<B> TryConnectedBlockIter<B>
+where
+    B: ConnectedBlock + Send + 'static,
+{
+    /// the worker threads are dispatched in this `new` constructor!
+    pub fn new(db: &BitcoinDB, end: usize) -> Self {
+        Self::with_options(db, end, &ConnectedIterOptions::default())
+    }
 
-        #[cfg(not(feature = "on-disk-utxo"))]
-        let unspent: InMemoryUtxoCache<B> = Arc::new(Mutex::new(hash_hasher::HashedMap::default()));
+    /// Use the UTXO backend selected in `options`.
+    pub fn with_options(db: &BitcoinDB, end: usize, options: &ConnectedIterOptions) -> Self {
+        Self::build(db, end, options, connect::<B>)
+    }
 
-        // all tasks
-        let heights = 0..end;
+    /// Use a custom UTXO backend, which must be empty.
+    ///
+    /// The backend is dropped with the iterator.
+    pub fn with_backend<U>(db: &BitcoinDB, end: usize, backend: Arc<U>) -> Self
+    where
+        U: UtxoBackend<TxOutOf<B>> + ?Sized + 'static,
+    {
+        Self::build_with_backend(db, end, backend, connect::<B>)
+    }
 
-        #[cfg(feature = "on-disk-utxo")]
-        let output_iterator = {
-            let db = db.clone();
-            let unspent = unspent.clone();
+    /// Keep the UTXO cache in `dir`, and checkpoint it every `interval` blocks.
+    ///
+    /// Any previous cache in `dir` is removed.
+    #[cfg(feature = "on-disk-utxo")]
+    pub fn with_checkpoints(db: &BitcoinDB, dir: &Path, end: usize, interval: usize) -> Self {
+        let cache_dir = CacheDir::new(dir);
+        if let Err(e) = cache_dir.clear() {
+            return Self::failed(e);
+        }
+        Self::persistent(db, cache_dir, 0, end, interval)
+    }
 
-            heights.into_par_iter_sync(move |height| update_unspent_cache(&unspent, &db, height))
-        };
+    /// Resume from the last checkpoint in `dir`, see [`Self::with_checkpoints`].
+    #[cfg(feature = "on-disk-utxo")]
+    pub fn from_checkpoint(db: &BitcoinDB, dir: &Path, end: usize) -> Self {
+        let cache_dir = CacheDir::new(dir);
+        match cache_dir.restore(|path| open_db_read_only(path)) {
+            Ok(info) => Self::persistent(db, cache_dir, info.height, end, info.interval),
+            Err(e) => Self::failed(e),
+        }
+    }
 
-        #[cfg(not(feature = "on-disk-utxo"))]
-        let output_iterator = {
+    #[cfg(feature = "on-disk-utxo")]
+    fn persistent(
+        db: &BitcoinDB,
+        cache_dir: CacheDir,
+        start: usize,
+        end: usize,
+        interval: usize,
+    ) -> Self {
+        let unspent = match OnDiskUtxo::open(&cache_dir.working_path()) {
+            Ok(unspent) => Arc::new(unspent),
+            Err(e) => return Self::failed(e),
+        };
+        let segment = {
             let db = db.clone();
             let unspent = unspent.clone();
-
-            heights
-                .into_par_iter_sync(move |height| update_unspent_cache::<B>(&unspent, &db, height))
+            Box::new(move |heights: Range<usize>| pipeline(&db, &unspent, heights, connect::<B>))
+        };
+        let mut checkpoints = Checkpoints {
+            cache_dir,
+            unspent,
+            segment,
+            interval: interval.max(1),
+            next: start,
+            end: end.max(start),
         };
+        match checkpoints.next_segment() {
+            Ok(inner) => Self {
+                inner,
+                error: None,
+                start,
+                checkpoints: Some(checkpoints),
+            },
+            Err(e) => Self::failed(e),
+        }
+    }
+}
+
+impl<P> TryConnectedBlockIter<P>
+where
+    P: BlockProjection + Send + 'static,
+{
+    /// Build a [`BlockProjection`] of each block in the worker threads,
+    /// with the prevouts of the block, using the UTXO backend selected in `options`.
+    pub fn projected(db: &BitcoinDB, end: usize, options: &ConnectedIterOptions) -> Self {
+        Self::build(db, end, options, project::<P>)
+    }
+}
 
-        let output_iterator =
-            output_iterator.into_par_iter_sync(move |blk| connect_outpoints(&unspent, blk));
+impl<R> TryConnectedBlockIter<R>
+where
+    R: Send + 'static,
+{
+    fn build<T>(
+        db: &BitcoinDB,
+        end: usize,
+        options: &ConnectedIterOptions,
+        connect: Connect<T, R>,
+    ) -> Self
+    where
+        T: From<TxOut> + Send + 'static,
+    {
+        let backend: Result<Arc<dyn UtxoBackend<T>>> = match options.backend {
+            UtxoBackendKind::InMemory => Ok(Arc::new(InMemoryUtxo::new())),
+            #[cfg(feature = "on-disk-utxo")]
+            UtxoBackendKind::OnDisk => OnDiskUtxo::temporary().map(|u| Arc::new(u) as Arc<_>),
+            #[cfg(feature = "on-disk-utxo")]
+            UtxoBackendKind::Hybrid { memory_budget } => {
+                HybridUtxo::temporary(memory_budget).map(|u| Arc::new(u) as Arc<_>)
+            }
+        };
+        match backend {
+            Ok(backend) => Self::build_with_backend(db, end, backend, connect),
+            Err(e) => Self::failed(e),
+        }
+    }
 
+    fn build_with_backend<T, U>(
+        db: &BitcoinDB,
+        end: usize,
+        backend: Arc<U>,
+        connect: Connect<T, R>,
+    ) -> Self
+    where
+        T: 'static,
+        U: UtxoBackend<T> + ?Sized + 'static,
+    {
         Self {
-            inner: output_iterator,
-            // `cache_dir` will be deleted when ConnectedBlockIter is dropped.
+            inner: Some(pipeline(db, &backend, 0..end, connect)),
+            error: None,
+            start: 0,
             #[cfg(feature = "on-disk-utxo")]
-            cache: Some(cache_dir),
+            checkpoints: None,
         }
     }
+}
 
-    #[cfg(feature = "on-disk-utxo")]
-    fn null() -> Self {
+impl<B> TryConnectedBlockIter<B> {
+    /// Stop the worker threads.
+    fn stop(&mut self) {
+        self.inner = None;
+        #[cfg(feature = "on-disk-utxo")]
+        {
+            self.checkpoints = None;
+        }
+    }
+
+    /// Pipeline of the next segment, when checkpoints are enabled.
+    fn next_segment(&mut self) -> Result<Option<ParIterSync<Result<B>>>> {
+        #[cfg(feature = "on-disk-utxo")]
+        if let Some(checkpoints) = self.checkpoints.as_mut() {
+            return checkpoints.next_segment();
+        }
+        Ok(None)
+    }
+
+    /// Height of the first block yielded, non-zero when resuming from a checkpoint.
+    pub fn start_height(&self) -> usize {
+        self.start
+    }
+
+    fn failed(error: Error) -> Self {
         Self {
-            inner: Vec::new().into_par_iter_sync(|_: usize| Err(())),
+            inner: None,
+            error: Some(error),
+            start: 0,
             #[cfg(feature = "on-disk-utxo")]
-            cache: None,
+            checkpoints: None,
         }
     }
 }
@@ -131,21 +335,58 @@ impl<B> Iterator for ConnectedBlockIter<B> {
     type Item = B;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next()
+        match self.0.next()? {
+            Ok(block) => Some(block),
+            Err(e) => {
+                log::error!("connected block iteration stopped: {e}");
+                None
+            }
+        }
+    }
+}
+
+impl<B> Iterator for TryConnectedBlockIter<B> {
+    type Item = Result<B>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+        loop {
+            if let Some(item) = self.inner.as_mut()?.next() {
+                if item.is_err() {
+                    self.stop();
+                }
+                return Some(item);
+            }
+            // all blocks of the segment are connected.
+            match self.next_segment() {
+                Ok(inner) => self.inner = inner,
+                Err(e) => {
+                    self.stop();
+                    return Some(Err(e));
+                }
+            }
+        }
     }
 }
 
 #[cfg(test)]
-#[cfg(feature = "on-disk-utxo")]
 mod test_empty {
-    use crate::{CompactConnectedBlock, ConnectedBlockIter};
+    use super::{ConnectedBlockIter, TryConnectedBlockIter};
+    use crate::parser::error::Error;
+    use crate::CompactConnectedBlock;
 
     #[test]
     fn test_empty() {
-        let mut empty = ConnectedBlockIter::null();
+        let mut empty = ConnectedBlockIter(TryConnectedBlockIter::failed(Error::TxDbUnavailable));
         for _ in 0..100 {
             let b: Option<CompactConnectedBlock> = empty.next();
             assert!(b.is_none());
         }
+        let mut failed =
+            TryConnectedBlockIter::<CompactConnectedBlock>::failed(Error::TxDbUnavailable);
+        assert!(matches!(failed.next(), Some(Err(Error::TxDbUnavailable))));
+        assert!(failed.next().is_none());
     }
 }
@@ -1,20 +1,59 @@
-#[cfg(not(feature = "on-disk-utxo"))]
-pub use in_mem_utxo::{connect_outpoints, update_unspent_cache};
+//! Built-in implementations of [`UtxoBackend`](crate::iter::utxo_backend::UtxoBackend).
+
+#[cfg(feature = "on-disk-utxo")]
+pub use hybrid_utxo::HybridUtxo;
+pub use in_mem_utxo::InMemoryUtxo;
+#[cfg(feature = "on-disk-utxo")]
+pub(crate) use on_disk_utxo::open_db_read_only;
 #[cfg(feature = "on-disk-utxo")]
-pub use on_disk_utxo::{connect_outpoints, update_unspent_cache};
+pub use on_disk_utxo::OnDiskUtxo;
 
 #[cfg(feature = "on-disk-utxo")]
 mod on_disk_utxo {
-    use crate::iter::connected_block_iter::KEY_LENGTH;
-    use crate::parser::block_types::connected_block::{ConnectedBlock, ConnectedTx};
-    use crate::BitcoinDB;
+    use crate::iter::bip30::is_duplicate_coinbase;
+    use crate::iter::utxo_backend::UtxoBackend;
+    use crate::parser::error::{Error, Result};
+    use crate::parser::undo::Coin;
     use bitcoin::consensus::{Decodable, Encodable};
     use bitcoin::{Block, TxOut, Txid};
     use rocksdb::{WriteBatch, DB};
-    use std::sync::Arc;
+    use std::path::Path;
+    use tempdir::TempDir;
+
+    /// 32 (txid) + 4 (i32 out n)
+    pub(super) const KEY_LENGTH: u32 = 32 + 4;
+
+    fn db_options() -> rocksdb::Options {
+        let mut options = rocksdb::Options::default();
+        // create table
+        options.create_if_missing(true);
+        // config to more jobs
+        options.set_max_background_jobs(num_cpus::get() as i32);
+        // configure mem-table to a large value (256 MB)
+        options.set_write_buffer_size(0x10000000);
+        // configure l0 and l1 size, let them have the same size (1 GB)
+        options.set_level_zero_file_num_compaction_trigger(4);
+        options.set_max_bytes_for_level_base(0x40000000);
+        // 256MB file size
+        options.set_target_file_size_base(0x10000000);
+        // use a smaller compaction multiplier
+        options.set_max_bytes_for_level_multiplier(4.0);
+        // use 8-byte prefix (2 ^ 64 is far enough for transaction counts)
+        options.set_prefix_extractor(rocksdb::SliceTransform::create_fixed_prefix(8));
+        options
+    }
+
+    fn create_db(path: impl AsRef<Path>) -> Result<DB> {
+        Ok(DB::open(&db_options(), path)?)
+    }
+
+    /// Open an existing database, e.g. a checkpoint, without writing to it.
+    pub(crate) fn open_db_read_only(path: impl AsRef<Path>) -> Result<DB> {
+        Ok(DB::open_for_read_only(&db_options(), path, false)?)
+    }
 
     #[inline(always)]
-    fn txout_key(txid: Txid, n: u32) -> Vec<u8> {
+    pub(super) fn txout_key(txid: Txid, n: u32) -> Vec<u8> {
         use bitcoin::hashes::Hash;
 
         let mut bytes = Vec::with_capacity(KEY_LENGTH as usize);
@@ -23,223 +62,666 @@ mod on_disk_utxo {
         bytes
     }
 
+    /// `height * 2 + is_coinbase`, as in Bitcoin Core, followed by the output.
     #[inline(always)]
-    fn encode_txout(txo: &TxOut) -> Vec<u8> {
+    pub(super) fn encode_coin(txo: &TxOut, height: u32, is_coinbase: bool) -> Vec<u8> {
         let mut bytes = Vec::new();
+        let code = (height << 1) + is_coinbase as u32;
+        code.consensus_encode(&mut bytes).unwrap();
         txo.consensus_encode(&mut bytes).unwrap();
         bytes
     }
 
-    /// read block, update UTXO cache, return block
-    pub fn update_unspent_cache(
-        unspent: &Arc<DB>,
-        db: &BitcoinDB,
-        height: usize,
-    ) -> Result<Block, ()> {
-        let block = db.get_block::<Block>(height).map_err(|_| ())?;
-        let mut batch = WriteBatch::default();
+    #[inline(always)]
+    pub(super) fn decode_coin(mut bytes: &[u8]) -> Result<Coin> {
+        let code = u32::consensus_decode(&mut bytes)?;
+        Ok(Coin {
+            out: TxOut::consensus_decode(&mut bytes)?,
+            height: code >> 1,
+            is_coinbase: code & 1 == 1,
+        })
+    }
 
-        // insert new transactions
-        for tx in block.txdata.iter() {
-            // clone outputs
-            let txid = tx.compute_txid();
+    /// UTXO cache in a rocksdb database.
+    pub struct OnDiskUtxo {
+        db: DB,
+        /// deleted when dropped, after `db` is closed.
+        _dir: Option<TempDir>,
+    }
 
-            for (n, o) in (0_u32..).zip(tx.output.iter()) {
-                let key = txout_key(txid, n);
-                let value = encode_txout(o);
-                batch.put(key, value);
-            }
+    impl OnDiskUtxo {
+        /// Open, or create, the cache at `path`.
+        pub fn open(path: &Path) -> Result<Self> {
+            Ok(Self {
+                db: create_db(path)?,
+                _dir: None,
+            })
         }
-        match unspent.write_without_wal(batch) {
-            Ok(_) => Ok(block),
-            Err(e) => {
-                log::error!("failed to write UTXO to cache, error: {}", e);
-                Err(())
-            }
+
+        /// Create a cache in a temporary directory, deleted when dropped.
+        pub fn temporary() -> Result<Self> {
+            let dir = TempDir::new("rocks_db")?;
+            Ok(Self {
+                db: create_db(dir.path())?,
+                _dir: Some(dir),
+            })
+        }
+
+        pub(crate) fn db(&self) -> &DB {
+            &self.db
         }
     }
 
-    /// fetch_block_connected, thread safe
-    pub fn connect_outpoints<B>(unspent: &Arc<DB>, block: Block) -> Result<B, ()>
+    impl<T> UtxoBackend<T> for OnDiskUtxo
     where
-        B: ConnectedBlock,
+        T: From<TxOut>,
     {
-        let block_hash = block.header.block_hash();
-        let mut output_block = B::from(block.header, block_hash);
-
-        // collect rocks db keys
-        let keys = block
-            .txdata
-            .iter()
-            .flat_map(|tx| {
-                tx.input.iter().filter_map(|input| {
-                    if input.previous_output.is_null() {
-                        None
-                    } else {
-                        Some(txout_key(
-                            input.previous_output.txid,
-                            input.previous_output.vout,
-                        ))
+        fn insert_block(&self, block: &Block, height: usize) -> Result<()> {
+            let mut batch = WriteBatch::default();
+
+            // insert new transactions
+            for tx in block.txdata.iter() {
+                // clone outputs
+                let txid = tx.compute_txid();
+                let is_coinbase = tx.is_coinbase();
+
+                // only coinbase transactions have repeated txids (BIP30),
+                // the repeated outputs overwrite the unspent ones.
+                if is_coinbase {
+                    if let Ok(Some(_)) = self.db.get(txout_key(txid, 0)) {
+                        if !is_duplicate_coinbase(height, &txid) {
+                            log::warn!(
+                                "coinbase {txid} at height {height} overwrites unspent outputs"
+                            );
+                        }
                     }
+                }
+
+                for (n, o) in (0_u32..).zip(tx.output.iter()) {
+                    let key = txout_key(txid, n);
+                    let value = encode_coin(o, height as u32, is_coinbase);
+                    batch.put(key, value);
+                }
+            }
+            self.db.write_without_wal(batch)?;
+            Ok(())
+        }
+
+        fn spend_block(&self, block: &Block) -> Result<Vec<Coin<T>>> {
+            let outpoints = block
+                .txdata
+                .iter()
+                .filter(|tx| !tx.is_coinbase())
+                .flat_map(|tx| tx.input.iter().map(|input| input.previous_output))
+                .collect::<Vec<_>>();
+
+            // collect rocks db keys
+            let keys = outpoints
+                .iter()
+                .map(|o| txout_key(o.txid, o.vout))
+                .collect::<Vec<_>>();
+
+            // get utxo
+            let tx_outs = self.db.multi_get(keys.iter());
+
+            // remove keys
+            for key in keys {
+                self.db.delete(&key)?;
+            }
+
+            outpoints
+                .into_iter()
+                .zip(tx_outs)
+                .map(|(outpoint, tx_out)| match tx_out {
+                    Ok(Some(bytes)) => Ok(decode_coin(&bytes)?.map(Into::into)),
+                    Ok(None) => Err(Error::PrevoutNotFound(outpoint)),
+                    Err(e) => Err(Error::RuntimeError(e.to_string())),
                 })
-            })
-            .collect::<Vec<_>>();
+                .collect()
+        }
+    }
+}
 
-        // get utxo
-        let tx_outs = unspent.multi_get(keys.clone());
+/// UTXO cache keeping recent outputs in memory within a byte budget,
+/// and spilling the oldest ones to rocksdb.
+///
+/// Most outputs are spent shortly after being created, so recent outputs
+/// are kept in memory and the outputs of the oldest blocks are written
+/// to disk when the budget is exceeded. Provably unspendable outputs
+/// (e.g. `OP_RETURN`) are never stored.
+#[cfg(feature = "on-disk-utxo")]
+mod hybrid_utxo {
+    use super::on_disk_utxo::{decode_coin, encode_coin, txout_key, OnDiskUtxo};
+    use crate::iter::bip30::is_duplicate_coinbase;
+    use crate::iter::utxo_backend::UtxoBackend;
+    use crate::parser::error::{Error, Result};
+    use crate::parser::script::is_unspendable;
+    use crate::parser::undo::Coin;
+    use bitcoin::{Block, OutPoint, TxOut};
+    use hash_hasher::HashedMap;
+    use rocksdb::WriteBatch;
+    use std::collections::BTreeMap;
+    use std::mem::size_of;
+    use std::sync::Mutex;
+
+    /// Bytes of a hash table bucket: the entry and its control byte.
+    const BUCKET_SIZE: usize = size_of::<(OutPoint, Coin)>() + 1;
+
+    #[inline(always)]
+    fn disk_key(outpoint: &OutPoint) -> Vec<u8> {
+        txout_key(outpoint.txid, outpoint.vout)
+    }
+
+    /// Bytes allocated for the script of `coin`, outside of the hash table.
+    #[inline(always)]
+    fn heap_size(coin: &Coin) -> usize {
+        coin.out.script_pubkey.len()
+    }
+
+    struct MemoryCache {
+        /// Keyed by the full outpoint: a txid prefix would not be enough
+        /// to write spilled outputs under their disk key, nor to tell apart
+        /// transactions sharing the prefix.
+        outputs: HashedMap<OutPoint, Coin>,
+        /// bytes of the scripts in `outputs`.
+        heap_size: usize,
+    }
+
+    impl MemoryCache {
+        /// Bytes used by the cache: the buckets of the hash table,
+        /// about 8/7 of its capacity, and the scripts.
+        fn size(&self) -> usize {
+            (self.outputs.capacity() * 8 + 6) / 7 * BUCKET_SIZE + self.heap_size
+        }
 
-        // remove keys
-        for key in keys {
-            if let Err(e) = unspent.delete(&key) {
-                log::error!("failed to remove key {key:?}: {e}");
-                return Err(());
+        /// Bytes used by `len` entries and `heap_size` bytes of scripts once
+        /// the table is shrunk: it has less than twice 8/7 buckets per entry,
+        /// and at least 8 buckets.
+        fn shrunk_size(len: usize, heap_size: usize) -> usize {
+            (len * 16 / 7 + 8) * BUCKET_SIZE + heap_size
+        }
+    }
+
+    pub struct HybridUtxo {
+        memory: Mutex<MemoryCache>,
+        disk: OnDiskUtxo,
+        budget: usize,
+    }
+
+    impl HybridUtxo {
+        /// `budget`: bytes used by the outputs kept in memory.
+        pub fn new(disk: OnDiskUtxo, budget: usize) -> Self {
+            Self {
+                memory: Mutex::new(MemoryCache {
+                    outputs: HashedMap::default(),
+                    heap_size: 0,
+                }),
+                disk,
+                budget,
             }
         }
 
-        // pointer to record read position in tx_outs
-        let mut pos = 0;
+        /// Spill outputs to a temporary rocksdb cache, deleted when dropped.
+        pub fn temporary(budget: usize) -> Result<Self> {
+            Ok(Self::new(OnDiskUtxo::temporary()?, budget))
+        }
+
+        /// Bytes used by the outputs in memory.
+        #[cfg(test)]
+        pub(crate) fn memory_size(&self) -> usize {
+            self.memory.lock().unwrap().size()
+        }
 
-        for tx in block.txdata {
-            let mut output_tx: B::Tx = ConnectedTx::from(&tx);
+        /// Write the outputs of the oldest blocks to disk, until
+        /// 3/4 of the budget is used once the table is shrunk.
+        fn spill(&self, memory: &mut MemoryCache) -> Result<()> {
+            let target = self.budget / 4 * 3;
+            // entries and script bytes per height.
+            let mut heights: BTreeMap<u32, (usize, usize)> = BTreeMap::new();
+            for coin in memory.outputs.values() {
+                let (len, heap) = heights.entry(coin.height).or_default();
+                *len += 1;
+                *heap += heap_size(coin);
+            }
+            let (mut len, mut heap) = (memory.outputs.len(), memory.heap_size);
+            let mut last_spilled = None;
+            for (height, (n, h)) in heights {
+                if MemoryCache::shrunk_size(len, heap) <= target {
+                    break;
+                }
+                len -= n;
+                heap -= h;
+                last_spilled = Some(height);
+            }
+            if let Some(last_spilled) = last_spilled {
+                let mut batch = WriteBatch::default();
+                memory.outputs.retain(|outpoint, coin| {
+                    let keep = coin.height > last_spilled;
+                    if !keep {
+                        let value = encode_coin(&coin.out, coin.height, coin.is_coinbase);
+                        batch.put(disk_key(outpoint), value);
+                    }
+                    keep
+                });
+                memory.heap_size = heap;
+                self.disk.db().write_without_wal(batch)?;
+            }
+            // the table does not shrink when outputs are removed.
+            memory.outputs.shrink_to_fit();
+            Ok(())
+        }
+    }
 
-            // spend new inputs
-            for input in tx.input {
-                // skip coinbase transaction
-                if input.previous_output.is_null() {
-                    continue;
+    impl<T> UtxoBackend<T> for HybridUtxo
+    where
+        T: From<TxOut>,
+    {
+        /// Add the spendable outputs of `block` to the cache.
+        fn insert_block(&self, block: &Block, height: usize) -> Result<()> {
+            let mut new_outputs = Vec::new();
+            let mut stale = WriteBatch::default();
+            for tx in block.txdata.iter() {
+                let txid = tx.compute_txid();
+                let is_coinbase = tx.is_coinbase();
+                // a repeated coinbase (BIP30) replaces the outputs, even if they were spilled.
+                if is_coinbase && is_duplicate_coinbase(height, &txid) {
+                    for vout in 0..tx.output.len() as u32 {
+                        stale.delete(disk_key(&OutPoint::new(txid, vout)));
+                    }
+                }
+                for (vout, o) in (0_u32..).zip(tx.output.iter()) {
+                    if is_unspendable(&o.script_pubkey) {
+                        continue;
+                    }
+                    let coin = Coin {
+                        out: o.clone(),
+                        height: height as u32,
+                        is_coinbase,
+                    };
+                    new_outputs.push((OutPoint::new(txid, vout), coin));
                 }
+            }
+            if !stale.is_empty() {
+                self.disk.db().write_without_wal(stale)?;
+            }
 
-                let prev_txo = match tx_outs.get(pos).unwrap() {
-                    Ok(Some(bytes)) => TxOut::consensus_decode(&mut bytes.as_slice()).ok(),
-                    Ok(None) => None,
-                    Err(_) => None,
-                };
-
-                if let Some(out) = prev_txo {
-                    output_tx.add_input(out.into());
-                    pos += 1;
-                } else {
-                    log::error!("cannot find previous outpoint, bad data");
-                    return Err(());
+            let mut memory = self.memory.lock()?;
+            for (outpoint, coin) in new_outputs {
+                memory.heap_size += heap_size(&coin);
+                if let Some(old) = memory.outputs.insert(outpoint, coin) {
+                    memory.heap_size -= heap_size(&old);
                 }
             }
-            output_block.add_tx(output_tx);
+            // the disk is written while holding the lock, so that
+            // spilled outputs are always found by `spend_block`.
+            if memory.size() > self.budget {
+                self.spill(&mut memory)?;
+            }
+            Ok(())
         }
 
-        Ok(output_block)
+        fn spend_block(&self, block: &Block) -> Result<Vec<Coin<T>>> {
+            let outpoints: Vec<OutPoint> = block
+                .txdata
+                .iter()
+                .filter(|tx| !tx.is_coinbase())
+                .flat_map(|tx| tx.input.iter().map(|i| i.previous_output))
+                .collect();
+
+            // spend outputs found in memory, and collect the others.
+            let mut prevouts: Vec<Option<Coin>> = Vec::with_capacity(outpoints.len());
+            let mut missing = Vec::new();
+            {
+                let mut memory = self.memory.lock()?;
+                for outpoint in outpoints.iter() {
+                    match memory.outputs.remove(outpoint) {
+                        Some(coin) => {
+                            memory.heap_size -= heap_size(&coin);
+                            prevouts.push(Some(coin));
+                        }
+                        None => {
+                            missing.push(prevouts.len());
+                            prevouts.push(None);
+                        }
+                    }
+                }
+            }
+
+            // spend outputs spilled to disk.
+            if !missing.is_empty() {
+                let disk = self.disk.db();
+                let keys: Vec<Vec<u8>> = missing.iter().map(|i| disk_key(&outpoints[*i])).collect();
+                let values = disk.multi_get(keys.iter());
+                let mut batch = WriteBatch::default();
+                for ((i, key), value) in missing.iter().zip(keys.iter()).zip(values) {
+                    let bytes = value?.ok_or(Error::PrevoutNotFound(outpoints[*i]))?;
+                    prevouts[*i] = Some(decode_coin(&bytes)?);
+                    batch.delete(key);
+                }
+                disk.write_without_wal(batch)?;
+            }
+
+            prevouts
+                .into_iter()
+                .zip(outpoints)
+                .map(|(coin, outpoint)| {
+                    let coin = coin.ok_or(Error::PrevoutNotFound(outpoint))?;
+                    Ok(coin.map(Into::into))
+                })
+                .collect()
+        }
     }
 }
 
-#[cfg(not(feature = "on-disk-utxo"))]
 mod in_mem_utxo {
+    use crate::iter::bip30::is_duplicate_coinbase;
     use crate::iter::util::VecMap;
-    use crate::parser::block_types::connected_block::{ConnectedBlock, ConnectedTx};
-    use crate::BitcoinDB;
-    use bitcoin::{Block, Txid};
+    use crate::iter::utxo_backend::UtxoBackend;
+    use crate::parser::error::{Error, Result};
+    use crate::parser::undo::Coin;
+    use bitcoin::{Block, TxOut, Txid};
     use hash_hasher::HashedMap;
     use std::sync::{Arc, Mutex};
 
-    #[cfg(not(feature = "on-disk-utxo"))]
-    type InMemoryUtxoCache<B> = Arc<
-        Mutex<
-            HashedMap<Txid, Arc<Mutex<VecMap<<<B as ConnectedBlock>::Tx as ConnectedTx>::TxOut>>>>,
-        >,
-    >;
-
-    /// read block, update UTXO cache, return block
-    pub fn update_unspent_cache<B>(
-        unspent: &InMemoryUtxoCache<B>,
-        db: &BitcoinDB,
-        height: usize,
-    ) -> Result<Block, ()>
-    where
-        B: ConnectedBlock,
-    {
-        let block = db.get_block::<Block>(height).map_err(|_| ())?;
-        let mut new_unspent_cache = Vec::with_capacity(block.txdata.len());
-
-        // insert new transactions
-        for tx in block.txdata.iter() {
-            // clone outputs
-            let outs: Vec<Option<Box<<B::Tx as ConnectedTx>::TxOut>>> = tx
-                .output
-                .iter()
-                .map(|o| Some(Box::new(o.clone().into())))
-                .collect();
-
-            // update unspent cache
-            let outs: VecMap<<B::Tx as ConnectedTx>::TxOut> =
-                VecMap::from_vec(outs.into_boxed_slice());
-            let new_unspent = Arc::new(Mutex::new(outs));
+    /// Unspent outputs of a transaction.
+    struct UnspentTx<T> {
+        height: u32,
+        is_coinbase: bool,
+        outputs: VecMap<T>,
+    }
 
-            let txid = tx.compute_txid();
+    /// UTXO cache keeping all unspent outputs in memory, converted to `T`.
+    pub struct InMemoryUtxo<T> {
+        unspent: Mutex<HashedMap<Txid, Arc<Mutex<UnspentTx<T>>>>>,
+    }
 
-            // the new transaction should not be in unspent
-            #[cfg(debug_assertions)]
-            if unspent.lock().unwrap().contains_key(&txid) {
-                log::warn!("found duplicate key {txid}");
+    impl<T> InMemoryUtxo<T> {
+        pub fn new() -> Self {
+            Self {
+                unspent: Mutex::new(HashedMap::default()),
             }
+        }
 
-            new_unspent_cache.push((txid, new_unspent));
+        /// Number of transactions with unspent outputs.
+        #[cfg(test)]
+        pub(crate) fn len(&self) -> usize {
+            self.unspent.lock().unwrap().len()
         }
-        unspent.lock().unwrap().extend(new_unspent_cache);
-        // if some exception happens in lower stream
-        Ok(block)
     }
 
-    /// fetch_block_connected, thread safe
-    pub fn connect_outpoints<B>(unspent: &InMemoryUtxoCache<B>, block: Block) -> Result<B, ()>
+    impl<T> Default for InMemoryUtxo<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<T> UtxoBackend<T> for InMemoryUtxo<T>
     where
-        B: ConnectedBlock,
+        T: From<TxOut> + Send,
     {
-        let block_hash = block.header.block_hash();
-        let mut output_block = B::from(block.header, block_hash);
+        /// A repeated txid overwrites the unspent outputs, as in Bitcoin Core (BIP30).
+        fn insert_block(&self, block: &Block, height: usize) -> Result<()> {
+            let mut new_unspent_cache = Vec::with_capacity(block.txdata.len());
+
+            // insert new transactions
+            for tx in block.txdata.iter() {
+                // clone outputs
+                let outs: Vec<Option<Box<T>>> = tx
+                    .output
+                    .iter()
+                    .map(|o| Some(Box::new(o.clone().into())))
+                    .collect();
+
+                // update unspent cache
+                let new_unspent = Arc::new(Mutex::new(UnspentTx {
+                    height: height as u32,
+                    is_coinbase: tx.is_coinbase(),
+                    outputs: VecMap::from_vec(outs.into_boxed_slice()),
+                }));
+
+                let txid = tx.compute_txid();
+
+                // only coinbase transactions have repeated txids (BIP30),
+                // the repeated outputs overwrite the unspent ones.
+                if tx.is_coinbase()
+                    && self.unspent.lock()?.contains_key(&txid)
+                    && !is_duplicate_coinbase(height, &txid)
+                {
+                    log::warn!("coinbase {txid} at height {height} overwrites unspent outputs");
+                }
 
-        for tx in block.txdata {
-            let mut output_tx: B::Tx = ConnectedTx::from(&tx);
+                new_unspent_cache.push((txid, new_unspent));
+            }
+            self.unspent.lock()?.extend(new_unspent_cache);
+            Ok(())
+        }
 
-            // spend new inputs
-            for input in tx.input {
-                // skip coinbase transaction
-                if input.previous_output.is_null() {
-                    continue;
-                }
+        fn spend_block(&self, block: &Block) -> Result<Vec<Coin<T>>> {
+            let mut prevouts = Vec::new();
 
-                let prev_txid = &input.previous_output.txid;
-                let n = input.previous_output.vout as usize;
+            for tx in block.txdata.iter().filter(|tx| !tx.is_coinbase()) {
+                for input in tx.input.iter() {
+                    let prev_txid = &input.previous_output.txid;
+                    let n = input.previous_output.vout as usize;
 
-                // temporarily lock unspent
-                let prev_tx = {
-                    let prev_tx = unspent.lock().unwrap();
-                    prev_tx.get(prev_txid).cloned()
-                };
+                    // temporarily lock unspent
+                    let prev_tx = {
+                        let prev_tx = self.unspent.lock()?;
+                        prev_tx.get(prev_txid).cloned()
+                    };
+                    let prev_tx = prev_tx.ok_or(Error::PrevoutNotFound(input.previous_output))?;
 
-                if let Some(prev_tx) = prev_tx {
                     // temporarily lock prev_tx
-                    let (tx_out, is_empty) = {
-                        let mut prev_tx_lock = prev_tx.lock().unwrap();
-                        let tx_out = prev_tx_lock.remove(n);
-                        let is_empty = prev_tx_lock.is_empty();
-                        (tx_out, is_empty)
+                    let (coin, is_empty) = {
+                        let mut prev_tx_lock = prev_tx.lock()?;
+                        let coin = prev_tx_lock.outputs.remove(n).map(|out| Coin {
+                            out: *out,
+                            height: prev_tx_lock.height,
+                            is_coinbase: prev_tx_lock.is_coinbase,
+                        });
+                        let is_empty = prev_tx_lock.outputs.is_empty();
+                        (coin, is_empty)
                     };
                     // remove a key immediately when the key contains no transaction
                     if is_empty {
-                        unspent.lock().unwrap().remove(prev_txid);
-                    }
-                    if let Some(out) = tx_out {
-                        output_tx.add_input(*out);
-                    } else {
-                        log::error!("cannot find previous outpoint, bad data");
-                        return Err(());
+                        self.unspent.lock()?.remove(prev_txid);
                     }
-                } else {
-                    log::error!("cannot find previous transactions, bad data");
-                    return Err(());
+                    let coin = coin.ok_or(Error::PrevoutNotFound(input.previous_output))?;
+                    prevouts.push(coin);
                 }
             }
-            output_block.add_tx(output_tx);
+
+            Ok(prevouts)
         }
+    }
+}
 
-        Ok(output_block)
+#[cfg(test)]
+fn block_with(txdata: Vec<bitcoin::Transaction>) -> bitcoin::Block {
+    let mut block = bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Bitcoin);
+    block.txdata = txdata;
+    block
+}
+
+/// A block index of `len` copies of the genesis block, for block times.
+#[cfg(test)]
+fn block_index(len: usize) -> crate::BlockIndex {
+    let header = bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Bitcoin).header;
+    let records = (0..len)
+        .map(|height| crate::parser::block_index::BlockIndexRecord {
+            n_version: 0,
+            n_height: height as i32,
+            n_status: 0,
+            n_tx: 0,
+            n_file: 0,
+            n_data_pos: 0,
+            n_undo_pos: 0,
+            block_header: header,
+        })
+        .collect();
+    crate::BlockIndex::from_records(records)
+}
+
+#[cfg(test)]
+mod test_bip30 {
+    use super::{block_index, block_with, InMemoryUtxo};
+    #[cfg(feature = "on-disk-utxo")]
+    use super::{HybridUtxo, OnDiskUtxo};
+    use crate::api::FullConnectedBlock;
+    use crate::iter::utxo_backend::{connect_block, UtxoBackend};
+    use crate::parser::block_types::full_block::FullTxOut;
+    use crate::BlockIndex;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::{Network, OutPoint, TxIn};
+
+    /// Mine the same coinbase at 91812 and 91842, as the first duplicate of BIP30,
+    /// then spend its output twice.
+    fn check_duplicate_coinbase(unspent: &dyn UtxoBackend<FullTxOut>, index: &BlockIndex) {
+        let coinbase = genesis_block(Network::Bitcoin).txdata.remove(0);
+        let txid = coinbase.compute_txid();
+
+        unspent
+            .insert_block(&block_with(vec![coinbase.clone()]), 91812)
+            .unwrap();
+        unspent
+            .insert_block(&block_with(vec![coinbase.clone()]), 91842)
+            .unwrap();
+
+        let mut spend = coinbase.clone();
+        spend.input = vec![TxIn {
+            previous_output: OutPoint::new(txid, 0),
+            ..Default::default()
+        }];
+        let spending_block = block_with(vec![coinbase.clone(), spend]);
+        let prevouts = unspent.spend_block(&spending_block).unwrap();
+        let connected: FullConnectedBlock =
+            connect_block(spending_block.clone(), prevouts, index).unwrap();
+        let input = &connected.txdata[1].input[0];
+        assert_eq!(input.prevout.value, coinbase.output[0].value.to_sat());
+        // the outputs of the later coinbase are kept
+        assert_eq!(input.prevout_height, 91842);
+        assert_eq!(input.prevout_time, spending_block.header.time);
+        assert!(input.prevout_is_coinbase);
+        // the overwritten outputs cannot be spent a second time
+        assert!(unspent.spend_block(&spending_block).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_coinbase() {
+        let unspent = InMemoryUtxo::<FullTxOut>::new();
+        check_duplicate_coinbase(&unspent, &block_index(91843));
+        assert_eq!(unspent.len(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "on-disk-utxo")]
+    fn test_duplicate_coinbase_on_disk() {
+        let index = block_index(91843);
+        check_duplicate_coinbase(&OnDiskUtxo::temporary().unwrap(), &index);
+        // all outputs spilled to disk, or all kept in memory.
+        for budget in [0, 1 << 20] {
+            check_duplicate_coinbase(&HybridUtxo::temporary(budget).unwrap(), &index);
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "on-disk-utxo")]
+mod test_hybrid {
+    use super::{block_index, block_with, HybridUtxo, OnDiskUtxo};
+    use crate::api::FullConnectedBlock;
+    use crate::iter::utxo_backend::{connect_block, UtxoBackend};
+    use crate::parser::block_types::full_block::FullTxOut;
+    use crate::parser::error::{Error, Result};
+    use crate::parser::undo::Coin;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, Network, OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Txid};
+
+    fn spend(outpoints: &[OutPoint]) -> Transaction {
+        let mut tx = genesis_block(Network::Bitcoin).txdata.remove(0);
+        tx.input = outpoints
+            .iter()
+            .map(|o| TxIn {
+                previous_output: *o,
+                ..Default::default()
+            })
+            .collect();
+        tx
+    }
+
+    #[test]
+    fn test_hybrid_cache() {
+        let dir = tempdir::TempDir::new("hybrid").unwrap();
+        for budget in [0, 1 << 20] {
+            let disk = OnDiskUtxo::open(&dir.path().join(budget.to_string())).unwrap();
+            let cache = HybridUtxo::new(disk, budget);
+            let mut funding = spend(&[OutPoint::new(Txid::from_byte_array([1; 32]), 0)]);
+            funding.output.push(TxOut {
+                value: Amount::ZERO,
+                script_pubkey: ScriptBuf::new_op_return([0u8; 4]),
+            });
+            let txid = funding.compute_txid();
+            let coinbase = genesis_block(Network::Bitcoin).txdata.remove(0);
+            UtxoBackend::<FullTxOut>::insert_block(
+                &cache,
+                &block_with(vec![coinbase.clone(), funding.clone()]),
+                1,
+            )
+            .unwrap();
+
+            let spending = block_with(vec![coinbase.clone(), spend(&[OutPoint::new(txid, 0)])]);
+            let prevouts = cache.spend_block(&spending).unwrap();
+            let connected: FullConnectedBlock =
+                connect_block(spending, prevouts, &block_index(2)).unwrap();
+            let input = &connected.txdata[1].input[0];
+            assert_eq!(input.prevout.value, funding.output[0].value.to_sat());
+            assert_eq!(input.prevout_height, 1);
+            assert!(!input.prevout_is_coinbase);
+            // spent outputs and OP_RETURN outputs are not in the cache
+            for vout in [0, 1] {
+                let block = block_with(vec![coinbase.clone(), spend(&[OutPoint::new(txid, vout)])]);
+                let result: Result<Vec<Coin<FullTxOut>>> = cache.spend_block(&block);
+                assert!(matches!(result, Err(Error::PrevoutNotFound(_))));
+            }
+        }
+    }
+
+    #[test]
+    fn test_hybrid_budget() {
+        let budget = 16 << 10;
+        let cache = HybridUtxo::temporary(budget).unwrap();
+        let mut unspent = Vec::new();
+        for height in 1..200u32 {
+            // a coinbase creating 50 outputs, and a transaction
+            // spending the outputs created two blocks before.
+            let mut coinbase = genesis_block(Network::Bitcoin).txdata.remove(0);
+            coinbase.lock_time = bitcoin::absolute::LockTime::from_consensus(height);
+            coinbase.output = (0..50)
+                .map(|i| TxOut {
+                    value: Amount::from_sat(i),
+                    script_pubkey: ScriptBuf::from_bytes(vec![0x51; 1 + i as usize]),
+                })
+                .collect();
+            let txid = coinbase.compute_txid();
+            let spent: Vec<OutPoint> = if unspent.len() > 50 {
+                unspent.drain(..50).collect()
+            } else {
+                Vec::new()
+            };
+            let mut txdata = vec![coinbase];
+            if !spent.is_empty() {
+                txdata.push(spend(&spent));
+            }
+            let block = block_with(txdata);
+
+            let prevouts: Vec<Coin<FullTxOut>> = cache.spend_block(&block).unwrap();
+            let values: Vec<u64> = prevouts.iter().map(|c| c.out.value).collect();
+            let expected: Vec<u64> = spent.iter().map(|o| o.vout as u64).collect();
+            assert_eq!(values, expected);
+            UtxoBackend::<FullTxOut>::insert_block(&cache, &block, height as usize).unwrap();
+            unspent.extend((0..50).map(|vout| OutPoint::new(txid, vout)));
+            assert!(cache.memory_size() <= budget, "height {}", height);
+        }
     }
 }
@@ -1,9 +1,30 @@
 //! This module defines the infrastructure for efficient iteration over blocks
 
+pub(crate) mod bip30;
 mod block_iter;
+pub(crate) mod block_stats_iter;
+pub(crate) mod block_verify_iter;
 mod connected_block_iter;
 mod fetch_connected_async;
+#[cfg(feature = "verify-scripts")]
+pub(crate) mod script_verify_iter;
 mod util;
+pub(crate) mod utxo_backend;
+#[cfg(feature = "on-disk-utxo")]
+mod utxo_checkpoint;
+pub(crate) mod utxo_set_hash_iter;
 
-pub use block_iter::BlockIter;
-pub use connected_block_iter::ConnectedBlockIter;
+pub use bip30::{duplicate_coinbases, DuplicateCoinbase};
+pub use block_iter::{BlockIter, TryBlockIter};
+pub use block_stats_iter::{BlockStats, BlockStatsIter};
+pub use block_verify_iter::{check_block, BlockError, BlockVerification, BlockVerifyIter};
+pub use connected_block_iter::{ConnectedBlockIter, TryConnectedBlockIter};
+pub use fetch_connected_async::InMemoryUtxo;
+#[cfg(feature = "on-disk-utxo")]
+pub use fetch_connected_async::{HybridUtxo, OnDiskUtxo};
+#[cfg(feature = "verify-scripts")]
+pub use script_verify_iter::{
+    script_flags, verify_tx_scripts, ScriptFailure, ScriptVerification, ScriptVerifyIter,
+};
+pub use utxo_backend::{ConnectedIterOptions, UtxoBackend, UtxoBackendKind};
+pub use utxo_set_hash_iter::{UtxoSetHashIter, UtxoSetInfo};
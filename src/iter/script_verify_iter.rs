@@ -0,0 +1,226 @@
+//! Consensus script verification of historical blocks.
+//!
+//! Scripts are verified by libbitcoinconsensus, with the flags Bitcoin Core
+//! applies at each height (`GetBlockScriptFlags` in validation.cpp).
+//! Spent outputs are read from undo data, so every block is independent
+//! and verified in parallel.
+//!
+//! Requires the `verify-scripts` feature.
+
+use crate::api::BitcoinDB;
+use crate::iter::block_stats_iter::block_with_undo;
+use crate::parser::error::Result;
+use bitcoin::consensus::serialize;
+use bitcoin::{BlockHash, Transaction, TxOut, Txid};
+use bitcoinconsensus::{
+    Utxo, VERIFY_CHECKLOCKTIMEVERIFY, VERIFY_CHECKSEQUENCEVERIFY, VERIFY_DERSIG, VERIFY_NONE,
+    VERIFY_NULLDUMMY, VERIFY_P2SH, VERIFY_TAPROOT, VERIFY_WITNESS,
+};
+use par_iter_sync::{IntoParallelIteratorSync, ParIterSync};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const BIP66_HEIGHT: usize = 363725;
+const BIP65_HEIGHT: usize = 388381;
+const CSV_HEIGHT: usize = 419328;
+const SEGWIT_HEIGHT: usize = 481824;
+/// The only block violating P2SH rules, mined before BIP16 activation.
+const BIP16_EXCEPTION: (usize, &str) = (
+    170060,
+    "00000000000002dc756eebf4f49723ed8d30cc28a5f108eb94b1ba88ac4f9c22",
+);
+/// The only block violating taproot rules, mined before taproot activation.
+const TAPROOT_EXCEPTION: (usize, &str) = (
+    692261,
+    "0000000000000000000f14c35b2d841e986ab5441de8c585d5ffe55ea1e395ad",
+);
+
+/// Script verification flags enforced for the block at `height` on mainnet.
+///
+/// P2SH, segwit and taproot are enforced from genesis, except for one
+/// block each that violates them. Other rules apply from their activation height.
+pub fn script_flags(height: usize, block_hash: &BlockHash) -> u32 {
+    let is_exception =
+        |(h, hash): (usize, &str)| height == h && block_hash.to_string().as_str() == hash;
+    let mut flags = if is_exception(BIP16_EXCEPTION) {
+        VERIFY_NONE
+    } else if is_exception(TAPROOT_EXCEPTION) {
+        VERIFY_P2SH | VERIFY_WITNESS
+    } else {
+        VERIFY_P2SH | VERIFY_WITNESS | VERIFY_TAPROOT
+    };
+    if height >= BIP66_HEIGHT {
+        flags |= VERIFY_DERSIG;
+    }
+    if height >= BIP65_HEIGHT {
+        flags |= VERIFY_CHECKLOCKTIMEVERIFY;
+    }
+    if height >= CSV_HEIGHT {
+        flags |= VERIFY_CHECKSEQUENCEVERIFY;
+    }
+    if height >= SEGWIT_HEIGHT {
+        flags |= VERIFY_NULLDUMMY;
+    }
+    flags
+}
+
+/// An input whose script failed verification.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct ScriptFailure {
+    pub txid: Txid,
+    /// Index of the input in the transaction.
+    pub input: usize,
+    /// Error reported by libbitcoinconsensus.
+    pub error: String,
+}
+
+/// Result of verifying the scripts of all inputs of a block.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct ScriptVerification {
+    pub height: usize,
+    pub block_hash: BlockHash,
+    /// Flags used for verification, see [`script_flags`].
+    pub flags: u32,
+    /// Number of inputs verified.
+    pub inputs: usize,
+    pub failures: Vec<ScriptFailure>,
+}
+
+impl ScriptVerification {
+    /// Whether all inputs passed verification.
+    pub fn is_valid(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Verify the scripts of every input of `tx`, given the spent outputs
+/// in the order of the inputs.
+///
+/// Coinbase transactions have nothing to verify.
+pub fn verify_tx_scripts(tx: &Transaction, prevouts: &[TxOut], flags: u32) -> Vec<ScriptFailure> {
+    if tx.is_coinbase() {
+        return Vec::new();
+    }
+    let txid = tx.compute_txid();
+    if prevouts.len() != tx.input.len() {
+        return vec![ScriptFailure {
+            txid,
+            input: prevouts.len().min(tx.input.len()),
+            error: format!(
+                "{} inputs, but {} spent outputs",
+                tx.input.len(),
+                prevouts.len()
+            ),
+        }];
+    }
+    let tx_bytes = serialize(tx);
+    // taproot signatures commit to all spent outputs
+    let spent_outputs: Vec<Utxo> = prevouts
+        .iter()
+        .map(|o| Utxo {
+            script_pubkey: o.script_pubkey.as_bytes().as_ptr(),
+            script_pubkey_len: o.script_pubkey.len() as u32,
+            value: o.value.to_sat() as i64,
+        })
+        .collect();
+    prevouts
+        .iter()
+        .enumerate()
+        .filter_map(|(input, prevout)| {
+            bitcoinconsensus::verify_with_flags(
+                prevout.script_pubkey.as_bytes(),
+                prevout.value.to_sat(),
+                &tx_bytes,
+                Some(&spent_outputs),
+                input,
+                flags,
+            )
+            .err()
+            .map(|e| ScriptFailure {
+                txid,
+                input,
+                error: e.to_string(),
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn verify_block_scripts(db: &BitcoinDB, height: usize) -> Result<ScriptVerification> {
+    let (block, undo) = block_with_undo(db, height)?;
+    let block_hash = block.block_hash();
+    let flags = script_flags(height, &block_hash);
+    let failures = block
+        .txdata
+        .par_iter()
+        .skip(1)
+        .zip(undo.par_iter())
+        .flat_map_iter(|(tx, tx_undo)| {
+            let prevouts: Vec<TxOut> = tx_undo.prevouts.iter().map(|c| c.out.clone()).collect();
+            verify_tx_scripts(tx, &prevouts, flags)
+        })
+        .collect();
+    Ok(ScriptVerification {
+        height,
+        block_hash,
+        flags,
+        inputs: undo.iter().map(|u| u.prevouts.len()).sum(),
+        failures,
+    })
+}
+
+/// Iterate through blocks, yielding the script verification result of each block.
+pub struct ScriptVerifyIter(ParIterSync<ScriptVerification>);
+
+impl ScriptVerifyIter {
+    /// the worker threads are dispatched in this `new` constructor!
+    pub fn new(db: &BitcoinDB, start: usize, end: usize) -> Self {
+        let db = db.clone();
+        Self((start..end.max(start)).into_par_iter_sync(move |height| {
+            verify_block_scripts(&db, height).map_err(|e| {
+                log::error!("failed to verify scripts of block {height}: {e}");
+            })
+        }))
+    }
+}
+
+impl Iterator for ScriptVerifyIter {
+    type Item = ScriptVerification;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_script_flags() {
+        let hash = BlockHash::from_str(BIP16_EXCEPTION.1).unwrap();
+        assert_eq!(script_flags(BIP16_EXCEPTION.0, &hash), VERIFY_NONE);
+        let other = BlockHash::from_str(TAPROOT_EXCEPTION.1).unwrap();
+        assert_eq!(
+            script_flags(BIP16_EXCEPTION.0, &other),
+            VERIFY_P2SH | VERIFY_WITNESS | VERIFY_TAPROOT
+        );
+        assert_eq!(
+            script_flags(TAPROOT_EXCEPTION.0, &other),
+            VERIFY_P2SH
+                | VERIFY_WITNESS
+                | VERIFY_DERSIG
+                | VERIFY_CHECKLOCKTIMEVERIFY
+                | VERIFY_CHECKSEQUENCEVERIFY
+                | VERIFY_NULLDUMMY
+        );
+        assert_eq!(
+            script_flags(BIP65_HEIGHT, &hash),
+            VERIFY_P2SH
+                | VERIFY_WITNESS
+                | VERIFY_TAPROOT
+                | VERIFY_DERSIG
+                | VERIFY_CHECKLOCKTIMEVERIFY
+        );
+    }
+}
@@ -4,14 +4,12 @@
 /// `Some(Box<T>)` (representing a UTXO) or `None` (representing an empty slot). The vector
 /// is compact and allows fast access, removal, and checks for emptiness.
 ///
-/// This is used when the feature `"on-disk-utxo"` is **not** enabled.
-#[cfg(not(feature = "on-disk-utxo"))]
+/// This is used by the in-memory UTXO backend.
 pub(crate) struct VecMap<T> {
     size: u32,
     inner: Box<[Option<Box<T>>]>,
 }
 
-#[cfg(not(feature = "on-disk-utxo"))]
 impl<T> VecMap<T> {
     /// Creates a new `VecMap` from a vector of optional boxed elements.
     ///
@@ -64,7 +62,6 @@ impl<T> VecMap<T> {
 }
 
 #[cfg(test)]
-#[cfg(not(feature = "on-disk-utxo"))]
 mod test_vec_map {
     use crate::api::CompactTxOut;
     use crate::iter::util::VecMap;
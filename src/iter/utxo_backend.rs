@@ -0,0 +1,102 @@
+//! Storage of unspent outputs used by connected iteration.
+//!
+//! The backend is chosen at runtime through [`ConnectedIterOptions`],
+//! or given directly to [`TryConnectedBlockIter::with_backend`](crate::TryConnectedBlockIter::with_backend),
+//! so that other key-value stores (LMDB, sled, ...) can be plugged in
+//! by implementing [`UtxoBackend`].
+
+use crate::parser::block_index::BlockIndex;
+use crate::parser::block_types::connected_block::{block_time, ConnectedBlock, ConnectedTx};
+use crate::parser::error::{Error, Result};
+use crate::parser::undo::Coin;
+use bitcoin::Block;
+
+/// A UTXO cache for connected iteration.
+///
+/// `T` is the type of the outputs returned, `<B::Tx as ConnectedTx>::TxOut`
+/// for the connected block type `B`.
+///
+/// Blocks are inserted and spent from several threads at once, and possibly
+/// out of order, with one guarantee: the outputs of all blocks before `h` are
+/// inserted before the inputs of block `h` are spent.
+///
+/// A repeated txid (only the two duplicated coinbases of BIP30, see
+/// [`duplicate_coinbases`](crate::duplicate_coinbases)) overwrites the unspent
+/// outputs, as in Bitcoin Core.
+pub trait UtxoBackend<T>: Send + Sync {
+    /// Add the outputs created by `block`, at `height`.
+    fn insert_block(&self, block: &Block, height: usize) -> Result<()>;
+
+    /// Remove the outputs spent by `block` and return them, with the height
+    /// and coinbase flag of the transactions that created them, in the order
+    /// of the inputs, the coinbase input excluded.
+    ///
+    /// Returns [`Error::PrevoutNotFound`] if an output is not in the cache.
+    fn spend_block(&self, block: &Block) -> Result<Vec<Coin<T>>>;
+}
+
+/// Built-in UTXO backends.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UtxoBackendKind {
+    /// All unspent outputs in memory (about 32 GB on mainnet), the fastest.
+    InMemory,
+    /// All unspent outputs in a temporary rocksdb database.
+    #[cfg(feature = "on-disk-utxo")]
+    OnDisk,
+    /// Recent outputs in memory up to about `memory_budget` bytes,
+    /// the oldest ones spilled to a temporary rocksdb database.
+    #[cfg(feature = "on-disk-utxo")]
+    Hybrid { memory_budget: usize },
+}
+
+impl Default for UtxoBackendKind {
+    /// `OnDisk` with the `on-disk-utxo` feature, `InMemory` otherwise.
+    fn default() -> Self {
+        #[cfg(feature = "on-disk-utxo")]
+        return UtxoBackendKind::OnDisk;
+        #[cfg(not(feature = "on-disk-utxo"))]
+        return UtxoBackendKind::InMemory;
+    }
+}
+
+/// Options of connected iteration.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ConnectedIterOptions {
+    /// Where unspent outputs are kept.
+    pub backend: UtxoBackendKind,
+}
+
+impl ConnectedIterOptions {
+    pub fn with_backend(backend: UtxoBackendKind) -> Self {
+        Self { backend }
+    }
+}
+
+/// Build a connected block from `block` and the outputs spent by its inputs,
+/// as returned by [`UtxoBackend::spend_block`].
+pub(crate) fn connect_block<B>(
+    block: Block,
+    prevouts: Vec<Coin<<B::Tx as ConnectedTx>::TxOut>>,
+    block_index: &BlockIndex,
+) -> Result<B>
+where
+    B: ConnectedBlock,
+{
+    let block_hash = block.header.block_hash();
+    let mut output_block = B::from(&block, block_hash);
+    let mut prevouts = prevouts.into_iter();
+    for tx in block.txdata {
+        let mut output_tx: B::Tx = ConnectedTx::from(&tx);
+        if !tx.is_coinbase() {
+            for input in tx.input {
+                let coin = prevouts
+                    .next()
+                    .ok_or(Error::PrevoutNotFound(input.previous_output))?;
+                let time = block_time(block_index, coin.height)?;
+                output_tx.add_input(&input, coin, time);
+            }
+        }
+        output_block.add_tx(output_tx);
+    }
+    Ok(output_block)
+}
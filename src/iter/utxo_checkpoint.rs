@@ -0,0 +1,239 @@
+//! Persistent UTXO cache of `ConnectedBlockIter`, with checkpoints.
+//!
+//! The cache directory contains:
+//! - `utxo/`: the rocksdb cache being updated by the iteration,
+//! - `checkpoint/`: a rocksdb checkpoint (hard links to `utxo/` files)
+//!   taken when no block is being connected, together with a `HEIGHT` file
+//!   holding its height, its interval and [`CHECKPOINT_FORMAT`].
+//!
+//! A checkpoint at height `n` holds the outputs left unspent by blocks
+//! `0..n`. The working cache is only consistent between two segments
+//! of the iteration, so a crash always resumes from `checkpoint/`,
+//! whose content replaces `utxo/`.
+//!
+//! Checkpoints are written to `checkpoint.tmp/` then renamed, so one
+//! complete checkpoint always exists once the first one is written.
+
+use crate::parser::error::{Error, Result};
+use rocksdb::checkpoint::Checkpoint;
+use rocksdb::DB;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const WORKING_DIR: &str = "utxo";
+const CHECKPOINT_DIR: &str = "checkpoint";
+const CHECKPOINT_TMP_DIR: &str = "checkpoint.tmp";
+const HEIGHT_FILE: &str = "HEIGHT";
+
+/// Version of the encoding of cached outputs, bumped whenever it changes.
+///
+/// Checkpoints without a version were written before coins kept their
+/// height and coinbase flag, and cannot be resumed.
+const CHECKPOINT_FORMAT: u32 = 2;
+
+/// Height and interval of the last checkpoint found in a cache directory.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct CheckpointInfo {
+    /// Number of blocks connected, i.e. the next height to connect.
+    pub(crate) height: usize,
+    /// Number of blocks between two checkpoints.
+    pub(crate) interval: usize,
+}
+
+/// Layout of a persistent UTXO cache directory.
+pub(crate) struct CacheDir {
+    root: PathBuf,
+}
+
+impl CacheDir {
+    pub(crate) fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// Path of the rocksdb cache updated by the iteration.
+    pub(crate) fn working_path(&self) -> PathBuf {
+        self.root.join(WORKING_DIR)
+    }
+
+    /// Remove any cache and checkpoint, to start again from height 0.
+    pub(crate) fn clear(&self) -> Result<()> {
+        for dir in [WORKING_DIR, CHECKPOINT_DIR, CHECKPOINT_TMP_DIR] {
+            remove_dir_if_exists(&self.root.join(dir))?;
+        }
+        fs::create_dir_all(&self.root)?;
+        Ok(())
+    }
+
+    /// Checkpoint `db` after `info.height` blocks are connected.
+    ///
+    /// No block may be in the process of being connected.
+    pub(crate) fn save(&self, db: &DB, info: CheckpointInfo) -> Result<()> {
+        let tmp = self.root.join(CHECKPOINT_TMP_DIR);
+        let checkpoint = self.root.join(CHECKPOINT_DIR);
+        remove_dir_if_exists(&tmp)?;
+        Checkpoint::new(db)?.create_checkpoint(&tmp)?;
+        fs::write(
+            tmp.join(HEIGHT_FILE),
+            format!(
+                "{}\n{}\n{}\n",
+                info.height, info.interval, CHECKPOINT_FORMAT
+            ),
+        )?;
+        remove_dir_if_exists(&checkpoint)?;
+        fs::rename(&tmp, &checkpoint)?;
+        Ok(())
+    }
+
+    /// Replace the working cache by the last checkpoint.
+    ///
+    /// `open` opens the rocksdb checkpoint at the given path, read-only:
+    /// it must stay intact if the process crashes again.
+    pub(crate) fn restore<F>(&self, open: F) -> Result<CheckpointInfo>
+    where
+        F: Fn(&Path) -> Result<DB>,
+    {
+        let mut checkpoint = self.root.join(CHECKPOINT_DIR);
+        // a crash may happen between removing the old checkpoint and renaming the new one.
+        if !checkpoint.join(HEIGHT_FILE).exists() {
+            checkpoint = self.root.join(CHECKPOINT_TMP_DIR);
+        }
+        let (info, format) = read_height_file(&checkpoint.join(HEIGHT_FILE))
+            .ok_or_else(|| Error::CheckpointNotFound(self.root.clone()))?;
+        if format != CHECKPOINT_FORMAT {
+            return Err(Error::IncompatibleCheckpoint(self.root.clone()));
+        }
+        let working = self.working_path();
+        remove_dir_if_exists(&working)?;
+        let db = open(&checkpoint)?;
+        Checkpoint::new(&db)?.create_checkpoint(&working)?;
+        Ok(info)
+    }
+}
+
+/// The checkpoint info and format version, 1 if the file has none.
+fn read_height_file(path: &Path) -> Option<(CheckpointInfo, u32)> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut lines = content.lines().map(|l| l.trim());
+    let height = lines.next()?.parse().ok()?;
+    let interval = lines.next()?.parse().ok()?;
+    let format = match lines.next() {
+        Some(line) => line.parse().ok()?,
+        None => 1,
+    };
+    Some((CheckpointInfo { height, interval }, format))
+}
+
+fn remove_dir_if_exists(path: &Path) -> Result<()> {
+    if path.exists() {
+        fs::remove_dir_all(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocksdb::Options;
+
+    fn open(path: &Path) -> DB {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        DB::open(&options, path).unwrap()
+    }
+
+    fn open_read_only(path: &Path) -> Result<DB> {
+        Ok(DB::open_for_read_only(&Options::default(), path, false)?)
+    }
+
+    /// A cache checkpointed at height 1000, then updated further.
+    fn checkpointed(root: &Path) -> (CacheDir, CheckpointInfo) {
+        let cache = CacheDir::new(root);
+        cache.clear().unwrap();
+        let info = CheckpointInfo {
+            height: 1000,
+            interval: 100,
+        };
+        let db = open(&cache.working_path());
+        db.put(b"coin", b"1000").unwrap();
+        cache.save(&db, info).unwrap();
+        db.put(b"coin", b"1050").unwrap();
+        (cache, info)
+    }
+
+    fn restored_coin(cache: &CacheDir) -> Option<Vec<u8>> {
+        open(&cache.working_path()).get(b"coin").unwrap()
+    }
+
+    #[test]
+    fn test_height_file() {
+        let dir = tempdir::TempDir::new("checkpoint").unwrap();
+        let path = dir.path().join(HEIGHT_FILE);
+        assert_eq!(read_height_file(&path), None);
+        let info = CheckpointInfo {
+            height: 750000,
+            interval: 10000,
+        };
+        fs::write(&path, "750000\n10000\n2\n").unwrap();
+        assert_eq!(read_height_file(&path), Some((info, 2)));
+        fs::write(&path, "750000\n10000\n").unwrap();
+        assert_eq!(read_height_file(&path), Some((info, 1)));
+        fs::write(&path, "750000\n").unwrap();
+        assert_eq!(read_height_file(&path), None);
+    }
+
+    #[test]
+    fn test_missing_checkpoint() {
+        let dir = tempdir::TempDir::new("checkpoint").unwrap();
+        let cache = CacheDir::new(dir.path());
+        cache.clear().unwrap();
+        let restored = cache.restore(|_| unreachable!());
+        assert!(matches!(restored, Err(Error::CheckpointNotFound(_))));
+    }
+
+    #[test]
+    fn test_incompatible_checkpoint() {
+        let dir = tempdir::TempDir::new("checkpoint").unwrap();
+        let cache = CacheDir::new(dir.path());
+        cache.clear().unwrap();
+        let checkpoint = dir.path().join(CHECKPOINT_DIR);
+        fs::create_dir(&checkpoint).unwrap();
+        // written before the format was versioned.
+        fs::write(checkpoint.join(HEIGHT_FILE), "1000\n100\n").unwrap();
+        let restored = cache.restore(|_| unreachable!());
+        assert!(matches!(restored, Err(Error::IncompatibleCheckpoint(_))));
+    }
+
+    #[test]
+    fn test_restore() {
+        let dir = tempdir::TempDir::new("checkpoint").unwrap();
+        let (cache, info) = checkpointed(dir.path());
+        assert_eq!(cache.restore(open_read_only).unwrap(), info);
+        assert_eq!(restored_coin(&cache), Some(b"1000".to_vec()));
+    }
+
+    #[test]
+    fn test_restore_renaming_crash() {
+        let dir = tempdir::TempDir::new("checkpoint").unwrap();
+        let (cache, info) = checkpointed(dir.path());
+        // crash after removing `checkpoint/`, before renaming `checkpoint.tmp/`.
+        fs::rename(
+            dir.path().join(CHECKPOINT_DIR),
+            dir.path().join(CHECKPOINT_TMP_DIR),
+        )
+        .unwrap();
+        assert_eq!(cache.restore(open_read_only).unwrap(), info);
+        assert_eq!(restored_coin(&cache), Some(b"1000".to_vec()));
+    }
+
+    #[test]
+    fn test_restore_saving_crash() {
+        let dir = tempdir::TempDir::new("checkpoint").unwrap();
+        let (cache, info) = checkpointed(dir.path());
+        // crash while writing the next checkpoint, before its `HEIGHT` file.
+        fs::create_dir(dir.path().join(CHECKPOINT_TMP_DIR)).unwrap();
+        assert_eq!(cache.restore(open_read_only).unwrap(), info);
+        assert_eq!(restored_coin(&cache), Some(b"1000".to_vec()));
+    }
+}
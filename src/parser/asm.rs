@@ -0,0 +1,249 @@
+//! Script disassembly in Bitcoin Core's format.
+//!
+//! [`script_to_asm`] reproduces `ScriptToAsmStr`, i.e. the `asm` fields
+//! printed by `bitcoin-cli getrawtransaction <txid> true`.
+//! [`script_instructions`] gives the same information as a list that
+//! can be serialized with serde.
+
+use crate::parser::script::is_unspendable;
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::opcodes::Opcode;
+use bitcoin::Script;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A single decoded instruction of a script.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum ScriptInstruction {
+    /// A non-push opcode, named as in Bitcoin Core (e.g. `OP_DUP`, `1`, `OP_CHECKLOCKTIMEVERIFY`).
+    Op { opcode: u8, name: String },
+    /// Data pushed onto the stack, serialized as hex.
+    Push {
+        #[serde(serialize_with = "serialize_hex", deserialize_with = "deserialize_hex")]
+        data: Vec<u8>,
+    },
+    /// The rest of the script could not be decoded (e.g. a push past the end).
+    Invalid,
+}
+
+/// Disassemble a script into Bitcoin Core's ASM format.
+///
+/// Pushes of up to 4 bytes are shown as numbers, longer pushes as hex.
+/// With `attempt_sighash_decode`, pushes that are valid DER signatures get
+/// their sighash type byte replaced by a suffix such as `[ALL]`, as Core
+/// does for `scriptSig.asm`.
+pub fn script_to_asm(script: &Script, attempt_sighash_decode: bool) -> String {
+    let decode_sighash = attempt_sighash_decode && !is_unspendable(script);
+    let mut asm = String::new();
+    for ins in script.instructions() {
+        if !asm.is_empty() {
+            asm.push(' ');
+        }
+        match ins {
+            Ok(Instruction::PushBytes(data)) => {
+                let data = data.as_bytes();
+                if data.len() <= 4 {
+                    asm.push_str(&script_num(data).to_string());
+                } else if decode_sighash {
+                    asm.push_str(&push_with_sighash(data));
+                } else {
+                    asm.push_str(&data.to_lower_hex_string());
+                }
+            }
+            Ok(Instruction::Op(op)) => asm.push_str(&op_name(op)),
+            Err(_) => {
+                asm.push_str("[error]");
+                break;
+            }
+        }
+    }
+    asm
+}
+
+/// Decode a script into a list of instructions.
+///
+/// Decoding stops at the first invalid push, which is reported as [`ScriptInstruction::Invalid`].
+pub fn script_instructions(script: &Script) -> Vec<ScriptInstruction> {
+    let mut instructions = Vec::new();
+    for ins in script.instructions() {
+        match ins {
+            Ok(Instruction::PushBytes(data)) => instructions.push(ScriptInstruction::Push {
+                data: data.as_bytes().to_vec(),
+            }),
+            Ok(Instruction::Op(op)) => instructions.push(ScriptInstruction::Op {
+                opcode: op.to_u8(),
+                name: op_name(op),
+            }),
+            Err(_) => {
+                instructions.push(ScriptInstruction::Invalid);
+                break;
+            }
+        }
+    }
+    instructions
+}
+
+/// Opcode name as returned by Core's `GetOpName`.
+fn op_name(op: Opcode) -> String {
+    match op.to_u8() {
+        0x00 => "0".to_string(),
+        0x4f => "-1".to_string(),
+        code @ 0x51..=0x60 => (code - 0x50).to_string(),
+        0xb1 => "OP_CHECKLOCKTIMEVERIFY".to_string(),
+        0xb2 => "OP_CHECKSEQUENCEVERIFY".to_string(),
+        0xbb..=0xfe => "OP_UNKNOWN".to_string(),
+        _ => op.to_string(),
+    }
+}
+
+/// Decode a `CScriptNum` (little-endian, sign bit in the most significant byte).
+fn script_num(data: &[u8]) -> i64 {
+    let last = match data.last() {
+        Some(last) => last,
+        None => return 0,
+    };
+    let mut result = 0i64;
+    for (i, b) in data.iter().enumerate() {
+        result |= (*b as i64) << (8 * i);
+    }
+    if last & 0x80 != 0 {
+        -(result & !(0x80i64 << (8 * (data.len() - 1))))
+    } else {
+        result
+    }
+}
+
+/// Hex of a push, with the sighash type of a signature decoded.
+fn push_with_sighash(data: &[u8]) -> String {
+    if is_valid_signature_encoding(data) {
+        let sighash = match data[data.len() - 1] {
+            0x01 => Some("ALL"),
+            0x02 => Some("NONE"),
+            0x03 => Some("SINGLE"),
+            0x81 => Some("ALL|ANYONECANPAY"),
+            0x82 => Some("NONE|ANYONECANPAY"),
+            0x83 => Some("SINGLE|ANYONECANPAY"),
+            _ => None,
+        };
+        if let Some(sighash) = sighash {
+            return format!(
+                "{}[{}]",
+                data[..data.len() - 1].to_lower_hex_string(),
+                sighash
+            );
+        }
+    }
+    data.to_lower_hex_string()
+}
+
+/// Strict DER encoding followed by a sighash byte (BIP66, `IsValidSignatureEncoding`).
+fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    if sig.len() < 9 || sig.len() > 73 {
+        return false;
+    }
+    if sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+    if sig[2] != 0x02 || len_r == 0 || sig[4] & 0x80 != 0 {
+        return false;
+    }
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 {
+        return false;
+    }
+    if sig[len_r + 4] != 0x02 || len_s == 0 || sig[len_r + 6] & 0x80 != 0 {
+        return false;
+    }
+    if len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0 {
+        return false;
+    }
+    true
+}
+
+fn serialize_hex<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&data.to_lower_hex_string())
+}
+
+fn deserialize_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    Vec::from_hex(&s).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::ScriptBuf;
+
+    fn script(hex: &str) -> ScriptBuf {
+        ScriptBuf::from_hex(hex).unwrap()
+    }
+
+    #[test]
+    fn test_p2pkh_asm() {
+        let spk = script("76a91412ab8dc588ca9d5787dde7eb29569da63c3a238c88ac");
+        assert_eq!(
+            script_to_asm(&spk, false),
+            "OP_DUP OP_HASH160 12ab8dc588ca9d5787dde7eb29569da63c3a238c OP_EQUALVERIFY OP_CHECKSIG"
+        );
+    }
+
+    #[test]
+    fn test_numbers_and_names() {
+        // OP_0 OP_1NEGATE OP_16 <0x81> <0xff00> <0x0001> OP_CLTV OP_CSV OP_NOP10 OP_CHECKSIGADD 0xbb 0xff
+        let s = script("004f60018102ff00020001b1b2b9babbff");
+        assert_eq!(
+            script_to_asm(&s, false),
+            "0 -1 16 -1 255 256 OP_CHECKLOCKTIMEVERIFY OP_CHECKSEQUENCEVERIFY OP_NOP10 \
+             OP_CHECKSIGADD OP_UNKNOWN OP_INVALIDOPCODE"
+        );
+        // truncated push
+        assert_eq!(script_to_asm(&script("51020a"), false), "1 [error]");
+        assert_eq!(
+            script_instructions(&script("51020a")),
+            vec![
+                ScriptInstruction::Op {
+                    opcode: 0x51,
+                    name: "1".to_string()
+                },
+                ScriptInstruction::Invalid
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sighash_decode() {
+        let sig = "3045022100d41ec8e8bcd5fc6d2fa1d2c92bc2cc2ed9c5b8aa2a1a0c8c0b7c8bd7d3ae0bb3\
+                   02207c1f3aa5e5f6a1c2f7d0bd7fd4bba6b3a0f0d0a2e3b9d7a8c0a0e58c9bff1c9f";
+        let s = script(&format!("48{}81", sig));
+        assert_eq!(
+            script_to_asm(&s, true),
+            format!("{}[ALL|ANYONECANPAY]", sig)
+        );
+        assert_eq!(script_to_asm(&s, false), format!("{}81", sig));
+        // undefined hashtype is left as is
+        let s = script(&format!("48{}04", sig));
+        assert_eq!(script_to_asm(&s, true), format!("{}04", sig));
+    }
+
+    #[test]
+    fn test_instructions() {
+        let spk = script("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+        let instructions = script_instructions(&spk);
+        assert_eq!(
+            instructions,
+            vec![
+                ScriptInstruction::Push { data: vec![] },
+                ScriptInstruction::Push {
+                    data: Vec::from_hex("751e76e8199196d454941c45d1b3a323f1433bd6").unwrap()
+                },
+            ]
+        );
+    }
+}
@@ -1,7 +1,12 @@
 //! Read transactions and blocks from blk.dat files.
 
 use crate::parser::error::{Error, Result};
+#[cfg(feature = "mmap")]
+use crate::parser::mmap::MmapPool;
 use crate::parser::reader::BlockchainRead;
+use crate::parser::undo::BlockUndo;
+#[cfg(feature = "mmap")]
+use crate::parser::xor::xor_in_place;
 use crate::parser::xor::{XorReader, XOR_MASK_LEN};
 use bitcoin::io::Cursor;
 use bitcoin::{Block, Transaction};
@@ -9,6 +14,8 @@ use std::collections::HashMap;
 use std::fs::{DirEntry, File};
 use std::io::{Seek, SeekFrom};
 use std::path::{Path, PathBuf};
+#[cfg(feature = "mmap")]
+use std::sync::Arc;
 
 // the size of a header is 80.
 const HEADER_SIZE: u64 = 80;
@@ -22,19 +29,32 @@ fn resolve_path(entry: &DirEntry) -> std::io::Result<PathBuf> {
     }
 }
 
+/// Extract index from a file name of the form `{prefix}{index}.dat`.
+fn parse_file_index(path: &Path, prefix: &str) -> Option<i32> {
+    let file_name = path.file_name().and_then(|f| f.to_str())?;
+    let s = file_name.strip_prefix(prefix)?;
+    let index = s.strip_suffix(".dat")?;
+    index.parse::<i32>().ok()
+}
+
 /// Extract index from block file name.
 ///
 /// For example, return `Some(0)` for `blk00000.dat`.
 fn parse_blk_index(path: impl AsRef<Path>) -> Option<i32> {
-    let file_name = path.as_ref().file_name().and_then(|f| f.to_str())?;
-    let s = file_name.strip_prefix("blk")?;
-    let blk_index = s.strip_suffix(".dat")?;
-    blk_index.parse::<i32>().ok()
+    parse_file_index(path.as_ref(), "blk")
+}
+
+/// Extract index from undo file name.
+///
+/// For example, return `Some(0)` for `rev00000.dat`.
+fn parse_rev_index(path: impl AsRef<Path>) -> Option<i32> {
+    parse_file_index(path.as_ref(), "rev")
 }
 
-/// Scan `blocks` folder to build an index of all blk files.
-fn scan_blocks_dir(blocks_dir: &Path) -> Result<HashMap<i32, PathBuf>> {
+/// Scan `blocks` folder to build an index of all blk and rev files.
+fn scan_blocks_dir(blocks_dir: &Path) -> Result<(HashMap<i32, PathBuf>, HashMap<i32, PathBuf>)> {
     let mut blk_files = HashMap::with_capacity(5000);
+    let mut rev_files = HashMap::with_capacity(5000);
     for entry in std::fs::read_dir(blocks_dir)? {
         let path = resolve_path(&entry?)?;
         if !path.is_file() {
@@ -43,13 +63,16 @@ fn scan_blocks_dir(blocks_dir: &Path) -> Result<HashMap<i32, PathBuf>> {
 
         if let Some(index) = parse_blk_index(path.as_path()) {
             blk_files.insert(index, path);
+        } else if let Some(index) = parse_rev_index(path.as_path()) {
+            rev_files.insert(index, path);
         }
     }
     blk_files.shrink_to_fit();
+    rev_files.shrink_to_fit();
     if blk_files.is_empty() {
         Err(Error::EmptyBlockFiles)
     } else {
-        Ok(blk_files)
+        Ok((blk_files, rev_files))
     }
 }
 
@@ -70,11 +93,15 @@ fn read_xor_mask<P: AsRef<Path>>(blocks_dir: P) -> std::io::Result<Option<[u8; X
     Ok(Some(buf))
 }
 
-/// An index of all blk files found.
+/// An index of all blk (and rev) files found.
 #[derive(Debug, Clone)]
 pub struct BlkFile {
     files: HashMap<i32, PathBuf>,
+    rev_files: HashMap<i32, PathBuf>,
     xor_mask: Option<[u8; XOR_MASK_LEN]>,
+    /// blk files are read from memory maps if set.
+    #[cfg(feature = "mmap")]
+    mmap_pool: Option<Arc<MmapPool>>,
 }
 
 impl BlkFile {
@@ -85,12 +112,24 @@ impl BlkFile {
     /// `path`: Path of `bitcoin_core_data_dir/blocks`.
     pub(crate) fn new(path: &Path) -> Result<BlkFile> {
         let xor_mask = read_xor_mask(path)?;
+        let (files, rev_files) = scan_blocks_dir(path)?;
         Ok(Self {
-            files: scan_blocks_dir(path)?,
+            files,
+            rev_files,
             xor_mask,
+            #[cfg(feature = "mmap")]
+            mmap_pool: None,
         })
     }
 
+    /// Read blk files from memory maps, keeping at most
+    /// `max_mapped_files` of the most recently used ones mapped.
+    #[cfg(feature = "mmap")]
+    pub(crate) fn with_mmap_pool(mut self, max_mapped_files: usize) -> Self {
+        self.mmap_pool = Some(Arc::new(MmapPool::new(max_mapped_files)));
+        self
+    }
+
     /// Read a Block from blk file.
     #[inline]
     pub(crate) fn read_raw_block(&self, n_file: i32, offset: u32) -> Result<Vec<u8>> {
@@ -99,6 +138,13 @@ impl BlkFile {
             .get(&n_file)
             .ok_or(Error::BlockFileNotFound(n_file))?;
 
+        #[cfg(feature = "mmap")]
+        if let Some(pool) = &self.mmap_pool {
+            return read_mapped(pool, n_file, blk_path, |map| {
+                self.copy_out(map, offset as u64)
+            });
+        }
+
         let mut r = XorReader::new(File::open(blk_path)?, self.xor_mask);
         r.seek(SeekFrom::Start(offset as u64 - 4))?;
         let block_size = r.read_u32()?;
@@ -124,14 +170,83 @@ impl BlkFile {
             .get(&n_file)
             .ok_or(Error::BlockFileNotFound(n_file))?;
 
-        let mut r = XorReader::new(File::open(blk_path)?, self.xor_mask);
+        let pos = n_pos as u64 + n_tx_offset as u64 + HEADER_SIZE;
 
-        r.seek(SeekFrom::Start(
-            n_pos as u64 + n_tx_offset as u64 + HEADER_SIZE,
-        ))?;
+        #[cfg(feature = "mmap")]
+        if let Some(pool) = &self.mmap_pool {
+            return read_mapped(pool, n_file, blk_path, |map| {
+                let mut r = XorReader::new(std::io::Cursor::new(map), self.xor_mask);
+                r.seek(SeekFrom::Start(pos))?;
+                r.read_transaction()
+            });
+        }
 
+        let mut r = XorReader::new(File::open(blk_path)?, self.xor_mask);
+        r.seek(SeekFrom::Start(pos))?;
         r.read_transaction()
     }
+
+    /// Copy the block at `offset` out of a mapped blk file, removing the XOR mask.
+    #[cfg(feature = "mmap")]
+    fn copy_out(&self, map: &[u8], offset: u64) -> Result<Vec<u8>> {
+        let eof = || Error::Io(std::io::ErrorKind::UnexpectedEof.into());
+        let size_pos = offset.checked_sub(4).ok_or_else(eof)?;
+        let start = offset as usize;
+        let mut size = [0u8; 4];
+        size.copy_from_slice(map.get(size_pos as usize..start).ok_or_else(eof)?);
+        if let Some(mask) = &self.xor_mask {
+            xor_in_place(&mut size, mask, size_pos);
+        }
+        let size = u32::from_le_bytes(size) as usize;
+        let mut block = map.get(start..start + size).ok_or_else(eof)?.to_vec();
+        if let Some(mask) = &self.xor_mask {
+            xor_in_place(&mut block, mask, offset);
+        }
+        Ok(block)
+    }
+
+    /// Read the undo data of a block from rev file.
+    pub(crate) fn read_undo(&self, n_file: i32, offset: u32) -> Result<BlockUndo> {
+        let rev_path = self
+            .rev_files
+            .get(&n_file)
+            .ok_or(Error::UndoFileNotFound(n_file))?;
+
+        let mut r = XorReader::new(File::open(rev_path)?, self.xor_mask);
+        r.seek(SeekFrom::Start(offset as u64 - 4))?;
+        let undo_size = r.read_u32()?;
+        let undo = r.read_vec_u8(undo_size)?;
+
+        BlockUndo::decode(&mut Cursor::new(undo))
+    }
+}
+
+/// Read blk file `n_file` from its mapping in `pool`.
+///
+/// The file still being written by Bitcoin Core may have grown since it was
+/// mapped: reading past the end of the mapping maps the file again, once.
+#[cfg(feature = "mmap")]
+fn read_mapped<T, F>(pool: &MmapPool, n_file: i32, path: &Path, read: F) -> Result<T>
+where
+    F: Fn(&[u8]) -> Result<T>,
+{
+    match read(&pool.get(n_file, path)?) {
+        Err(e) if is_eof(&e) => read(&pool.remap(n_file, path)?),
+        result => result,
+    }
+}
+
+#[cfg(feature = "mmap")]
+fn is_eof(err: &Error) -> bool {
+    use bitcoin::consensus::encode;
+    use bitcoin::io::ErrorKind;
+    match err {
+        Error::Io(e) => e.kind() == std::io::ErrorKind::UnexpectedEof,
+        Error::BitcoinIo(e) | Error::Encode(encode::Error::Io(e)) => {
+            e.kind() == ErrorKind::UnexpectedEof
+        }
+        _ => false,
+    }
 }
 
 #[cfg(test)]
@@ -146,5 +261,69 @@ mod tests {
         assert_eq!(13412451, parse_blk_index("blk13412451.dat").unwrap());
         assert!(parse_blk_index("blkindex.dat").is_none());
         assert!(parse_blk_index("invalid.dat").is_none());
+        assert!(parse_blk_index("rev00000.dat").is_none());
+    }
+
+    #[test]
+    fn test_parse_rev_index() {
+        assert_eq!(0, parse_rev_index("rev00000.dat").unwrap());
+        assert_eq!(3164, parse_rev_index("rev03164.dat").unwrap());
+        assert!(parse_rev_index("blk00000.dat").is_none());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap_parity() {
+        use crate::parser::xor::xor_in_place;
+        use bitcoin::blockdata::constants::genesis_block;
+        use bitcoin::consensus::serialize;
+        use bitcoin::Network;
+
+        let block = genesis_block(Network::Bitcoin);
+        let raw = serialize(&block);
+        let mask = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
+        // two blocks, the second one not aligned on the mask.
+        let mut blk = Vec::new();
+        for padding in [0, 3] {
+            blk.extend(vec![0; padding]);
+            blk.extend([0xF9, 0xBE, 0xB4, 0xD9]);
+            blk.extend((raw.len() as u32).to_le_bytes());
+            blk.extend(&raw);
+        }
+        let second = (raw.len() + 8 + 3 + 8) as u32;
+        xor_in_place(&mut blk, &mask, 0);
+
+        let dir = std::env::temp_dir().join(format!("blk-mmap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("blk00000.dat"), &blk).unwrap();
+        std::fs::write(dir.join("xor.dat"), mask).unwrap();
+
+        let plain = BlkFile::new(&dir).unwrap();
+        let mapped = BlkFile::new(&dir).unwrap().with_mmap_pool(1);
+        for offset in [8, second] {
+            assert_eq!(plain.read_raw_block(0, offset).unwrap(), raw);
+            assert_eq!(mapped.read_raw_block(0, offset).unwrap(), raw);
+            assert_eq!(
+                mapped.read_transaction(0, offset, 1).unwrap(),
+                block.txdata[0]
+            );
+            assert_eq!(
+                plain.read_transaction(0, offset, 1).unwrap(),
+                block.txdata[0]
+            );
+        }
+        assert!(mapped.read_raw_block(0, blk.len() as u32 + 4).is_err());
+        assert!(mapped.read_raw_block(0, 2).is_err());
+
+        // a block appended after the file was mapped.
+        let third = blk.len() as u64 + 8;
+        let mut appended = [0xF9, 0xBE, 0xB4, 0xD9].to_vec();
+        appended.extend((raw.len() as u32).to_le_bytes());
+        appended.extend(&raw);
+        xor_in_place(&mut appended, &mask, blk.len() as u64);
+        blk.extend(appended);
+        std::fs::write(dir.join("blk00000.dat"), &blk).unwrap();
+        assert_eq!(mapped.read_raw_block(0, third as u32).unwrap(), raw);
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
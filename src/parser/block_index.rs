@@ -4,6 +4,7 @@ use crate::parser::error::Result;
 use crate::parser::reader::BlockchainRead;
 use crate::BlockHeader;
 use bitcoin::io::Cursor;
+use bitcoin::pow::Work;
 use bitcoin::BlockHash;
 use leveldb::database::iterator::LevelDBIterator;
 use leveldb::database::Database;
@@ -27,6 +28,8 @@ const BLOCK_VALID_MASK: u32 = BLOCK_VALID_HEADER
     | BLOCK_VALID_SCRIPTS;
 const BLOCK_HAVE_DATA: u32 = 8;
 const BLOCK_HAVE_UNDO: u32 = 16;
+/// Number of blocks used to compute the median time past.
+const MEDIAN_TIME_SPAN: usize = 11;
 
 /// BLOCK_INDEX RECORD as defined in Bitcoin Core.
 #[derive(Serialize, Clone)]
@@ -108,24 +111,82 @@ pub struct BlockIndex {
     pub records: Box<[BlockIndexRecord]>,
     /// Map from block hash to block height.
     pub hash_to_height: HashMap<BlockHash, i32>,
+    /// Total work of the chain up to and including each block.
+    chain_work: Box<[Work]>,
 }
 
 impl BlockIndex {
     /// Build a collections of block index.
     pub(crate) fn new(p: impl AsRef<Path>) -> Result<BlockIndex> {
         let records = load_block_index(p.as_ref())?.into_boxed_slice();
+        Ok(Self::from_records(records))
+    }
 
+    pub(crate) fn from_records(records: Box<[BlockIndexRecord]>) -> BlockIndex {
         // build a reverse index to lookup block height of a particular block hash.
         let mut hash_to_height = HashMap::with_capacity(records.len());
         for b in records.iter() {
             hash_to_height.insert(b.block_header.block_hash(), b.n_height);
         }
         hash_to_height.shrink_to_fit();
-        Ok(BlockIndex {
+
+        let mut total: Option<Work> = None;
+        let chain_work = records
+            .iter()
+            .map(|r| {
+                let work = r.block_header.work();
+                let sum = total.map_or(work, |t| t + work);
+                total = Some(sum);
+                sum
+            })
+            .collect();
+        BlockIndex {
             records,
             hash_to_height,
-        })
+            chain_work,
+        }
+    }
+
+    /// Median of the timestamps of the last 11 blocks ending at `height`
+    /// (`GetMedianTimePast` in Bitcoin Core).
+    pub fn median_time_past(&self, height: usize) -> Option<u32> {
+        let first = (height + 1).saturating_sub(MEDIAN_TIME_SPAN);
+        let mut times = self
+            .records
+            .get(first..=height)?
+            .iter()
+            .map(|r| r.block_header.time)
+            .collect::<Vec<u32>>();
+        times.sort_unstable();
+        Some(times[times.len() / 2])
+    }
+
+    /// Total work of the chain up to and including `height` (`nChainWork`).
+    pub fn chain_work(&self, height: usize) -> Option<Work> {
+        self.chain_work.get(height).copied()
+    }
+
+    /// Difficulty of the block at `height`, as shown by `getblockheader`.
+    pub fn difficulty(&self, height: usize) -> Option<f64> {
+        let record = self.records.get(height)?;
+        Some(difficulty(record.block_header.bits.to_consensus()))
+    }
+}
+
+/// Difficulty of `bits` relative to the minimum difficulty,
+/// computed the same way as `GetDifficulty` in Bitcoin Core.
+pub(crate) fn difficulty(bits: u32) -> f64 {
+    let mut shift = (bits >> 24) & 0xff;
+    let mut diff = 0x0000ffff as f64 / (bits & 0x00ffffff) as f64;
+    while shift < 29 {
+        diff *= 256.0;
+        shift += 1;
+    }
+    while shift > 29 {
+        diff /= 256.0;
+        shift -= 1;
     }
+    diff
 }
 
 #[inline]
@@ -10,6 +10,7 @@
 //!   omitting less critical data like previous block hash, Merkle root, and
 //!   input witness for efficient processing.
 
+use super::full_block::block_sizes;
 use crate::parser::script::evaluate_script;
 use bitcoin::{Address, Block, BlockHash, Transaction, TxIn, TxOut, Txid};
 use serde::{Deserialize, Serialize};
@@ -42,7 +43,7 @@ impl From<Block> for CompactBlock {
     fn from(block: Block) -> Self {
         let block_hash = block.header.block_hash();
         Self {
-            header: CompactBlockHeader::new(block.header, block_hash),
+            header: CompactBlockHeader::new(&block, block_hash),
             txdata: block.txdata.into_iter().map(|x| x.into()).collect(),
         }
     }
@@ -53,6 +54,9 @@ impl From<Block> for CompactBlock {
 /// A `CompactBlockHeader` includes:
 /// - `block_hash`: The hash of the block.
 /// - `time`: The timestamp of the block.
+/// - `size`, `stripped_size` and `weight`: The serialized sizes of the block.
+/// - `total_fees`: The sum of transaction fees, for connected blocks,
+///   `None` if the fee of a transaction is unknown.
 ///
 /// It omits the following fields:
 /// - `nonce`
@@ -63,13 +67,23 @@ impl From<Block> for CompactBlock {
 pub struct CompactBlockHeader {
     pub block_hash: BlockHash,
     pub time: u32,
+    pub size: u64,
+    pub stripped_size: u64,
+    pub weight: u64,
+    /// Only known for connected blocks.
+    pub total_fees: Option<u64>,
 }
 
 impl CompactBlockHeader {
-    pub fn new(blk: crate::BlockHeader, block_hash: BlockHash) -> Self {
+    pub fn new(blk: &Block, block_hash: BlockHash) -> Self {
+        let (size, stripped_size, weight) = block_sizes(blk);
         Self {
             block_hash,
-            time: blk.time,
+            time: blk.header.time,
+            size,
+            stripped_size,
+            weight,
+            total_fees: None,
         }
     }
 }
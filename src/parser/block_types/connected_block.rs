@@ -4,9 +4,17 @@ use super::compact_block::{CompactBlockHeader, CompactTxOut};
 use super::full_block::{FullBlockHeader, FullTxOut};
 use crate::parser::blk_file::BlkFile;
 use crate::parser::error::{Error, Result};
+use crate::parser::input::{InputType, RevealedScripts};
+use crate::parser::policy::{
+    check_ephemeral_dust, check_standard_input, check_standard_tx, first_reason, input_sigop_cost,
+    legacy_sigop_count, NonStandardReason,
+};
 use crate::parser::tx_index::TxDB;
-use crate::{BlockHeader, BlockIndex};
-use bitcoin::{Block, BlockHash, Transaction, TxIn, TxOut, Txid};
+use crate::parser::undo::Coin;
+use crate::BlockIndex;
+use bitcoin::{
+    Block, BlockHash, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
 use log::warn;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -22,12 +30,14 @@ pub trait ConnectedBlock {
     /// Associated output type.
     type Tx: ConnectedTx + Send;
 
-    /// Construct a ConnectedBlock from parts of a block.
+    /// Construct a ConnectedBlock from a block, without adding its transactions.
     ///
     /// Used in `connected_block_iter.rs`.
-    fn from(block_header: BlockHeader, block_hash: BlockHash) -> Self;
+    fn from(block: &Block, block_hash: BlockHash) -> Self;
 
-    /// Add a new transaction in this block.
+    /// Add a new transaction in this block, after all its inputs are added.
+    ///
+    /// This computes the fee of the transaction.
     ///
     /// Used in `connected_block_iter.rs`.
     fn add_tx(&mut self, tx: Self::Tx);
@@ -58,10 +68,11 @@ pub trait ConnectedTx {
     /// This function is used in `connected_block_iter.rs`.
     fn from(tx: &Transaction) -> Self;
 
-    /// Add a input to this ConnectedTx.
+    /// Add a input to this ConnectedTx, `prevout` being the output spent by `tx_in`,
+    /// created in a block with timestamp `prevout_time`.
     ///
     /// This function is used in `connected_block_iter.rs`.
-    fn add_input(&mut self, input: Self::TxOut);
+    fn add_input(&mut self, tx_in: &TxIn, prevout: Coin<Self::TxOut>, prevout_time: u32);
 
     /// Build ConnectedTx from Tx,
     /// and attach inputs to this ConnectedTx using tx-index.
@@ -89,13 +100,76 @@ pub struct FullConnectedBlock {
     pub txdata: Vec<FullConnectedTransaction>,
 }
 
+/// Full format of connected input: the input, the output it spends,
+/// and the block that created this output.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct FullConnectedTxIn {
+    pub previous_output: OutPoint,
+    pub script_sig: ScriptBuf,
+    pub sequence: u32,
+    pub witness: Witness,
+    /// The output spent by this input.
+    pub prevout: FullTxOut,
+    /// Height of the block that created `prevout`.
+    pub prevout_height: u32,
+    /// Timestamp of the block that created `prevout`.
+    pub prevout_time: u32,
+    /// Whether `prevout` was created by a coinbase transaction.
+    pub prevout_is_coinbase: bool,
+    /// How this input is spent, classified using `prevout`.
+    pub input_type: InputType,
+    /// Scripts revealed by this input.
+    pub revealed_scripts: RevealedScripts,
+}
+
+impl FullConnectedTxIn {
+    /// Whether this input signals replaceability (BIP125).
+    pub fn is_rbf(&self) -> bool {
+        Sequence(self.sequence).is_rbf()
+    }
+}
+
+/// Simple format of connected input, without scripts.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct CompactConnectedTxIn {
+    pub txid: Txid,
+    pub vout: u32,
+    pub sequence: u32,
+    /// The output spent by this input.
+    pub prevout: CompactTxOut,
+    /// Height of the block that created `prevout`.
+    pub prevout_height: u32,
+    /// Timestamp of the block that created `prevout`.
+    pub prevout_time: u32,
+    /// Whether `prevout` was created by a coinbase transaction.
+    pub prevout_is_coinbase: bool,
+}
+
+impl CompactConnectedTxIn {
+    /// Whether this input signals replaceability (BIP125).
+    pub fn is_rbf(&self) -> bool {
+        Sequence(self.sequence).is_rbf()
+    }
+}
+
 /// Simple format of connected transaction.
 /// See fields for details of this struct.
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 pub struct CompactConnectedTransaction {
     pub txid: Txid,
-    pub input: Vec<CompactTxOut>,
+    pub input: Vec<CompactConnectedTxIn>,
     pub output: Vec<CompactTxOut>,
+    /// Serialized size in bytes, including witness data.
+    pub size: u64,
+    /// Transaction weight as defined in BIP141.
+    pub weight: u64,
+    /// Virtual size in vbytes.
+    pub vsize: u64,
+    /// Whether this is the coinbase transaction of its block.
+    pub is_coinbase: bool,
+    /// Fee in satoshi, 0 for coinbase transactions,
+    /// `None` if the outputs are worth more than the connected inputs.
+    pub fee: Option<u64>,
 }
 
 /// Full format of connected transaction.
@@ -105,25 +179,121 @@ pub struct FullConnectedTransaction {
     pub version: i32,
     pub lock_time: u32,
     pub txid: Txid,
-    pub input: Vec<FullTxOut>,
+    pub input: Vec<FullConnectedTxIn>,
     pub output: Vec<FullTxOut>,
+    /// Serialized size in bytes, including witness data.
+    pub size: u64,
+    /// Transaction weight as defined in BIP141.
+    pub weight: u64,
+    /// Virtual size in vbytes.
+    pub vsize: u64,
+    /// Whether this is the coinbase transaction of its block.
+    pub is_coinbase: bool,
+    /// Fee in satoshi, 0 for coinbase transactions,
+    /// `None` if the outputs are worth more than the connected inputs.
+    pub fee: Option<u64>,
+    /// Sigops in scriptSigs and output scripts, not looking at spent outputs.
+    pub legacy_sigop_count: u32,
+    /// Sigop cost as defined in BIP141.
+    pub sigop_cost: u64,
+    /// Why Bitcoin Core would not relay this transaction, `None` if standard.
+    /// Always `None` for coinbase transactions.
+    pub non_standard: Option<NonStandardReason>,
+}
+
+impl FullConnectedTransaction {
+    /// Fee rate in sat/vB.
+    /// `None` if the fee is unknown.
+    pub fn fee_rate(&self) -> Option<f64> {
+        self.fee.map(|fee| fee as f64 / self.vsize as f64)
+    }
+
+    /// Whether Bitcoin Core would relay this transaction.
+    pub fn is_standard(&self) -> bool {
+        self.non_standard.is_none()
+    }
+
+    fn update_fee(&mut self) {
+        self.fee = fee(
+            self.is_coinbase,
+            self.input.iter().map(|i| i.prevout.value),
+            self.output.iter().map(|o| o.value),
+        );
+        if let (None, Some(fee)) = (&self.non_standard, self.fee) {
+            let outputs = self
+                .output
+                .iter()
+                .map(|o| (o.value, o.script_pubkey.as_script()));
+            self.non_standard = check_ephemeral_dust(outputs, fee).err();
+        }
+    }
+}
+
+impl CompactConnectedTransaction {
+    /// Fee rate in sat/vB.
+    /// `None` if the fee is unknown.
+    pub fn fee_rate(&self) -> Option<f64> {
+        self.fee.map(|fee| fee as f64 / self.vsize as f64)
+    }
+
+    fn update_fee(&mut self) {
+        self.fee = fee(
+            self.is_coinbase,
+            self.input.iter().map(|i| i.prevout.value),
+            self.output.iter().map(|o| o.value),
+        );
+    }
 }
 
 impl ConnectedTx for FullConnectedTransaction {
     type TxOut = FullTxOut;
 
     fn from(tx: &Transaction) -> Self {
+        let (size, weight, vsize) = tx_sizes(tx);
+        let legacy_sigops = legacy_sigop_count(tx);
         Self {
             version: tx.version.0,
             lock_time: tx.lock_time.to_consensus_u32(),
             txid: tx.compute_txid(),
             input: Vec::new(),
             output: tx.output.clone().into_iter().map(|x| x.into()).collect(),
+            size,
+            weight,
+            vsize,
+            is_coinbase: tx.is_coinbase(),
+            fee: None,
+            legacy_sigop_count: legacy_sigops,
+            sigop_cost: legacy_sigops as u64 * 4,
+            non_standard: if tx.is_coinbase() {
+                None
+            } else {
+                check_standard_tx(tx).err()
+            },
         }
     }
 
-    fn add_input(&mut self, input: Self::TxOut) {
-        self.input.push(input);
+    fn add_input(&mut self, tx_in: &TxIn, prevout: Coin<Self::TxOut>, prevout_time: u32) {
+        let script_pubkey = &prevout.out.script_pubkey;
+        self.sigop_cost += input_sigop_cost(tx_in, script_pubkey);
+        let reason = check_standard_input(tx_in, script_pubkey).err();
+        self.non_standard = match (self.non_standard, reason) {
+            (Some(a), Some(b)) => first_reason(Err(a), Err(b)).err(),
+            (a, b) => a.or(b),
+        };
+        let input_type = InputType::classify(tx_in, Some(script_pubkey));
+        let revealed_scripts = RevealedScripts::extract(tx_in, &input_type);
+        self.input.push(FullConnectedTxIn {
+            previous_output: tx_in.previous_output,
+            script_sig: tx_in.script_sig.clone(),
+            sequence: tx_in.sequence.to_consensus_u32(),
+            witness: tx_in.witness.clone(),
+            prevout: prevout.out,
+            prevout_height: prevout.height,
+            prevout_time,
+            prevout_is_coinbase: prevout.is_coinbase,
+            input_type,
+            revealed_scripts,
+        });
     }
 
     fn connect(
@@ -133,16 +303,14 @@ impl ConnectedTx for FullConnectedTransaction {
         blk_file: &BlkFile,
     ) -> Result<Self> {
         let is_coinbase = tx.is_coinbase();
-        Ok(Self {
-            version: tx.version.0,
-            lock_time: tx.lock_time.to_consensus_u32(),
-            txid: tx.compute_txid(),
-            input: connect_tx_inputs(&tx.input, is_coinbase, tx_db, blk_index, blk_file)?
-                .into_iter()
-                .map(Into::into)
-                .collect(),
-            output: tx.output.into_iter().map(Into::into).collect(),
-        })
+        let outputs = connect_tx_inputs(&tx.input, is_coinbase, tx_db, blk_index, blk_file)?;
+        let mut connected = <Self as ConnectedTx>::from(&tx);
+        for (tx_in, coin) in tx.input.iter().zip(outputs) {
+            let time = block_time(blk_index, coin.height)?;
+            connected.add_input(tx_in, coin.map(Into::into), time);
+        }
+        connected.update_fee();
+        Ok(connected)
     }
 }
 
@@ -150,15 +318,29 @@ impl ConnectedTx for CompactConnectedTransaction {
     type TxOut = CompactTxOut;
 
     fn from(tx: &Transaction) -> Self {
+        let (size, weight, vsize) = tx_sizes(tx);
         Self {
             txid: tx.compute_txid(),
             input: Vec::new(),
             output: tx.output.clone().into_iter().map(Into::into).collect(),
+            size,
+            weight,
+            vsize,
+            is_coinbase: tx.is_coinbase(),
+            fee: None,
         }
     }
 
-    fn add_input(&mut self, input: Self::TxOut) {
-        self.input.push(input);
+    fn add_input(&mut self, tx_in: &TxIn, prevout: Coin<Self::TxOut>, prevout_time: u32) {
+        self.input.push(CompactConnectedTxIn {
+            txid: tx_in.previous_output.txid,
+            vout: tx_in.previous_output.vout,
+            sequence: tx_in.sequence.to_consensus_u32(),
+            prevout: prevout.out,
+            prevout_height: prevout.height,
+            prevout_is_coinbase: prevout.is_coinbase,
+            prevout_time,
+        });
     }
 
     fn connect(
@@ -168,28 +350,36 @@ impl ConnectedTx for CompactConnectedTransaction {
         blk_file: &BlkFile,
     ) -> Result<Self> {
         let is_coinbase = tx.is_coinbase();
-        Ok(Self {
-            txid: tx.compute_txid(),
-            input: connect_tx_inputs(&tx.input, is_coinbase, tx_db, blk_index, blk_file)?
-                .into_iter()
-                .map(Into::into)
-                .collect(),
-            output: tx.output.into_iter().map(Into::into).collect(),
-        })
+        let outputs = connect_tx_inputs(&tx.input, is_coinbase, tx_db, blk_index, blk_file)?;
+        let mut connected = <Self as ConnectedTx>::from(&tx);
+        for (tx_in, coin) in tx.input.iter().zip(outputs) {
+            let time = block_time(blk_index, coin.height)?;
+            connected.add_input(tx_in, coin.map(Into::into), time);
+        }
+        connected.update_fee();
+        Ok(connected)
     }
 }
 
 impl ConnectedBlock for FullConnectedBlock {
     type Tx = FullConnectedTransaction;
 
-    fn from(block_header: BlockHeader, block_hash: BlockHash) -> Self {
+    fn from(block: &Block, block_hash: BlockHash) -> Self {
+        let mut header = FullBlockHeader::parse(block, block_hash);
+        header.total_fees = Some(0);
         Self {
-            header: FullBlockHeader::parse(block_header, block_hash),
-            txdata: Vec::new(),
+            header,
+            txdata: Vec::with_capacity(block.txdata.len()),
         }
     }
 
-    fn add_tx(&mut self, tx: Self::Tx) {
+    fn add_tx(&mut self, mut tx: Self::Tx) {
+        tx.update_fee();
+        self.header.total_fees = self
+            .header
+            .total_fees
+            .zip(tx.fee)
+            .map(|(f, tx_fee)| f + tx_fee);
         self.txdata.push(tx);
     }
 
@@ -200,24 +390,33 @@ impl ConnectedBlock for FullConnectedBlock {
         blk_file: &BlkFile,
     ) -> Result<Self> {
         let block_hash = block.header.block_hash();
-        Ok(Self {
-            header: FullBlockHeader::parse(block.header, block_hash),
-            txdata: connect_block_inputs(block.txdata, tx_db, blk_index, blk_file)?,
-        })
+        let mut connected = <Self as ConnectedBlock>::from(&block, block_hash);
+        for tx in connect_block_inputs(block.txdata, tx_db, blk_index, blk_file)? {
+            connected.add_tx(tx);
+        }
+        Ok(connected)
     }
 }
 
 impl ConnectedBlock for CompactConnectedBlock {
     type Tx = CompactConnectedTransaction;
 
-    fn from(block_header: BlockHeader, block_hash: BlockHash) -> Self {
+    fn from(block: &Block, block_hash: BlockHash) -> Self {
+        let mut header = CompactBlockHeader::new(block, block_hash);
+        header.total_fees = Some(0);
         Self {
-            header: CompactBlockHeader::new(block_header, block_hash),
-            txdata: Vec::new(),
+            header,
+            txdata: Vec::with_capacity(block.txdata.len()),
         }
     }
 
-    fn add_tx(&mut self, tx: Self::Tx) {
+    fn add_tx(&mut self, mut tx: Self::Tx) {
+        tx.update_fee();
+        self.header.total_fees = self
+            .header
+            .total_fees
+            .zip(tx.fee)
+            .map(|(f, tx_fee)| f + tx_fee);
         self.txdata.push(tx);
     }
 
@@ -228,10 +427,11 @@ impl ConnectedBlock for CompactConnectedBlock {
         blk_file: &BlkFile,
     ) -> Result<Self> {
         let block_hash = block.header.block_hash();
-        Ok(Self {
-            header: CompactBlockHeader::new(block.header, block_hash),
-            txdata: connect_block_inputs(block.txdata, tx_db, blk_index, blk_file)?,
-        })
+        let mut connected = <Self as ConnectedBlock>::from(&block, block_hash);
+        for tx in connect_block_inputs(block.txdata, tx_db, blk_index, blk_file)? {
+            connected.add_tx(tx);
+        }
+        Ok(connected)
     }
 }
 
@@ -250,7 +450,7 @@ where
     let all_tx_in: Vec<_> = transactions.iter().flat_map(|tx| tx.input.iter()).collect();
 
     // connect transactions inputs in parallel
-    let mut connected_outputs: VecDeque<Option<TxOut>> = all_tx_in
+    let mut connected_outputs: VecDeque<Option<Coin>> = all_tx_in
         .par_iter()
         .map(|x| connect_input(x, tx_db, blk_index, blk_file))
         .collect();
@@ -263,11 +463,11 @@ where
 
         let mut outputs = Vec::with_capacity(outpoints_count);
 
-        for _ in &tx.input {
+        for tx_in in &tx.input {
             let connected_out = connected_outputs.pop_front().unwrap();
             if let Some(out) = connected_out {
                 // also do not push the null input connected to coinbase transaction
-                outputs.push(out);
+                outputs.push((tx_in, out));
             }
         }
 
@@ -279,17 +479,57 @@ where
             });
         }
 
-        let mut tx = Tx::from(&tx);
-        for output in outputs {
-            tx.add_input(output.into());
+        let mut connected = Tx::from(&tx);
+        for (tx_in, coin) in outputs {
+            let time = block_time(blk_index, coin.height)?;
+            connected.add_input(tx_in, coin.map(Into::into), time);
         }
 
-        connected_tx.push(tx);
+        connected_tx.push(connected);
     }
 
     Ok(connected_tx)
 }
 
+/// Compute `(size, weight, vsize)` of a transaction.
+#[inline]
+fn tx_sizes(tx: &Transaction) -> (u64, u64, u64) {
+    let weight = tx.weight();
+    (
+        tx.total_size() as u64,
+        weight.to_wu(),
+        weight.to_vbytes_ceil(),
+    )
+}
+
+/// Input value minus output value, 0 for coinbase transactions.
+///
+/// `None` if the outputs are worth more than the inputs,
+/// which means the inputs were not connected correctly.
+#[inline]
+fn fee(
+    is_coinbase: bool,
+    input: impl Iterator<Item = u64>,
+    output: impl Iterator<Item = u64>,
+) -> Option<u64> {
+    if is_coinbase {
+        return Some(0);
+    }
+    let input_value: u64 = input.sum();
+    let output_value: u64 = output.sum();
+    input_value.checked_sub(output_value)
+}
+
+/// Timestamp of the block at `height`.
+#[inline]
+pub(crate) fn block_time(blk_index: &BlockIndex, height: u32) -> Result<u32> {
+    blk_index
+        .records
+        .get(height as usize)
+        .map(|r| r.block_header.time)
+        .ok_or(Error::BlockIndexRecordNotFound(height as usize))
+}
+
 /// This function converts multiple Inputs of a single transaction to Outputs in parallel.
 #[inline]
 fn connect_tx_inputs(
@@ -298,8 +538,8 @@ fn connect_tx_inputs(
     tx_db: &TxDB,
     blk_index: &BlockIndex,
     blk_file: &BlkFile,
-) -> Result<Vec<TxOut>> {
-    let connected_outputs: Vec<TxOut> = tx_in
+) -> Result<Vec<Coin>> {
+    let connected_outputs: Vec<Coin> = tx_in
         .par_iter()
         .filter_map(|x| connect_input(x, tx_db, blk_index, blk_file))
         .collect();
@@ -318,16 +558,17 @@ fn connect_tx_inputs(
     }
 }
 
-/// This function connect a single TxIn to outputs. It converts:
+/// This function connect a single TxIn to the output it spends,
+/// with the height of the block containing it. It converts:
 /// - read failure to `None`
 /// - coinbase transaction output to `None`
 #[inline]
-fn connect_input(
+pub(crate) fn connect_input(
     tx_in: &TxIn,
     tx_db: &TxDB,
     blk_index: &BlockIndex,
     blk_file: &BlkFile,
-) -> Option<TxOut> {
+) -> Option<Coin> {
     // skip coinbase transaction
     if is_coin_base(tx_in) {
         return None;
@@ -342,7 +583,11 @@ fn connect_input(
         return match blk_file.read_block(pos.n_file, pos.n_data_pos) {
             Ok(mut blk) => {
                 let mut tx = blk.txdata.swap_remove(0);
-                Some(tx.output.swap_remove(0))
+                Some(Coin {
+                    out: tx.output.swap_remove(0),
+                    height: 0,
+                    is_coinbase: true,
+                })
             }
             Err(_) => None,
         };
@@ -358,8 +603,15 @@ fn connect_input(
             if n >= len as u32 {
                 warn!("outpoint {outpoint} exceeds range");
                 None
+            } else if let Some(height) = tx_db.get_position_height(&tx_pos) {
+                Some(Coin {
+                    is_coinbase: tx.is_coinbase(),
+                    out: tx.output.swap_remove(n as usize),
+                    height: height as u32,
+                })
             } else {
-                Some(tx.output.swap_remove(n as usize))
+                warn!("cannot find the block of {outpoint}");
+                None
             }
         } else {
             warn!("fail to read transaction for {outpoint}");
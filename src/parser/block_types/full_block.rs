@@ -7,12 +7,16 @@
 //! Key differences from the base [`bitcoin::Block`]:
 //! - `FullBlock` includes computed block hash, transaction IDs, output addresses, and script types.
 //! - `FullTransaction` contains precomputed transaction ID, output addresses, and script types.
+//! - `FullTxIn` includes the precomputed input type of each input.
 //! - `FullTxOut` includes precomputed script types and addresses for each output.
 
 use crate::api::Block;
+use crate::parser::asm::{script_instructions, script_to_asm, ScriptInstruction};
+use crate::parser::input::{InputType, RevealedScripts};
 use crate::parser::script::{evaluate_script, ScriptType};
+use bitcoin::consensus::encode::VarInt;
 use bitcoin::hash_types::TxMerkleNode;
-use bitcoin::{Address, BlockHash, Transaction, TxOut, Txid};
+use bitcoin::{Address, BlockHash, OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Txid, Witness};
 use serde::{Deserialize, Serialize};
 
 /// A Bitcoin block with additional metadata.
@@ -33,13 +37,14 @@ impl From<Block> for FullBlock {
     fn from(block: bitcoin::Block) -> Self {
         let block_hash = block.header.block_hash();
         Self {
-            header: FullBlockHeader::parse(block.header, block_hash),
+            header: FullBlockHeader::parse(&block, block_hash),
             txdata: block.txdata.into_iter().map(|x| x.into()).collect(),
         }
     }
 }
 
-/// Full header of a Bitcoin block, with added `block_hash` compared to the base [`crate::BlockHeader`].
+/// Full header of a Bitcoin block, with added `block_hash` and block size
+/// information compared to the base [`crate::BlockHeader`].
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 pub struct FullBlockHeader {
     pub version: i32,
@@ -50,11 +55,22 @@ pub struct FullBlockHeader {
     pub nonce: u32,
     /// Precomputed.
     pub block_hash: BlockHash,
+    /// Serialized size of the block in bytes, including witness data.
+    pub size: u64,
+    /// Serialized size of the block in bytes, excluding witness data.
+    pub stripped_size: u64,
+    /// Block weight as defined in BIP141.
+    pub weight: u64,
+    /// Sum of transaction fees in satoshi, only known for connected blocks
+    /// whose transaction fees are all known.
+    pub total_fees: Option<u64>,
 }
 
 impl FullBlockHeader {
-    /// Creates a `FullBlockHeader` from a base `BlockHeader` and the computed `block_hash`.
-    pub fn parse(b: crate::BlockHeader, block_hash: BlockHash) -> Self {
+    /// Creates a `FullBlockHeader` from a block and its computed `block_hash`.
+    pub fn parse(block: &Block, block_hash: BlockHash) -> Self {
+        let b = &block.header;
+        let (size, stripped_size, weight) = block_sizes(block);
         Self {
             version: b.version.to_consensus(),
             block_hash,
@@ -63,23 +79,49 @@ impl FullBlockHeader {
             time: b.time,
             bits: b.bits.to_consensus(),
             nonce: b.nonce,
+            size,
+            stripped_size,
+            weight,
+            total_fees: None,
         }
     }
 }
 
+/// Compute `(size, stripped_size, weight)` of a block, in a single pass
+/// over its transactions.
+pub(crate) fn block_sizes(block: &Block) -> (u64, u64, u64) {
+    let header = 80 + VarInt::from(block.txdata.len()).size();
+    let (size, stripped_size) = block
+        .txdata
+        .iter()
+        .fold((header, header), |(size, stripped_size), tx| {
+            (size + tx.total_size(), stripped_size + tx.base_size())
+        });
+    let (size, stripped_size) = (size as u64, stripped_size as u64);
+    (size, stripped_size, stripped_size * 3 + size)
+}
+
 /// A Bitcoin transaction with additional metadata.
 ///
 /// A [`FullTransaction`] extends the [`Transaction`] by adding precomputed metadata:
 /// - `txid`: The transaction ID.
 /// - `output_addresses`: The list of addresses for each transaction output.
 /// - `output_script_types`: The script type for each transaction output.
+/// - `size`, `weight` and `vsize`: Computed before the witness is dropped from coinbase inputs.
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 pub struct FullTransaction {
     pub version: i32,
     pub lock_time: u32,
-    pub input: Vec<bitcoin::TxIn>,
+    /// List of inputs, with additional metadata.
+    pub input: Vec<FullTxIn>,
     /// Precomputed transaction ID.
     pub txid: Txid,
+    /// Serialized size in bytes, including witness data.
+    pub size: u64,
+    /// Transaction weight as defined in BIP141.
+    pub weight: u64,
+    /// Virtual size in vbytes.
+    pub vsize: u64,
     /// List of outputs, with additional metadata.
     pub output: Vec<FullTxOut>,
 }
@@ -88,17 +130,71 @@ impl From<Transaction> for FullTransaction {
     fn from(tx: Transaction) -> Self {
         let is_coinbase = tx.is_coinbase();
         let txid = tx.compute_txid();
-        let input = if is_coinbase { Vec::new() } else { tx.input };
+        let weight = tx.weight();
+        let size = tx.total_size() as u64;
+        let input = if is_coinbase {
+            Vec::new()
+        } else {
+            tx.input.into_iter().map(FullTxIn::from).collect()
+        };
         Self {
             version: tx.version.0,
             lock_time: tx.lock_time.to_consensus_u32(),
             txid,
+            size,
+            weight: weight.to_wu(),
+            vsize: weight.to_vbytes_ceil(),
             input,
             output: tx.output.into_iter().map(FullTxOut::from).collect(),
         }
     }
 }
 
+/// A Bitcoin transaction input with additional metadata.
+///
+/// A [`FullTxIn`] extends the [`bitcoin::TxIn`] by adding precomputed information:
+/// - `input_type`: How the spent output is unlocked, guessed from `script_sig` and `witness`
+///   since the spent output is not known.
+/// - `revealed_scripts`: Scripts revealed by P2SH, P2WSH and taproot script-path spends.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct FullTxIn {
+    pub previous_output: OutPoint,
+    pub script_sig: ScriptBuf,
+    pub sequence: u32,
+    pub witness: Witness,
+    /// Precomputed input type.
+    pub input_type: InputType,
+    /// Precomputed scripts revealed by this input.
+    pub revealed_scripts: RevealedScripts,
+}
+
+impl From<TxIn> for FullTxIn {
+    fn from(tx_in: TxIn) -> Self {
+        let input_type = InputType::classify(&tx_in, None);
+        let revealed_scripts = RevealedScripts::extract(&tx_in, &input_type);
+        Self {
+            previous_output: tx_in.previous_output,
+            script_sig: tx_in.script_sig,
+            sequence: tx_in.sequence.to_consensus_u32(),
+            witness: tx_in.witness,
+            input_type,
+            revealed_scripts,
+        }
+    }
+}
+
+impl FullTxIn {
+    /// `scriptSig.asm` as shown by Bitcoin Core, with signature hash types decoded.
+    pub fn script_sig_asm(&self) -> String {
+        script_to_asm(&self.script_sig, true)
+    }
+
+    /// The `script_sig` as a list of instructions.
+    pub fn script_sig_instructions(&self) -> Vec<ScriptInstruction> {
+        script_instructions(&self.script_sig)
+    }
+}
+
 /// A Bitcoin transaction output with additional metadata.
 ///
 /// A [`FullTxOut`] extends the [`bitcoin::TxOut`] by adding precomputed information:
@@ -125,3 +221,38 @@ impl From<TxOut> for FullTxOut {
         }
     }
 }
+
+impl FullTxOut {
+    /// `scriptPubKey.asm` as shown by Bitcoin Core.
+    pub fn asm(&self) -> String {
+        script_to_asm(&self.script_pubkey, false)
+    }
+
+    /// The `script_pubkey` as a list of instructions.
+    pub fn instructions(&self) -> Vec<ScriptInstruction> {
+        script_instructions(&self.script_pubkey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::Network;
+
+    #[test]
+    fn test_block_sizes() {
+        let mut block = genesis_block(Network::Bitcoin);
+        let mut tx = block.txdata[0].clone();
+        tx.input[0].witness = Witness::from_slice(&[[1u8; 32]]);
+        block.txdata.push(tx);
+        assert_eq!(
+            block_sizes(&block),
+            (
+                block.total_size() as u64,
+                (block.weight().to_wu() - block.total_size() as u64) / 3,
+                block.weight().to_wu()
+            )
+        );
+    }
+}
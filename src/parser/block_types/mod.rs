@@ -16,7 +16,19 @@
 //! - `CompactConnectedBlock`
 //! - `FullConnectedBlock`
 //!     Corresponding to the basic F/S Blocks.
+//!
+//! ## Block Projections
+//!
+//! User-defined formats implementing `projection::BlockProjection`,
+//! built from a borrowed block and, in connected iteration, its prevouts.
+//!
+//! ## Block Views
+//!
+//! `view::BlockView` and `view::TxView` read fields in place from the
+//! serialized block, without decoding it.
 
 pub mod compact_block;
 pub mod connected_block;
 pub mod full_block;
+pub mod projection;
+pub mod view;
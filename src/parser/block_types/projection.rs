@@ -0,0 +1,110 @@
+//! User-defined block formats built from a borrowed block.
+//!
+//! Converting a block with `From<Block>` or [`ConnectedBlock`](super::connected_block::ConnectedBlock)
+//! clones the scripts and evaluates every output script. A [`BlockProjection`]
+//! borrows the decoded block instead, and keeps only the fields it needs.
+//! Projections are built in the worker threads of the iterators.
+
+use crate::parser::undo::Coin;
+use bitcoin::{Block, BlockHash, Transaction};
+
+/// Where a block being projected is, and the outputs its inputs spend.
+#[derive(Clone, Copy, Debug)]
+pub struct BlockContext<'a> {
+    pub height: usize,
+    pub block_hash: BlockHash,
+    /// The outputs spent by the block, in the order of the inputs,
+    /// the coinbase input excluded.
+    ///
+    /// `None` unless the block is read by connected iteration.
+    pub prevouts: Option<&'a [Coin]>,
+}
+
+impl<'a> BlockContext<'a> {
+    /// The outputs spent by each transaction of `block`, empty for the coinbase.
+    ///
+    /// `None` unless the block is read by connected iteration.
+    pub fn tx_prevouts<'b>(
+        &self,
+        block: &'b Block,
+    ) -> Option<impl Iterator<Item = (&'b Transaction, &'a [Coin])>> {
+        let mut rest = self.prevouts?;
+        Some(block.txdata.iter().map(move |tx| {
+            let n = if tx.is_coinbase() { 0 } else { tx.input.len() };
+            let (prevouts, tail) = rest.split_at(n.min(rest.len()));
+            rest = tail;
+            (tx, prevouts)
+        }))
+    }
+}
+
+/// A block format built from a borrowed [`Block`].
+///
+/// # Example
+///
+/// Keep only the data pushed by `OP_RETURN` outputs:
+///
+/// ```rust
+/// use bitcoin_explorer::{Block, BlockContext, BlockProjection};
+///
+/// struct OpReturns(Vec<Vec<u8>>);
+///
+/// impl BlockProjection for OpReturns {
+///     fn project(block: &Block, _context: &BlockContext) -> Self {
+///         OpReturns(
+///             block
+///                 .txdata
+///                 .iter()
+///                 .flat_map(|tx| tx.output.iter())
+///                 .filter(|o| o.script_pubkey.is_op_return())
+///                 .map(|o| o.script_pubkey.as_bytes()[1..].to_vec())
+///                 .collect(),
+///         )
+///     }
+/// }
+/// ```
+pub trait BlockProjection {
+    fn project(block: &Block, context: &BlockContext) -> Self;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::{Network, OutPoint, TxIn};
+
+    #[test]
+    fn test_tx_prevouts() {
+        let mut block = genesis_block(Network::Bitcoin);
+        let coinbase = block.txdata[0].clone();
+        let mut spend = coinbase.clone();
+        spend.input = vec![TxIn::default(); 2];
+        spend.input[0].previous_output = OutPoint::new(coinbase.compute_txid(), 0);
+        spend.input[1].previous_output = OutPoint::new(coinbase.compute_txid(), 1);
+        block.txdata.push(spend);
+
+        let coin = |height| Coin {
+            out: coinbase.output[0].clone(),
+            height,
+            is_coinbase: true,
+        };
+        let prevouts = vec![coin(1), coin(2)];
+        let context = BlockContext {
+            height: 3,
+            block_hash: block.block_hash(),
+            prevouts: Some(&prevouts),
+        };
+        let heights: Vec<Vec<u32>> = context
+            .tx_prevouts(&block)
+            .unwrap()
+            .map(|(_, coins)| coins.iter().map(|c| c.height).collect())
+            .collect();
+        assert_eq!(heights, vec![vec![], vec![1, 2]]);
+
+        let unconnected = BlockContext {
+            prevouts: None,
+            ..context
+        };
+        assert!(unconnected.tx_prevouts(&block).is_none());
+    }
+}
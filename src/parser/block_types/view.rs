@@ -0,0 +1,526 @@
+//! Borrowed views over serialized blocks and transactions.
+//!
+//! Decoding a [`Block`] allocates every script and witness of the block.
+//! A [`BlockView`] only checks the structure of the bytes returned by
+//! [`BitcoinDB::get_raw_block`](crate::BitcoinDB::get_raw_block), and
+//! reads fields in place: txids, output values and scripts, and input
+//! outpoints are available without allocation. Use [`BlockView::decode`]
+//! or [`TxView::decode`] to get the fully decoded types.
+
+use crate::parser::error::{Error, Result};
+use crate::BlockHeader;
+use bitcoin::consensus::encode::{self, deserialize, MAX_VEC_SIZE};
+use bitcoin::hashes::{Hash, HashEngine};
+use bitcoin::{
+    Amount, Block, BlockHash, OutPoint, Script, Sequence, Transaction, TxOut, Txid, Wtxid,
+};
+use std::convert::TryInto;
+
+const HEADER_SIZE: usize = 80;
+const OUTPOINT_SIZE: usize = 36;
+
+/// A block borrowed from its serialization.
+///
+/// # Example
+///
+/// ```rust
+/// use bitcoin_explorer::{BitcoinDB, BlockView};
+/// use std::path::Path;
+///
+/// let db = BitcoinDB::new(Path::new("/Users/me/bitcoin"), false).unwrap();
+///
+/// let raw = db.get_raw_block(600000).unwrap();
+/// let block = BlockView::new(&raw).unwrap();
+/// for tx in block.transactions() {
+///     let tx = tx.unwrap();
+///     let value: u64 = tx.outputs().map(|o| o.value.to_sat()).sum();
+///     println!("{}: {}", tx.compute_txid(), value);
+/// }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct BlockView<'a> {
+    raw: &'a [u8],
+    tx_count: usize,
+    /// the transactions, after the transaction count.
+    txdata: &'a [u8],
+}
+
+impl<'a> BlockView<'a> {
+    /// Read the header and the transaction count of a serialized block.
+    ///
+    /// Transactions are checked as they are iterated.
+    pub fn new(raw: &'a [u8]) -> Result<Self> {
+        let mut bytes = Bytes::new(raw);
+        bytes.take(HEADER_SIZE)?;
+        let tx_count = bytes.compact_size()?;
+        Ok(Self {
+            raw,
+            tx_count,
+            txdata: bytes.rest(),
+        })
+    }
+
+    /// The serialized block.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    /// The 80 bytes of the serialized header.
+    pub fn header_bytes(&self) -> &'a [u8] {
+        &self.raw[..HEADER_SIZE]
+    }
+
+    pub fn header(&self) -> Result<BlockHeader> {
+        Ok(deserialize(self.header_bytes())?)
+    }
+
+    pub fn block_hash(&self) -> BlockHash {
+        BlockHash::hash(self.header_bytes())
+    }
+
+    /// The number of transactions, as serialized.
+    pub fn tx_count(&self) -> usize {
+        self.tx_count
+    }
+
+    /// Iterate through the transactions.
+    ///
+    /// The iteration stops after the first malformed transaction.
+    pub fn transactions(&self) -> TxViews<'a> {
+        TxViews {
+            bytes: Bytes::new(self.txdata),
+            remaining: self.tx_count,
+        }
+    }
+
+    /// Decode the whole block.
+    pub fn decode(&self) -> Result<Block> {
+        Ok(deserialize(self.raw)?)
+    }
+}
+
+/// Iterator of the transactions of a [`BlockView`].
+#[derive(Clone, Debug)]
+pub struct TxViews<'a> {
+    bytes: Bytes<'a>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for TxViews<'a> {
+    type Item = Result<TxView<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let tx = TxView::parse(&mut self.bytes);
+        self.remaining = if tx.is_ok() { self.remaining - 1 } else { 0 };
+        Some(tx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining))
+    }
+}
+
+/// A transaction borrowed from its serialization.
+///
+/// The structure of the bytes is checked when the view is created,
+/// so that inputs and outputs are read without failure afterwards.
+#[derive(Clone, Copy, Debug)]
+pub struct TxView<'a> {
+    raw: &'a [u8],
+    is_segwit: bool,
+    input_count: usize,
+    output_count: usize,
+    /// the serialization without version, segwit marker, witnesses and lock time.
+    body: &'a [u8],
+    /// the inputs, after the input count.
+    inputs: &'a [u8],
+    /// the outputs, after the output count.
+    outputs: &'a [u8],
+}
+
+impl<'a> TxView<'a> {
+    /// Check a serialized transaction.
+    ///
+    /// `raw` must contain exactly one transaction.
+    pub fn new(raw: &'a [u8]) -> Result<Self> {
+        let mut bytes = Bytes::new(raw);
+        let tx = Self::parse(&mut bytes)?;
+        if !bytes.rest().is_empty() {
+            return Err(encode::Error::ParseFailed("data not consumed entirely").into());
+        }
+        Ok(tx)
+    }
+
+    fn parse(bytes: &mut Bytes<'a>) -> Result<Self> {
+        let start = bytes.pos;
+        bytes.take(4)?;
+        let mut body_start = bytes.pos;
+        let mut input_count = bytes.compact_size()?;
+        let is_segwit = input_count == 0;
+        if is_segwit {
+            match bytes.u8()? {
+                1 => {}
+                flag => return Err(encode::Error::UnsupportedSegwitFlag(flag).into()),
+            }
+            body_start = bytes.pos;
+            input_count = bytes.compact_size()?;
+        }
+        let inputs_start = bytes.pos;
+        for _ in 0..input_count {
+            bytes.take(OUTPOINT_SIZE)?;
+            bytes.var_slice()?;
+            bytes.take(4)?;
+        }
+        let inputs = &bytes.data[inputs_start..bytes.pos];
+        let output_count = bytes.compact_size()?;
+        let outputs_start = bytes.pos;
+        for _ in 0..output_count {
+            bytes.take(8)?;
+            bytes.var_slice()?;
+        }
+        let outputs = &bytes.data[outputs_start..bytes.pos];
+        let body = &bytes.data[body_start..bytes.pos];
+        if is_segwit {
+            let mut has_witness = false;
+            for _ in 0..input_count {
+                let item_count = bytes.compact_size()?;
+                has_witness |= item_count > 0;
+                for _ in 0..item_count {
+                    bytes.var_slice()?;
+                }
+            }
+            if !has_witness {
+                return Err(encode::Error::ParseFailed(
+                    "witness flag set but no witnesses present",
+                )
+                .into());
+            }
+        }
+        bytes.take(4)?;
+        Ok(Self {
+            raw: &bytes.data[start..bytes.pos],
+            is_segwit,
+            input_count,
+            output_count,
+            body,
+            inputs,
+            outputs,
+        })
+    }
+
+    /// The serialized transaction, witnesses included.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    pub fn version(&self) -> i32 {
+        i32::from_le_bytes(self.raw[..4].try_into().unwrap())
+    }
+
+    pub fn lock_time(&self) -> u32 {
+        u32::from_le_bytes(self.raw[self.raw.len() - 4..].try_into().unwrap())
+    }
+
+    /// Whether the transaction is serialized with witnesses.
+    pub fn is_segwit(&self) -> bool {
+        self.is_segwit
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.input_count == 1
+            && self
+                .inputs()
+                .next()
+                .map_or(false, |i| i.previous_output.is_null())
+    }
+
+    /// Hash the serialization without witnesses, in place.
+    pub fn compute_txid(&self) -> Txid {
+        if !self.is_segwit {
+            return Txid::hash(self.raw);
+        }
+        let mut engine = Txid::engine();
+        engine.input(&self.raw[..4]);
+        engine.input(self.body);
+        engine.input(&self.raw[self.raw.len() - 4..]);
+        Txid::from_engine(engine)
+    }
+
+    pub fn compute_wtxid(&self) -> Wtxid {
+        Wtxid::hash(self.raw)
+    }
+
+    /// Size of the serialization without witnesses.
+    pub fn base_size(&self) -> usize {
+        self.body.len() + 8
+    }
+
+    /// Size of the serialization with witnesses.
+    pub fn total_size(&self) -> usize {
+        self.raw.len()
+    }
+
+    pub fn weight(&self) -> usize {
+        self.base_size() * 3 + self.total_size()
+    }
+
+    pub fn input_count(&self) -> usize {
+        self.input_count
+    }
+
+    pub fn output_count(&self) -> usize {
+        self.output_count
+    }
+
+    pub fn inputs(&self) -> impl Iterator<Item = TxInView<'a>> + Clone {
+        let mut bytes = Bytes::new(self.inputs);
+        (0..self.input_count).map_while(move |_| {
+            let outpoint = bytes.take(OUTPOINT_SIZE).ok()?;
+            let script_sig = bytes.var_slice().ok()?;
+            let sequence = bytes.u32().ok()?;
+            Some(TxInView {
+                previous_output: OutPoint {
+                    txid: Txid::from_byte_array(outpoint[..32].try_into().unwrap()),
+                    vout: u32::from_le_bytes(outpoint[32..].try_into().unwrap()),
+                },
+                script_sig: Script::from_bytes(script_sig),
+                sequence: Sequence(sequence),
+            })
+        })
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = TxOutView<'a>> + Clone {
+        let mut bytes = Bytes::new(self.outputs);
+        (0..self.output_count).map_while(move |_| {
+            let value = bytes.take(8).ok()?;
+            let script_pubkey = bytes.var_slice().ok()?;
+            Some(TxOutView {
+                value: Amount::from_sat(u64::from_le_bytes(value.try_into().unwrap())),
+                script_pubkey: Script::from_bytes(script_pubkey),
+            })
+        })
+    }
+
+    /// Decode the whole transaction.
+    pub fn decode(&self) -> Result<Transaction> {
+        Ok(deserialize(self.raw)?)
+    }
+}
+
+/// An input of a [`TxView`], witness excluded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TxInView<'a> {
+    pub previous_output: OutPoint,
+    pub script_sig: &'a Script,
+    pub sequence: Sequence,
+}
+
+/// An output of a [`TxView`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TxOutView<'a> {
+    pub value: Amount,
+    pub script_pubkey: &'a Script,
+}
+
+impl TxOutView<'_> {
+    pub fn to_tx_out(&self) -> TxOut {
+        TxOut {
+            value: self.value,
+            script_pubkey: self.script_pubkey.to_owned(),
+        }
+    }
+}
+
+/// A cursor over a byte slice, reading Bitcoin consensus encoding.
+#[derive(Clone, Debug)]
+struct Bytes<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.data.len() - self.pos < n {
+            return Err(Error::BitcoinIo(
+                bitcoin::io::ErrorKind::UnexpectedEof.into(),
+            ));
+        }
+        let slice = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Reject non-minimal encodings and sizes above [`MAX_VEC_SIZE`],
+    /// as rust-bitcoin does.
+    fn compact_size(&mut self) -> Result<usize> {
+        let (n, min) = match self.u8()? {
+            0xFD => (
+                u16::from_le_bytes(self.take(2)?.try_into().unwrap()) as u64,
+                0xFD,
+            ),
+            0xFE => (
+                u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as u64,
+                0x10000,
+            ),
+            0xFF => (
+                u64::from_le_bytes(self.take(8)?.try_into().unwrap()),
+                0x1_0000_0000,
+            ),
+            n => (n as u64, 0),
+        };
+        if n < min {
+            return Err(encode::Error::NonMinimalVarInt.into());
+        }
+        if n > MAX_VEC_SIZE as u64 {
+            return Err(encode::Error::OversizedVectorAllocation {
+                requested: n.try_into().unwrap_or(usize::MAX),
+                max: MAX_VEC_SIZE,
+            }
+            .into());
+        }
+        Ok(n as usize)
+    }
+
+    fn var_slice(&mut self) -> Result<&'a [u8]> {
+        let len = self.compact_size()?;
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::consensus::serialize;
+    use bitcoin::{Network, TxIn, Witness};
+
+    fn segwit_tx() -> Transaction {
+        let mut tx = genesis_block(Network::Bitcoin).txdata[0].clone();
+        let mut input = TxIn {
+            previous_output: OutPoint::new(tx.compute_txid(), 3),
+            sequence: Sequence(0xFFFF_FFFD),
+            ..TxIn::default()
+        };
+        input.witness = Witness::from_slice(&[vec![1u8; 72], vec![2u8; 33]]);
+        tx.input = vec![input.clone(), TxIn::default()];
+        tx.output.push(tx.output[0].clone());
+        tx
+    }
+
+    #[test]
+    fn test_block_view() {
+        let mut block = genesis_block(Network::Bitcoin);
+        block.txdata.push(segwit_tx());
+        let raw = serialize(&block);
+
+        let view = BlockView::new(&raw).unwrap();
+        assert_eq!(view.header().unwrap(), block.header);
+        assert_eq!(view.block_hash(), block.block_hash());
+        assert_eq!(view.tx_count(), 2);
+        assert_eq!(view.decode().unwrap(), block);
+
+        let txs = view.transactions().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(txs.len(), 2);
+        for (v, tx) in txs.iter().zip(block.txdata.iter()) {
+            assert_eq!(v.compute_txid(), tx.compute_txid());
+            assert_eq!(v.compute_wtxid(), tx.compute_wtxid());
+            assert_eq!(v.is_coinbase(), tx.is_coinbase());
+            assert_eq!(v.weight(), tx.weight().to_wu() as usize);
+            assert_eq!(v.version(), tx.version.0);
+            assert_eq!(v.lock_time(), tx.lock_time.to_consensus_u32());
+            assert_eq!(v.decode().unwrap(), *tx);
+            let outpoints: Vec<OutPoint> = v.inputs().map(|i| i.previous_output).collect();
+            let expected: Vec<OutPoint> = tx.input.iter().map(|i| i.previous_output).collect();
+            assert_eq!(outpoints, expected);
+            let outputs: Vec<TxOut> = v.outputs().map(|o| o.to_tx_out()).collect();
+            assert_eq!(outputs, tx.output);
+        }
+        assert!(txs[1].is_segwit());
+        assert_eq!(
+            txs[1].inputs().next().unwrap().sequence,
+            Sequence(0xFFFF_FFFD)
+        );
+    }
+
+    #[test]
+    fn test_truncated() {
+        let raw = serialize(&genesis_block(Network::Bitcoin));
+        let view = BlockView::new(&raw[..raw.len() - 1]).unwrap();
+        let mut txs = view.transactions();
+        assert!(txs.next().unwrap().is_err());
+        assert!(txs.next().is_none());
+        assert!(BlockView::new(&raw[..79]).is_err());
+
+        let tx = serialize(&segwit_tx());
+        assert!(TxView::new(&tx).is_ok());
+        assert!(TxView::new(&tx[..tx.len() - 1]).is_err());
+        assert!(TxView::new(&[tx.as_slice(), &[0]].concat()).is_err());
+    }
+
+    #[test]
+    fn test_non_canonical() {
+        let tx = serialize(&genesis_block(Network::Bitcoin).txdata[0]);
+        // the input count, 1.
+        assert_eq!(tx[4], 1);
+        let with_count = |count: &[u8]| [&tx[..4], count, &tx[5..]].concat();
+        assert!(TxView::new(&with_count(&[1])).is_ok());
+
+        let non_minimal = with_count(&[0xFD, 1, 0]);
+        assert!(matches!(
+            TxView::new(&non_minimal),
+            Err(Error::Encode(encode::Error::NonMinimalVarInt))
+        ));
+        assert!(deserialize::<Transaction>(&non_minimal).is_err());
+
+        let oversized = with_count(&[0xFE, 0, 0, 0, 1]);
+        assert!(matches!(
+            TxView::new(&oversized),
+            Err(Error::Encode(
+                encode::Error::OversizedVectorAllocation { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_empty_witnesses() {
+        let mut tx = segwit_tx();
+        for input in &mut tx.input {
+            input.witness.clear();
+        }
+        let legacy = serialize(&tx);
+        let (body, lock_time) = legacy.split_at(legacy.len() - 4);
+        let input_count = tx.input.len();
+        let raw = [
+            &body[..4],
+            &[0, 1],
+            &body[4..],
+            &vec![0; input_count],
+            lock_time,
+        ]
+        .concat();
+        assert!(matches!(
+            TxView::new(&raw),
+            Err(Error::Encode(encode::Error::ParseFailed(_)))
+        ));
+        assert!(deserialize::<Transaction>(&raw).is_err());
+    }
+}
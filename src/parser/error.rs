@@ -13,6 +13,16 @@ pub enum Error {
     EmptyBlockFiles,
     #[error("blk file {0} not found, try to sync with Bitcoin Core")]
     BlockFileNotFound(i32),
+    #[error("rev file {0} not found, try to sync with Bitcoin Core")]
+    UndoFileNotFound(i32),
+    #[error("undo data for block {0} not found")]
+    BlockUndoNotFound(usize),
+    #[error("Invalid undo data: {0}")]
+    InvalidUndoData(String),
+    #[error("Invalid mempool file: {0}")]
+    InvalidMempoolFile(String),
+    #[error("invalid fee estimates file: {0}")]
+    InvalidFeeEstimatesFile(String),
     #[error("block index record {0} not found")]
     BlockIndexRecordNotFound(usize),
     #[error("block index for {0} not found")]
@@ -21,6 +31,8 @@ pub enum Error {
     TransactionRecordNotFound(Txid),
     #[error("Some outpoints are not found, tx_index is not fully synced")]
     MissingOutputs { expected: usize, got: usize },
+    #[error("previous output {0} not found")]
+    PrevoutNotFound(bitcoin::OutPoint),
     #[error("failed to find height for transaction: {0}")]
     CannotFindHeightForTransaction(Txid),
     #[error("TxDB is not enabled or failed to be opened")]
@@ -35,6 +47,15 @@ pub enum Error {
     InvalidHash(String),
     #[error(transparent)]
     Leveldb(#[from] leveldb::error::Error),
+    #[cfg(feature = "on-disk-utxo")]
+    #[error(transparent)]
+    Rocksdb(#[from] rocksdb::Error),
+    #[error("no UTXO cache checkpoint found in {0}")]
+    CheckpointNotFound(PathBuf),
+    #[error("UTXO cache checkpoint in {0} has an incompatible format, start again")]
+    IncompatibleCheckpoint(PathBuf),
+    #[error("failed at block {height}: {source}")]
+    AtHeight { height: usize, source: Box<Error> },
     #[error(transparent)]
     Utf8Error(string::FromUtf8Error),
     #[error("Runtime: {0}")]
@@ -45,6 +66,16 @@ pub enum Error {
     SendError(String),
 }
 
+impl Error {
+    /// Attach the height of the block being processed to `err`.
+    pub(crate) fn at_height(height: usize, err: Error) -> Self {
+        Self::AtHeight {
+            height,
+            source: Box::new(err),
+        }
+    }
+}
+
 impl<T> From<sync::PoisonError<T>> for Error {
     fn from(err: sync::PoisonError<T>) -> Self {
         Self::PoisonError(err.to_string())
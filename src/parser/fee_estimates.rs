@@ -0,0 +1,524 @@
+//! Read the fee estimator state persisted by Bitcoin Core (`fee_estimates.dat`).
+//!
+//! The file stores the statistics of `CBlockPolicyEstimator`: for each fee
+//! rate bucket and each of three time horizons, the decayed number of
+//! transactions confirmed within a number of blocks.
+//! [`FeeEstimator::estimate_smart_fee`] computes `estimatesmartfee` from
+//! this state, as the node would right after loading the file.
+//!
+//! Translated from Bitcoin Core:
+//! [fees.cpp](https://github.com/bitcoin/bitcoin/blob/master/src/policy/fees.cpp).
+
+use crate::parser::error::{Error, Result};
+use bitcoin::consensus::encode::VarInt;
+use bitcoin::consensus::Decodable;
+use bitcoin::io::Cursor;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Oldest Bitcoin Core version (0.14.99) whose file format can be read.
+const FEE_ESTIMATES_FILE_VERSION: i32 = 149900;
+
+/// Require greater than 60% of X feerate transactions to be confirmed within Y/2 blocks.
+const HALF_SUCCESS_PCT: f64 = 0.6;
+/// Require greater than 85% of X feerate transactions to be confirmed within Y blocks.
+const SUCCESS_PCT: f64 = 0.85;
+/// Require greater than 95% of X feerate transactions to be confirmed within 2 * Y blocks.
+const DOUBLE_SUCCESS_PCT: f64 = 0.95;
+
+/// Require an avg of 0.1 tx in the combined feerate bucket per block to have stat significance.
+const SUFFICIENT_FEETXS: f64 = 0.1;
+/// Require an avg of 0.5 tx when using short decay since there are fewer blocks considered.
+const SUFFICIENT_TXS_SHORT: f64 = 0.5;
+
+/// Historical estimates that are older than this aren't valid.
+const OLDEST_ESTIMATE_HISTORY: u32 = 6 * 1008;
+
+/// Confirmation statistics of one time horizon (`TxConfirmStats`).
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct TxConfirmStats {
+    /// Decay applied to the statistics each block.
+    pub decay: f64,
+    /// Number of blocks aggregated in a period.
+    pub scale: u32,
+    /// Sum of the fee rates of transactions counted in each bucket.
+    pub feerate_avg: Vec<f64>,
+    /// Number of transactions confirmed in each bucket.
+    pub tx_ct_avg: Vec<f64>,
+    /// `conf_avg[p][b]`: transactions of bucket `b` confirmed within `(p + 1) * scale` blocks.
+    pub conf_avg: Vec<Vec<f64>>,
+    /// `fail_avg[p][b]`: transactions of bucket `b` that left the mempool
+    /// unconfirmed after `(p + 1) * scale` blocks.
+    pub fail_avg: Vec<Vec<f64>>,
+}
+
+/// Number of periods tracked per horizon is limited to a week of blocks.
+const MAX_PERIODS: usize = 6 * 24 * 7;
+
+impl TxConfirmStats {
+    fn decode(reader: &mut Cursor<Vec<u8>>, num_buckets: usize) -> Result<Self> {
+        let decay = read_double(reader)?;
+        if decay <= 0.0 || decay >= 1.0 {
+            return Err(invalid("decay must be between 0 and 1 (non-inclusive)"));
+        }
+        let scale = u32::consensus_decode(reader)?;
+        if scale == 0 {
+            return Err(invalid("scale must be non-zero"));
+        }
+        let feerate_avg = read_doubles(reader)?;
+        if feerate_avg.len() != num_buckets {
+            return Err(invalid("mismatch in feerate average bucket count"));
+        }
+        let tx_ct_avg = read_doubles(reader)?;
+        if tx_ct_avg.len() != num_buckets {
+            return Err(invalid("mismatch in tx count bucket count"));
+        }
+        let conf_avg = read_double_table(reader)?;
+        let max_periods = conf_avg.len();
+        if max_periods == 0 || max_periods > MAX_PERIODS {
+            return Err(invalid(
+                "must maintain estimates for between 1 and 1008 (one week) confirmation targets",
+            ));
+        }
+        if conf_avg.iter().any(|row| row.len() != num_buckets) {
+            return Err(invalid("mismatch in feerate conf average bucket count"));
+        }
+        let fail_avg = read_double_table(reader)?;
+        if fail_avg.len() != max_periods || fail_avg.iter().any(|row| row.len() != num_buckets) {
+            return Err(invalid("mismatch in confirms tracked for failures"));
+        }
+        Ok(Self {
+            decay,
+            scale,
+            feerate_avg,
+            tx_ct_avg,
+            conf_avg,
+            fail_avg,
+        })
+    }
+
+    /// Maximum confirmation target tracked by this horizon.
+    pub fn max_confirms(&self) -> u32 {
+        self.scale * self.conf_avg.len() as u32
+    }
+
+    /// Fee rate (sat/kvB) of the lowest bucket range whose transactions were
+    /// confirmed within `conf_target` blocks at least `success_break_point`
+    /// of the time (`EstimateMedianVal`).
+    ///
+    /// Transactions still unconfirmed are not persisted by Bitcoin Core,
+    /// so they are not counted here.
+    fn estimate_median_val(
+        &self,
+        buckets: &[f64],
+        conf_target: u32,
+        sufficient_tx_val: f64,
+        success_break_point: f64,
+    ) -> Option<f64> {
+        let period_target = ((conf_target + self.scale - 1) / self.scale) as usize;
+        let conf_avg = &self.conf_avg[period_target - 1];
+        let fail_avg = &self.fail_avg[period_target - 1];
+        let max_bucket_index = buckets.len() - 1;
+
+        let mut n_conf = 0.0;
+        let mut total_num = 0.0;
+        let mut fail_num = 0.0;
+        let mut partial_num = 0.0;
+
+        let mut cur_near_bucket = max_bucket_index;
+        let mut best_near_bucket = max_bucket_index;
+        let mut cur_far_bucket;
+        let mut best_far_bucket = max_bucket_index;
+
+        let mut found_answer = false;
+        let mut new_bucket_range = true;
+
+        // start counting from highest feerate transactions
+        for bucket in (0..=max_bucket_index).rev() {
+            if new_bucket_range {
+                cur_near_bucket = bucket;
+                new_bucket_range = false;
+            }
+            cur_far_bucket = bucket;
+            n_conf += conf_avg[bucket];
+            partial_num += self.tx_ct_avg[bucket];
+            total_num += self.tx_ct_avg[bucket];
+            fail_num += fail_avg[bucket];
+
+            // keep adding buckets until there are enough data points.
+            if partial_num < sufficient_tx_val / (1.0 - self.decay) {
+                continue;
+            }
+            partial_num = 0.0;
+            new_bucket_range = true;
+
+            let cur_pct = n_conf / (total_num + fail_num);
+            if cur_pct < success_break_point {
+                continue;
+            }
+            found_answer = true;
+            n_conf = 0.0;
+            total_num = 0.0;
+            fail_num = 0.0;
+            best_near_bucket = cur_near_bucket;
+            best_far_bucket = cur_far_bucket;
+        }
+
+        // report the average fee rate of the bucket containing the median transaction.
+        let min_bucket = best_near_bucket.min(best_far_bucket);
+        let max_bucket = best_near_bucket.max(best_far_bucket);
+        let mut tx_sum: f64 = self.tx_ct_avg[min_bucket..=max_bucket].iter().sum();
+        if !found_answer || tx_sum == 0.0 {
+            return None;
+        }
+        tx_sum /= 2.0;
+        for j in min_bucket..=max_bucket {
+            if self.tx_ct_avg[j] < tx_sum {
+                tx_sum -= self.tx_ct_avg[j];
+            } else {
+                return Some(self.feerate_avg[j] / self.tx_ct_avg[j]);
+            }
+        }
+        None
+    }
+}
+
+/// Result of [`FeeEstimator::estimate_smart_fee`].
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SmartFeeEstimate {
+    /// Estimated fee rate in sat/kvB.
+    pub fee_rate: u64,
+    /// Confirmation target for which the estimate was found.
+    pub blocks: u32,
+}
+
+/// Snapshot of `CBlockPolicyEstimator` read from `fee_estimates.dat`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct FeeEstimator {
+    /// Version of Bitcoin Core that wrote the file.
+    pub client_version: i32,
+    /// Height of the best block seen when the file was written.
+    pub best_seen_height: u32,
+    pub historical_first: u32,
+    pub historical_best: u32,
+    /// Upper bounds (sat/kvB) of the fee rate buckets.
+    pub buckets: Vec<f64>,
+    /// Medium horizon, up to 48 blocks.
+    pub fee_stats: TxConfirmStats,
+    /// Short horizon, up to 12 blocks.
+    pub short_stats: TxConfirmStats,
+    /// Long horizon, up to 1008 blocks.
+    pub long_stats: TxConfirmStats,
+}
+
+impl FeeEstimator {
+    /// Read and decode a `fee_estimates.dat` file.
+    ///
+    /// # Arguments
+    ///
+    /// `path`: Path of `bitcoin_core_data_dir/fee_estimates.dat`.
+    pub fn read(path: &Path) -> Result<Self> {
+        Self::decode(std::fs::read(path)?)
+    }
+
+    fn decode(bytes: Vec<u8>) -> Result<Self> {
+        let mut reader = Cursor::new(bytes);
+        let version_required = i32::consensus_decode(&mut reader)?;
+        if version_required < FEE_ESTIMATES_FILE_VERSION {
+            return Err(invalid(&format!(
+                "incompatible old file version {version_required}"
+            )));
+        }
+        let client_version = i32::consensus_decode(&mut reader)?;
+        let best_seen_height = u32::consensus_decode(&mut reader)?;
+        let historical_first = u32::consensus_decode(&mut reader)?;
+        let historical_best = u32::consensus_decode(&mut reader)?;
+        if historical_first > historical_best || historical_best > best_seen_height {
+            return Err(invalid(
+                "corrupt estimates file, historical block range is invalid",
+            ));
+        }
+        let buckets = read_doubles(&mut reader)?;
+        if buckets.len() <= 1 || buckets.len() > 1000 {
+            return Err(invalid("must have between 2 and 1000 feerate buckets"));
+        }
+        let fee_stats = TxConfirmStats::decode(&mut reader, buckets.len())?;
+        let short_stats = TxConfirmStats::decode(&mut reader, buckets.len())?;
+        let long_stats = TxConfirmStats::decode(&mut reader, buckets.len())?;
+        Ok(Self {
+            client_version,
+            best_seen_height,
+            historical_first,
+            historical_best,
+            buckets,
+            fee_stats,
+            short_stats,
+            long_stats,
+        })
+    }
+
+    /// Number of blocks covered by the persisted statistics.
+    fn historical_block_span(&self) -> u32 {
+        if self.historical_first == 0
+            || self.best_seen_height - self.historical_best > OLDEST_ESTIMATE_HISTORY
+        {
+            return 0;
+        }
+        self.historical_best - self.historical_first
+    }
+
+    /// Highest confirmation target with enough history for an estimate.
+    pub fn max_usable_estimate(&self) -> u32 {
+        self.long_stats
+            .max_confirms()
+            .min(self.historical_block_span() / 2)
+    }
+
+    fn estimate_median_val(
+        &self,
+        stats: &TxConfirmStats,
+        conf_target: u32,
+        sufficient_tx_val: f64,
+        success_break_point: f64,
+    ) -> Option<f64> {
+        stats.estimate_median_val(
+            &self.buckets,
+            conf_target,
+            sufficient_tx_val,
+            success_break_point,
+        )
+    }
+
+    /// Estimate from the shortest horizon tracking `conf_target`
+    /// (`estimateCombinedFee`).
+    fn estimate_combined_fee(
+        &self,
+        conf_target: u32,
+        success_threshold: f64,
+        check_shorter_horizon: bool,
+    ) -> Option<f64> {
+        if conf_target < 1 || conf_target > self.long_stats.max_confirms() {
+            return None;
+        }
+        let mut estimate = if conf_target <= self.short_stats.max_confirms() {
+            self.estimate_median_val(
+                &self.short_stats,
+                conf_target,
+                SUFFICIENT_TXS_SHORT,
+                success_threshold,
+            )
+        } else if conf_target <= self.fee_stats.max_confirms() {
+            self.estimate_median_val(
+                &self.fee_stats,
+                conf_target,
+                SUFFICIENT_FEETXS,
+                success_threshold,
+            )
+        } else {
+            self.estimate_median_val(
+                &self.long_stats,
+                conf_target,
+                SUFFICIENT_FEETXS,
+                success_threshold,
+            )
+        };
+        if check_shorter_horizon {
+            // if a lower target from a more recent horizon returns a lower answer, use it.
+            let shorter = [
+                (&self.fee_stats, SUFFICIENT_FEETXS),
+                (&self.short_stats, SUFFICIENT_TXS_SHORT),
+            ];
+            for (stats, sufficient_tx_val) in shorter {
+                if conf_target > stats.max_confirms() {
+                    let max = self.estimate_median_val(
+                        stats,
+                        stats.max_confirms(),
+                        sufficient_tx_val,
+                        success_threshold,
+                    );
+                    if let Some(max) = max.filter(|m| *m > 0.0) {
+                        if estimate.map_or(true, |e| max < e) {
+                            estimate = Some(max);
+                        }
+                    }
+                }
+            }
+        }
+        estimate
+    }
+
+    /// Estimate from the medium and long horizons at 95% success
+    /// (`estimateConservativeFee`).
+    fn estimate_conservative_fee(&self, double_target: u32) -> Option<f64> {
+        let mut estimate = None;
+        if double_target <= self.short_stats.max_confirms() {
+            estimate = self.estimate_median_val(
+                &self.fee_stats,
+                double_target,
+                SUFFICIENT_FEETXS,
+                DOUBLE_SUCCESS_PCT,
+            );
+        }
+        if double_target <= self.fee_stats.max_confirms() {
+            let long_estimate = self.estimate_median_val(
+                &self.long_stats,
+                double_target,
+                SUFFICIENT_FEETXS,
+                DOUBLE_SUCCESS_PCT,
+            );
+            if long_estimate > estimate {
+                estimate = long_estimate;
+            }
+        }
+        estimate
+    }
+
+    /// Equivalent of `estimatesmartfee conf_target`, computed offline.
+    ///
+    /// `conservative` selects the `CONSERVATIVE` estimate mode instead of
+    /// `ECONOMICAL`. Returns `None` when no estimate is available.
+    pub fn estimate_smart_fee(
+        &self,
+        conf_target: u32,
+        conservative: bool,
+    ) -> Option<SmartFeeEstimate> {
+        if conf_target == 0 || conf_target > self.long_stats.max_confirms() {
+            return None;
+        }
+        // it's not possible to get reasonable estimates for a target of 1.
+        let conf_target = conf_target.max(2).min(self.max_usable_estimate());
+        if conf_target <= 1 {
+            return None;
+        }
+
+        let half_est = self.estimate_combined_fee(conf_target / 2, HALF_SUCCESS_PCT, true);
+        let actual_est = self.estimate_combined_fee(conf_target, SUCCESS_PCT, true);
+        let double_est =
+            self.estimate_combined_fee(2 * conf_target, DOUBLE_SUCCESS_PCT, !conservative);
+        let mut median = max_estimate(max_estimate(half_est, actual_est), double_est);
+
+        if conservative || median.is_none() {
+            median = max_estimate(median, self.estimate_conservative_fee(2 * conf_target));
+        }
+
+        median.map(|m| SmartFeeEstimate {
+            fee_rate: m.round() as u64,
+            blocks: conf_target,
+        })
+    }
+}
+
+/// Larger of two estimates, a missing estimate being the smallest.
+#[inline]
+fn max_estimate(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    if b > a {
+        b
+    } else {
+        a
+    }
+}
+
+#[inline]
+fn invalid(msg: &str) -> Error {
+    Error::InvalidFeeEstimatesFile(msg.to_string())
+}
+
+/// Doubles are serialized as their IEEE 754 bit pattern (`EncodedDoubleFormatter`).
+#[inline]
+fn read_double(reader: &mut Cursor<Vec<u8>>) -> Result<f64> {
+    Ok(f64::from_bits(u64::consensus_decode(reader)?))
+}
+
+fn read_doubles(reader: &mut Cursor<Vec<u8>>) -> Result<Vec<f64>> {
+    let len = VarInt::consensus_decode(reader)?.0 as usize;
+    let mut doubles = Vec::with_capacity(len.min(1 << 16));
+    for _ in 0..len {
+        doubles.push(read_double(reader)?);
+    }
+    Ok(doubles)
+}
+
+fn read_double_table(reader: &mut Cursor<Vec<u8>>) -> Result<Vec<Vec<f64>>> {
+    let len = VarInt::consensus_decode(reader)?.0 as usize;
+    let mut table = Vec::with_capacity(len.min(1 << 16));
+    for _ in 0..len {
+        table.push(read_doubles(reader)?);
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::consensus::Encodable;
+
+    fn write_doubles(out: &mut Vec<u8>, doubles: &[f64]) {
+        VarInt(doubles.len() as u64).consensus_encode(out).unwrap();
+        for d in doubles {
+            d.to_bits().consensus_encode(out).unwrap();
+        }
+    }
+
+    /// All transactions of a horizon are in `bucket`, paying `fee_rate`,
+    /// and confirmed in the next block.
+    fn write_stats(out: &mut Vec<u8>, scale: u32, periods: usize, bucket: usize, fee_rate: f64) {
+        let n_buckets = 3;
+        let tx_count = 100.0;
+        let mut counts = vec![0.0; n_buckets];
+        counts[bucket] = tx_count;
+        let mut fee_rates = vec![0.0; n_buckets];
+        fee_rates[bucket] = fee_rate * tx_count;
+
+        0.998_f64.to_bits().consensus_encode(out).unwrap();
+        scale.consensus_encode(out).unwrap();
+        write_doubles(out, &fee_rates);
+        write_doubles(out, &counts);
+        VarInt(periods as u64).consensus_encode(out).unwrap();
+        for _ in 0..periods {
+            write_doubles(out, &counts);
+        }
+        VarInt(periods as u64).consensus_encode(out).unwrap();
+        for _ in 0..periods {
+            write_doubles(out, &[0.0; 3]);
+        }
+    }
+
+    #[test]
+    fn test_estimate_smart_fee() {
+        let mut bytes = Vec::new();
+        FEE_ESTIMATES_FILE_VERSION
+            .consensus_encode(&mut bytes)
+            .unwrap();
+        280000_i32.consensus_encode(&mut bytes).unwrap();
+        // best seen height, historical first, historical best
+        800000_u32.consensus_encode(&mut bytes).unwrap();
+        795000_u32.consensus_encode(&mut bytes).unwrap();
+        800000_u32.consensus_encode(&mut bytes).unwrap();
+        write_doubles(&mut bytes, &[1000.0, 2000.0, 1e99]);
+        write_stats(&mut bytes, 2, 24, 1, 1500.0);
+        write_stats(&mut bytes, 1, 12, 1, 1500.0);
+        write_stats(&mut bytes, 24, 42, 1, 1500.0);
+
+        let estimator = FeeEstimator::decode(bytes).unwrap();
+        assert_eq!(estimator.short_stats.max_confirms(), 12);
+        assert_eq!(estimator.fee_stats.max_confirms(), 48);
+        assert_eq!(estimator.long_stats.max_confirms(), 1008);
+        assert_eq!(estimator.max_usable_estimate(), 1008);
+
+        let estimate = estimator.estimate_smart_fee(1, false).unwrap();
+        assert_eq!(estimate.blocks, 2);
+        assert_eq!(estimate.fee_rate, 1500);
+        assert_eq!(
+            estimator.estimate_smart_fee(6, true).unwrap().fee_rate,
+            1500
+        );
+        assert!(estimator.estimate_smart_fee(1009, false).is_none());
+    }
+
+    #[test]
+    fn test_invalid_version() {
+        let mut bytes = Vec::new();
+        139900_i32.consensus_encode(&mut bytes).unwrap();
+        assert!(FeeEstimator::decode(bytes).is_err());
+    }
+}
@@ -0,0 +1,340 @@
+//! Validate the header chain of the block index.
+//!
+//! [`BlockIndex`] only keeps records that Bitcoin Core marked as valid.
+//! [`HeaderChainValidator`] checks the headers again: proof of work,
+//! difficulty retargeting, median-time-past and the future-time limit,
+//! and reports every violation found.
+//!
+//! Translated from Bitcoin Core:
+//! [pow.cpp](https://github.com/bitcoin/bitcoin/blob/master/src/pow.cpp) and
+//! [validation.cpp](https://github.com/bitcoin/bitcoin/blob/master/src/validation.cpp).
+
+use crate::parser::block_index::BlockIndex;
+use crate::BlockHeader;
+use bitcoin::pow::{CompactTarget, Target};
+use bitcoin::BlockHash;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of blocks between difficulty adjustments.
+const DIFFICULTY_ADJUSTMENT_INTERVAL: usize = 2016;
+/// Two weeks, in seconds.
+const POW_TARGET_TIMESPAN: u64 = 14 * 24 * 60 * 60;
+/// Blocks may be at most two hours ahead of the current time.
+const MAX_FUTURE_BLOCK_TIME: u32 = 2 * 60 * 60;
+const BIP34_HEIGHT: usize = 227931;
+const BIP66_HEIGHT: usize = 363725;
+const BIP65_HEIGHT: usize = 388381;
+/// Highest target allowed on mainnet, `2^224 - 1`.
+const POW_LIMIT: [u8; 32] = [
+    0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
+
+/// A rule broken by a header.
+///
+/// Displayed as the reject reasons of Bitcoin Core.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HeaderError {
+    /// `prev_blockhash` is not the hash of the previous record.
+    BadPrevBlock,
+    /// `bits` is negative, overflows, or is above the proof of work limit.
+    InvalidBits { bits: u32 },
+    /// The block hash is above the target.
+    HighHash,
+    /// `bits` does not follow the difficulty adjustment rules.
+    BadDiffBits { expected: u32, found: u32 },
+    /// The timestamp is not after the median time past of the previous block.
+    TimeTooOld { time: u32, median_time_past: u32 },
+    /// The timestamp is too far in the future.
+    TimeTooNew { time: u32, max_time: u32 },
+    /// The version is too low after BIP34, BIP66 or BIP65 activation.
+    BadVersion { version: i32 },
+}
+
+impl std::fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::BadPrevBlock => write!(f, "bad-prevblk"),
+            Self::InvalidBits { .. } | Self::HighHash => write!(f, "high-hash"),
+            Self::BadDiffBits { .. } => write!(f, "bad-diffbits"),
+            Self::TimeTooOld { .. } => write!(f, "time-too-old"),
+            Self::TimeTooNew { .. } => write!(f, "time-too-new"),
+            Self::BadVersion { version } => write!(f, "bad-version(0x{:08x})", version),
+        }
+    }
+}
+
+/// A header that broke a rule.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct HeaderViolation {
+    pub height: usize,
+    pub block_hash: BlockHash,
+    pub error: HeaderError,
+}
+
+/// Result of validating a range of headers.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct HeaderChainReport {
+    /// First height checked.
+    pub start: usize,
+    /// Number of headers checked.
+    pub headers: usize,
+    /// Violations, ordered by height.
+    pub violations: Vec<HeaderViolation>,
+}
+
+impl HeaderChainReport {
+    /// Whether all headers follow the rules.
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Check the headers of a [`BlockIndex`] against the mainnet consensus rules.
+///
+/// # Example
+/// ```rust
+/// use bitcoin_explorer::{BitcoinDB, HeaderChainValidator};
+/// use std::path::Path;
+///
+/// let path = Path::new("/Users/me/bitcoin");
+/// let db = BitcoinDB::new(path, false).unwrap();
+///
+/// let validator = HeaderChainValidator::new(&db.block_index);
+/// let report = validator.validate();
+/// for v in report.violations {
+///     println!("{}: {}", v.height, v.error);
+/// }
+/// ```
+pub struct HeaderChainValidator<'a> {
+    block_index: &'a BlockIndex,
+    /// Current time, used for the future-time limit.
+    now: u32,
+}
+
+impl<'a> HeaderChainValidator<'a> {
+    pub fn new(block_index: &'a BlockIndex) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as u32)
+            .unwrap_or(u32::MAX);
+        Self { block_index, now }
+    }
+
+    /// Use `now` (unix time) instead of the system time for the future-time limit.
+    pub fn with_time(mut self, now: u32) -> Self {
+        self.now = now;
+        self
+    }
+
+    /// Validate all headers.
+    pub fn validate(&self) -> HeaderChainReport {
+        self.validate_range(0, self.block_index.records.len())
+    }
+
+    /// Validate headers from `start` to `end` (excluded).
+    ///
+    /// Headers are checked in parallel.
+    pub fn validate_range(&self, start: usize, end: usize) -> HeaderChainReport {
+        let end = end.min(self.block_index.records.len());
+        let start = start.min(end);
+        let violations = (start..end)
+            .into_par_iter()
+            .flat_map_iter(|height| {
+                let block_hash = self.block_index.records[height].block_header.block_hash();
+                self.check_header(height)
+                    .into_iter()
+                    .map(move |error| HeaderViolation {
+                        height,
+                        block_hash,
+                        error,
+                    })
+            })
+            .collect();
+        HeaderChainReport {
+            start,
+            headers: end - start,
+            violations,
+        }
+    }
+
+    /// All rules broken by the header at `height`.
+    pub fn check_header(&self, height: usize) -> Vec<HeaderError> {
+        let records = &self.block_index.records;
+        let header = &records[height].block_header;
+        let mut errors = Vec::new();
+
+        let bits = header.bits.to_consensus();
+        match decode_compact(bits) {
+            Some(target) => {
+                if !target.is_met_by(header.block_hash()) {
+                    errors.push(HeaderError::HighHash);
+                }
+            }
+            None => errors.push(HeaderError::InvalidBits { bits }),
+        }
+        if height == 0 {
+            return errors;
+        }
+
+        let prev = &records[height - 1].block_header;
+        if header.prev_blockhash != prev.block_hash() {
+            errors.push(HeaderError::BadPrevBlock);
+        }
+        if let Some(expected) = self.next_work_required(height) {
+            if expected != bits {
+                errors.push(HeaderError::BadDiffBits {
+                    expected,
+                    found: bits,
+                });
+            }
+        }
+        if let Some(median_time_past) = self.block_index.median_time_past(height - 1) {
+            if header.time <= median_time_past {
+                errors.push(HeaderError::TimeTooOld {
+                    time: header.time,
+                    median_time_past,
+                });
+            }
+        }
+        let max_time = self.now.saturating_add(MAX_FUTURE_BLOCK_TIME);
+        if header.time > max_time {
+            errors.push(HeaderError::TimeTooNew {
+                time: header.time,
+                max_time,
+            });
+        }
+        let version = header.version.to_consensus();
+        if (version < 2 && height >= BIP34_HEIGHT)
+            || (version < 3 && height >= BIP66_HEIGHT)
+            || (version < 4 && height >= BIP65_HEIGHT)
+        {
+            errors.push(HeaderError::BadVersion { version });
+        }
+        errors
+    }
+
+    /// The `bits` required for the block at `height` (`GetNextWorkRequired`),
+    /// `None` for the genesis block or unknown heights.
+    pub fn next_work_required(&self, height: usize) -> Option<u32> {
+        if height == 0 {
+            return None;
+        }
+        let records = &self.block_index.records;
+        let last: &BlockHeader = &records.get(height - 1)?.block_header;
+        if height % DIFFICULTY_ADJUSTMENT_INTERVAL != 0 {
+            return Some(last.bits.to_consensus());
+        }
+        let first = &records
+            .get(height - DIFFICULTY_ADJUSTMENT_INTERVAL)?
+            .block_header;
+        let timespan = last.time as i64 - first.time as i64;
+        Some(calculate_next_work_required(
+            last.bits.to_consensus(),
+            timespan,
+        ))
+    }
+}
+
+/// Retarget from `bits` given the time taken by the last 2016 blocks
+/// (`CalculateNextWorkRequired`).
+fn calculate_next_work_required(bits: u32, timespan: i64) -> u32 {
+    let min = POW_TARGET_TIMESPAN as i64 / 4;
+    let max = POW_TARGET_TIMESPAN as i64 * 4;
+    let timespan = timespan.clamp(min, max) as u64;
+    let target = Target::from_compact(CompactTarget::from_consensus(bits)).to_be_bytes();
+    let mut new = div_small(mul_small(target, timespan), POW_TARGET_TIMESPAN);
+    if new > POW_LIMIT {
+        new = POW_LIMIT;
+    }
+    Target::from_be_bytes(new).to_compact_lossy().to_consensus()
+}
+
+/// Target of compact `bits` if it is positive, does not overflow,
+/// and is within the proof of work limit (`CheckProofOfWork`).
+fn decode_compact(bits: u32) -> Option<Target> {
+    let size = bits >> 24;
+    let word = bits & 0x007fffff;
+    let negative = word != 0 && bits & 0x00800000 != 0;
+    let overflow =
+        word != 0 && (size > 34 || (word > 0xff && size > 33) || (word > 0xffff && size > 32));
+    if negative || overflow || word == 0 {
+        return None;
+    }
+    let target = Target::from_compact(CompactTarget::from_consensus(bits));
+    if target == Target::ZERO || target.to_be_bytes() > POW_LIMIT {
+        return None;
+    }
+    Some(target)
+}
+
+/// Multiply a big-endian 256-bit integer, overflow is discarded.
+fn mul_small(mut n: [u8; 32], k: u64) -> [u8; 32] {
+    let mut carry: u128 = 0;
+    for byte in n.iter_mut().rev() {
+        let v = *byte as u128 * k as u128 + carry;
+        *byte = v as u8;
+        carry = v >> 8;
+    }
+    n
+}
+
+/// Divide a big-endian 256-bit integer, rounding down.
+fn div_small(mut n: [u8; 32], d: u64) -> [u8; 32] {
+    let mut rem: u128 = 0;
+    for byte in n.iter_mut() {
+        let v = (rem << 8) | *byte as u128;
+        *byte = (v / d as u128) as u8;
+        rem = v % d as u128;
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calculate_next_work_required() {
+        // slow blocks are capped at the proof of work limit
+        assert_eq!(
+            calculate_next_work_required(0x1d00ffff, 4 * POW_TARGET_TIMESPAN as i64),
+            0x1d00ffff
+        );
+        // blocks twice as fast halve the target
+        assert_eq!(
+            calculate_next_work_required(0x1d00ffff, POW_TARGET_TIMESPAN as i64 / 2),
+            0x1c7fff80
+        );
+        // adjustments are limited to a factor of 4
+        assert_eq!(
+            calculate_next_work_required(0x1c7fff80, 0),
+            calculate_next_work_required(0x1c7fff80, POW_TARGET_TIMESPAN as i64 / 4)
+        );
+        // first difficulty change of mainnet, at height 32256
+        assert_eq!(
+            calculate_next_work_required(0x1d00ffff, 1262152739 - 1261130161),
+            0x1d00d86a
+        );
+    }
+
+    #[test]
+    fn test_decode_compact() {
+        assert!(decode_compact(0x1d00ffff).is_some());
+        assert!(decode_compact(0x1d010000).is_none());
+        assert!(decode_compact(0x1c800001).is_none());
+        assert!(decode_compact(0x22010000).is_none());
+        assert!(decode_compact(0x00000000).is_none());
+    }
+
+    #[test]
+    fn test_big_int() {
+        let mut n = [0u8; 32];
+        n[31] = 200;
+        let m = mul_small(n, 1000);
+        assert_eq!(m[29..], [0x03, 0x0d, 0x40]);
+        assert_eq!(div_small(m, 1000), n);
+    }
+}
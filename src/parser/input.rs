@@ -0,0 +1,552 @@
+//! Classify how transaction inputs are spent.
+//!
+//! The spending pattern is recognised from the `script_sig` and the witness.
+//! When the output being spent is known, its `script_pubkey` decides the
+//! type, otherwise the type is guessed from the shape of the input data.
+//!
+//! Spends of P2SH, P2WSH and taproot script paths also reveal the script
+//! committed to by the output, which is decoded in [`RevealedScripts`].
+
+use crate::parser::asm::{script_instructions, script_to_asm, ScriptInstruction};
+use crate::parser::script::{evaluate_script, parse_multisig, MultiSig, ScriptInfo, ScriptType};
+use bitcoin::blockdata::opcodes::all;
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::taproot::ControlBlock;
+use bitcoin::{Network, Script, ScriptBuf, TxIn};
+use serde::{Deserialize, Serialize};
+
+/// First byte of a taproot annex (BIP341).
+const ANNEX_TAG: u8 = 0x50;
+/// Size of a taproot control block without merkle path (BIP341).
+const TAPROOT_CONTROL_BASE_SIZE: usize = 33;
+const TAPROOT_CONTROL_NODE_SIZE: usize = 32;
+const TAPROOT_CONTROL_MAX_NODE_COUNT: usize = 128;
+
+/// Different ways of spending an output.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum InputType {
+    Pay2PublicKey,
+    Pay2PublicKeyHash,
+    Pay2MultiSig,
+    /// P2SH with a redeem script other than a witness program.
+    Pay2ScriptHash,
+    /// P2SH-P2WPKH.
+    NestedPay2WitnessPublicKeyHash,
+    /// P2SH-P2WSH.
+    NestedPay2WitnessScriptHash,
+    Pay2WitnessPublicKeyHash,
+    Pay2WitnessScriptHash,
+    /// Taproot spend with a single signature for the output key.
+    Pay2TaprootKeyPath,
+    /// Taproot spend revealing a leaf script and its control block.
+    Pay2TaprootScriptPath,
+    Pay2Anchor,
+    /// Spend of a witness program of a version without defined semantics.
+    WitnessUnknown {
+        version: u8,
+    },
+    NotRecognised,
+}
+
+impl std::fmt::Display for InputType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Pay2PublicKey => write!(f, "Pay2PublicKey"),
+            Self::Pay2PublicKeyHash => write!(f, "Pay2PublicKeyHash"),
+            Self::Pay2MultiSig => write!(f, "Pay2MultiSig"),
+            Self::Pay2ScriptHash => write!(f, "Pay2ScriptHash"),
+            Self::NestedPay2WitnessPublicKeyHash => write!(f, "NestedPay2WitnessPublicKeyHash"),
+            Self::NestedPay2WitnessScriptHash => write!(f, "NestedPay2WitnessScriptHash"),
+            Self::Pay2WitnessPublicKeyHash => write!(f, "Pay2WitnessPublicKeyHash"),
+            Self::Pay2WitnessScriptHash => write!(f, "Pay2WitnessScriptHash"),
+            Self::Pay2TaprootKeyPath => write!(f, "Pay2TaprootKeyPath"),
+            Self::Pay2TaprootScriptPath => write!(f, "Pay2TaprootScriptPath"),
+            Self::Pay2Anchor => write!(f, "Pay2Anchor"),
+            Self::WitnessUnknown { version } => write!(f, "WitnessUnknown({version})"),
+            Self::NotRecognised => write!(f, "NotRecognised"),
+        }
+    }
+}
+
+impl InputType {
+    /// Classify an input.
+    ///
+    /// `prevout` is the `script_pubkey` of the spent output, if known.
+    /// Without it, the type is guessed from `script_sig` and the witness,
+    /// and spends that carry no data (e.g. pay-to-anchor) are not recognised.
+    pub fn classify(tx_in: &TxIn, prevout: Option<&Script>) -> Self {
+        match prevout {
+            Some(script_pubkey) => classify_with_prevout(tx_in, script_pubkey),
+            None => classify_without_prevout(tx_in),
+        }
+    }
+}
+
+/// A script revealed by an input, committed to by the hash in the spent output.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct RevealedScript {
+    pub script: ScriptBuf,
+    /// Type and addresses of the revealed script.
+    pub info: ScriptInfo,
+    /// Set if the revealed script is multisig.
+    pub multisig: Option<MultiSig>,
+}
+
+impl RevealedScript {
+    fn new(bytes: &[u8]) -> Self {
+        let script = ScriptBuf::from_bytes(bytes.to_vec());
+        Self {
+            info: evaluate_script(&script, Network::Bitcoin),
+            multisig: parse_multisig(&script),
+            script,
+        }
+    }
+
+    /// The script in Bitcoin Core's ASM format.
+    pub fn asm(&self) -> String {
+        script_to_asm(&self.script, false)
+    }
+
+    /// The script as a list of instructions.
+    pub fn instructions(&self) -> Vec<ScriptInstruction> {
+        script_instructions(&self.script)
+    }
+}
+
+/// Witness of a taproot script-path spend (BIP341).
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct TapScriptPath {
+    /// The leaf script being executed.
+    pub leaf_script: RevealedScript,
+    /// Leaf version, internal key, output key parity and merkle path.
+    pub control_block: ControlBlock,
+}
+
+impl TapScriptPath {
+    /// Leaf version, `0xc0` for tapscript.
+    pub fn leaf_version(&self) -> u8 {
+        self.control_block.leaf_version.to_consensus()
+    }
+
+    /// Depth of the leaf in the script tree.
+    pub fn depth(&self) -> usize {
+        self.control_block.merkle_branch.len()
+    }
+
+    /// Whether the leaf script contains an ordinal inscription envelope:
+    /// `OP_FALSE OP_IF OP_PUSH "ord"`.
+    pub fn has_inscription_envelope(&self) -> bool {
+        let ops: Vec<Instruction> = self
+            .leaf_script
+            .script
+            .instructions()
+            .filter_map(|o| o.ok())
+            .collect();
+        ops.windows(3).any(|w| match w {
+            [Instruction::PushBytes(f), Instruction::Op(op), Instruction::PushBytes(tag)] => {
+                f.is_empty() && *op == all::OP_IF && tag.as_bytes() == b"ord"
+            }
+            _ => false,
+        })
+    }
+}
+
+/// Scripts revealed by an input.
+#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct RevealedScripts {
+    /// Last `script_sig` push of P2SH spends, including nested segwit.
+    pub redeem_script: Option<RevealedScript>,
+    /// Last witness element of P2WSH spends, including P2SH-P2WSH.
+    pub witness_script: Option<RevealedScript>,
+    /// Leaf script and control block of taproot script-path spends.
+    pub tap_script: Option<TapScriptPath>,
+    /// Annex of taproot spends, including its `0x50` tag.
+    pub annex: Option<Vec<u8>>,
+}
+
+impl RevealedScripts {
+    /// Extract the scripts revealed by an input spent as `input_type`.
+    pub fn extract(tx_in: &TxIn, input_type: &InputType) -> Self {
+        let redeem_script = match input_type {
+            InputType::Pay2ScriptHash
+            | InputType::NestedPay2WitnessPublicKeyHash
+            | InputType::NestedPay2WitnessScriptHash => {
+                pushes(&tx_in.script_sig).and_then(|p| p.last().map(|s| RevealedScript::new(s)))
+            }
+            _ => None,
+        };
+        let witness_script = match input_type {
+            InputType::Pay2WitnessScriptHash | InputType::NestedPay2WitnessScriptHash => {
+                tx_in.witness.last().map(RevealedScript::new)
+            }
+            _ => None,
+        };
+        let (tap_script, annex) = match input_type {
+            InputType::Pay2TaprootKeyPath | InputType::Pay2TaprootScriptPath => {
+                let witness = taproot_witness(tx_in);
+                let annex = if witness.len() < tx_in.witness.len() {
+                    tx_in.witness.last().map(|a| a.to_vec())
+                } else {
+                    None
+                };
+                let tap_script = match witness.as_slice() {
+                    [.., leaf_script, control_block]
+                        if *input_type == InputType::Pay2TaprootScriptPath =>
+                    {
+                        ControlBlock::decode(control_block)
+                            .ok()
+                            .map(|control_block| TapScriptPath {
+                                leaf_script: RevealedScript::new(leaf_script),
+                                control_block,
+                            })
+                    }
+                    _ => None,
+                };
+                (tap_script, annex)
+            }
+            _ => (None, None),
+        };
+        Self {
+            redeem_script,
+            witness_script,
+            tap_script,
+            annex,
+        }
+    }
+}
+
+fn classify_with_prevout(tx_in: &TxIn, script_pubkey: &Script) -> InputType {
+    match evaluate_script(script_pubkey, Network::Bitcoin).pattern {
+        ScriptType::Pay2PublicKey => InputType::Pay2PublicKey,
+        ScriptType::Pay2PublicKeyHash => InputType::Pay2PublicKeyHash,
+        ScriptType::Pay2MultiSig => InputType::Pay2MultiSig,
+        ScriptType::Pay2ScriptHash => {
+            match pushes(&tx_in.script_sig).and_then(|p| p.last().copied()) {
+                Some(redeem_script) => nested_type(Script::from_bytes(redeem_script)),
+                None => InputType::Pay2ScriptHash,
+            }
+        }
+        ScriptType::Pay2WitnessPublicKeyHash => InputType::Pay2WitnessPublicKeyHash,
+        ScriptType::Pay2WitnessScriptHash => InputType::Pay2WitnessScriptHash,
+        ScriptType::Pay2Taproot => match taproot_witness(tx_in).len() {
+            0 => InputType::NotRecognised,
+            1 => InputType::Pay2TaprootKeyPath,
+            _ => InputType::Pay2TaprootScriptPath,
+        },
+        ScriptType::Pay2Anchor => InputType::Pay2Anchor,
+        ScriptType::WitnessUnknown { version } => InputType::WitnessUnknown { version },
+        ScriptType::OpReturn | ScriptType::NotRecognised => InputType::NotRecognised,
+    }
+}
+
+fn classify_without_prevout(tx_in: &TxIn) -> InputType {
+    let pushes = match pushes(&tx_in.script_sig) {
+        Some(pushes) => pushes,
+        None => return InputType::NotRecognised,
+    };
+
+    // native segwit
+    if pushes.is_empty() {
+        if tx_in.witness.is_empty() {
+            return InputType::NotRecognised;
+        }
+        if tx_in.witness.len() == 2
+            && is_signature(&tx_in.witness[0])
+            && is_public_key(&tx_in.witness[1])
+        {
+            return InputType::Pay2WitnessPublicKeyHash;
+        }
+        let witness = taproot_witness(tx_in);
+        if witness.len() == 1 && matches!(witness[0].len(), 64 | 65) {
+            return InputType::Pay2TaprootKeyPath;
+        }
+        if witness.len() >= 2 && is_control_block(witness.last().unwrap()) {
+            return InputType::Pay2TaprootScriptPath;
+        }
+        return InputType::Pay2WitnessScriptHash;
+    }
+
+    let last = pushes.last().unwrap();
+
+    // nested segwit
+    if pushes.len() == 1 && !tx_in.witness.is_empty() {
+        let redeem_script = Script::from_bytes(last);
+        if redeem_script.is_p2wpkh() || redeem_script.is_p2wsh() {
+            return nested_type(redeem_script);
+        }
+    }
+
+    if pushes.len() == 1 && is_signature(last) {
+        return InputType::Pay2PublicKey;
+    }
+    if pushes.len() == 2 && is_signature(pushes[0]) && is_public_key(last) {
+        return InputType::Pay2PublicKeyHash;
+    }
+    // the redeem script of P2SH is itself a recognised script.
+    if evaluate_script(Script::from_bytes(last), Network::Bitcoin).pattern
+        != ScriptType::NotRecognised
+    {
+        return InputType::Pay2ScriptHash;
+    }
+    // OP_0 followed by signatures, because of the extra pop of OP_CHECKMULTISIG.
+    if pushes.len() >= 2 && pushes[0].is_empty() && pushes[1..].iter().all(|p| is_signature(p)) {
+        return InputType::Pay2MultiSig;
+    }
+    InputType::NotRecognised
+}
+
+/// Type of a P2SH spend with the given redeem script.
+#[inline]
+fn nested_type(redeem_script: &Script) -> InputType {
+    if redeem_script.is_p2wpkh() {
+        InputType::NestedPay2WitnessPublicKeyHash
+    } else if redeem_script.is_p2wsh() {
+        InputType::NestedPay2WitnessScriptHash
+    } else {
+        InputType::Pay2ScriptHash
+    }
+}
+
+/// Data pushed by a push-only script, `None` if the script has other opcodes.
+pub(crate) fn pushes(script: &Script) -> Option<Vec<&[u8]>> {
+    script
+        .instructions()
+        .map(|i| match i {
+            Ok(Instruction::PushBytes(b)) => Some(b.as_bytes()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Witness stack without the annex of taproot spends.
+pub(crate) fn taproot_witness(tx_in: &TxIn) -> Vec<&[u8]> {
+    let mut witness: Vec<&[u8]> = tx_in.witness.iter().collect();
+    if witness.len() >= 2 && witness.last().unwrap().first() == Some(&ANNEX_TAG) {
+        witness.pop();
+    }
+    witness
+}
+
+/// Taproot control block: a leaf version byte, an internal key, and a merkle path.
+pub(crate) fn is_control_block(data: &[u8]) -> bool {
+    data.len() >= TAPROOT_CONTROL_BASE_SIZE
+        && (data.len() - TAPROOT_CONTROL_BASE_SIZE) % TAPROOT_CONTROL_NODE_SIZE == 0
+        && (data.len() - TAPROOT_CONTROL_BASE_SIZE) / TAPROOT_CONTROL_NODE_SIZE
+            <= TAPROOT_CONTROL_MAX_NODE_COUNT
+        && data[0] & 0xfe == 0xc0
+}
+
+/// DER encoded ECDSA signature followed by a sighash flag.
+#[inline]
+fn is_signature(data: &[u8]) -> bool {
+    (9..=73).contains(&data.len()) && data[0] == 0x30
+}
+
+/// Compressed or uncompressed public key.
+#[inline]
+fn is_public_key(data: &[u8]) -> bool {
+    matches!(
+        (data.len(), data.first()),
+        (33, Some(0x02 | 0x03)) | (65, Some(0x04))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::Witness;
+
+    fn tx_in(script_sig: Vec<u8>, witness: Vec<Vec<u8>>) -> TxIn {
+        TxIn {
+            script_sig: ScriptBuf::from_bytes(script_sig),
+            witness: Witness::from_slice(&witness),
+            ..Default::default()
+        }
+    }
+
+    fn push(data: &[u8]) -> Vec<u8> {
+        let mut v = vec![data.len() as u8];
+        v.extend(data);
+        v
+    }
+
+    fn signature() -> Vec<u8> {
+        let mut sig = vec![0x30; 71];
+        sig[70] = 0x01;
+        sig
+    }
+
+    fn public_key() -> Vec<u8> {
+        Vec::from_hex("022df8750480ad5b26950b25c7ba79d3e37d75f640f8e5d9bcd5b150a0f85014da").unwrap()
+    }
+
+    #[test]
+    fn test_classify_p2pkh() {
+        let input = tx_in([push(&signature()), push(&public_key())].concat(), vec![]);
+        let mut p2pkh = vec![0x76, 0xa9, 0x14];
+        p2pkh.extend([0x22; 20]);
+        p2pkh.extend([0x88, 0xac]);
+        assert_eq!(
+            InputType::classify(&input, None),
+            InputType::Pay2PublicKeyHash
+        );
+        assert_eq!(
+            InputType::classify(&input, Some(Script::from_bytes(&p2pkh))),
+            InputType::Pay2PublicKeyHash
+        );
+    }
+
+    #[test]
+    fn test_classify_segwit() {
+        let p2wpkh = tx_in(vec![], vec![signature(), public_key()]);
+        assert_eq!(
+            InputType::classify(&p2wpkh, None),
+            InputType::Pay2WitnessPublicKeyHash
+        );
+
+        let mut redeem_script = vec![0x00, 0x14];
+        redeem_script.extend([0x22; 20]);
+        let nested = tx_in(push(&redeem_script), vec![signature(), public_key()]);
+        assert_eq!(
+            InputType::classify(&nested, None),
+            InputType::NestedPay2WitnessPublicKeyHash
+        );
+        let mut p2sh = vec![0xa9, 0x14];
+        p2sh.extend([0x33; 20]);
+        p2sh.push(0x87);
+        assert_eq!(
+            InputType::classify(&nested, Some(Script::from_bytes(&p2sh))),
+            InputType::NestedPay2WitnessPublicKeyHash
+        );
+    }
+
+    #[test]
+    fn test_classify_taproot() {
+        let key_path = tx_in(vec![], vec![vec![0x44; 64]]);
+        assert_eq!(
+            InputType::classify(&key_path, None),
+            InputType::Pay2TaprootKeyPath
+        );
+
+        // leaf script, control block and annex
+        let mut control_block = vec![0xc0];
+        control_block.extend([0x55; 32 + 32]);
+        let script_path = tx_in(
+            vec![],
+            vec![
+                vec![0x44; 64],
+                vec![0x51],
+                control_block,
+                vec![ANNEX_TAG, 0x00],
+            ],
+        );
+        assert_eq!(
+            InputType::classify(&script_path, None),
+            InputType::Pay2TaprootScriptPath
+        );
+        let mut p2tr = vec![0x51, 0x20];
+        p2tr.extend([0x66; 32]);
+        assert_eq!(
+            InputType::classify(&script_path, Some(Script::from_bytes(&p2tr))),
+            InputType::Pay2TaprootScriptPath
+        );
+
+        let anchor = tx_in(vec![], vec![]);
+        assert_eq!(InputType::classify(&anchor, None), InputType::NotRecognised);
+        assert_eq!(
+            InputType::classify(&anchor, Some(Script::from_bytes(&[0x51, 0x02, 0x4e, 0x73]))),
+            InputType::Pay2Anchor
+        );
+    }
+
+    #[test]
+    fn test_revealed_scripts() {
+        // 1-of-1 multisig behind P2SH-P2WSH
+        let mut witness_script = vec![0x51, 0x21];
+        witness_script.extend(public_key());
+        witness_script.extend([0x51, 0xae]);
+        let mut redeem_script = vec![0x00, 0x20];
+        redeem_script.extend([0x22; 32]);
+        let input = tx_in(
+            push(&redeem_script),
+            vec![vec![], signature(), witness_script.clone()],
+        );
+        let input_type = InputType::classify(&input, None);
+        assert_eq!(input_type, InputType::NestedPay2WitnessScriptHash);
+
+        let revealed = RevealedScripts::extract(&input, &input_type);
+        let redeem = revealed.redeem_script.unwrap();
+        assert_eq!(redeem.script.as_bytes(), redeem_script.as_slice());
+        assert_eq!(redeem.info.pattern, ScriptType::Pay2WitnessScriptHash);
+        let witness = revealed.witness_script.unwrap();
+        assert_eq!(witness.script.as_bytes(), witness_script.as_slice());
+        assert_eq!(witness.info.pattern, ScriptType::Pay2MultiSig);
+        let multisig = witness.multisig.unwrap();
+        assert_eq!((multisig.required, multisig.total), (1, 1));
+        assert_eq!(multisig.public_keys[0].to_bytes(), public_key());
+
+        let p2pkh = tx_in([push(&signature()), push(&public_key())].concat(), vec![]);
+        let revealed = RevealedScripts::extract(&p2pkh, &InputType::Pay2PublicKeyHash);
+        assert_eq!(revealed, RevealedScripts::default());
+    }
+
+    #[test]
+    fn test_tap_script_path() {
+        // <key> OP_CHECKSIG OP_FALSE OP_IF "ord" OP_ENDIF
+        let mut leaf_script = vec![0x20];
+        leaf_script.extend(&public_key()[1..]);
+        leaf_script.extend([0xac, 0x00, 0x63, 0x03, b'o', b'r', b'd', 0x68]);
+        // leaf version 0xc0 with odd parity, internal key, and a merkle path of 2 nodes
+        let mut control_block = vec![0xc1];
+        control_block.extend(&public_key()[1..]);
+        control_block.extend([0x77; 64]);
+        let annex = vec![ANNEX_TAG, 0x01];
+        let input = tx_in(
+            vec![],
+            vec![
+                vec![0x44; 64],
+                leaf_script.clone(),
+                control_block,
+                annex.clone(),
+            ],
+        );
+        let input_type = InputType::classify(&input, None);
+        assert_eq!(input_type, InputType::Pay2TaprootScriptPath);
+
+        let revealed = RevealedScripts::extract(&input, &input_type);
+        assert_eq!(revealed.annex, Some(annex));
+        let tap_script = revealed.tap_script.unwrap();
+        assert_eq!(
+            tap_script.leaf_script.script.as_bytes(),
+            leaf_script.as_slice()
+        );
+        assert_eq!(tap_script.leaf_version(), 0xc0);
+        assert_eq!(tap_script.depth(), 2);
+        assert_eq!(
+            tap_script.control_block.internal_key.serialize(),
+            public_key()[1..]
+        );
+        assert!(tap_script.has_inscription_envelope());
+
+        let key_path = tx_in(vec![], vec![vec![0x44; 64]]);
+        let revealed = RevealedScripts::extract(&key_path, &InputType::Pay2TaprootKeyPath);
+        assert_eq!(revealed, RevealedScripts::default());
+    }
+
+    #[test]
+    fn test_classify_multisig() {
+        let bare = tx_in([vec![0x00], push(&signature())].concat(), vec![]);
+        assert_eq!(InputType::classify(&bare, None), InputType::Pay2MultiSig);
+
+        // 1-of-1 multisig behind P2SH
+        let mut redeem_script = vec![0x51, 0x21];
+        redeem_script.extend(public_key());
+        redeem_script.extend([0x51, 0xae]);
+        let p2sh = tx_in(
+            [vec![0x00], push(&signature()), push(&redeem_script)].concat(),
+            vec![],
+        );
+        assert_eq!(InputType::classify(&p2sh, None), InputType::Pay2ScriptHash);
+    }
+}
@@ -0,0 +1,245 @@
+//! Read the mempool persisted by Bitcoin Core (`mempool.dat`).
+//!
+//! Since Bitcoin Core 28.0, everything following the file version and the
+//! XOR key is obfuscated in the same way as `blk*.dat` files.
+//!
+//! See [mempool_persist.cpp](https://github.com/bitcoin/bitcoin/blob/master/src/node/mempool_persist.cpp).
+
+use crate::parser::error::{Error, Result};
+use crate::parser::xor::{XorReader, XOR_MASK_LEN};
+use bitcoin::consensus::encode::VarInt;
+use bitcoin::consensus::Decodable;
+use bitcoin::io::Cursor;
+use bitcoin::{Transaction, TxOut, Txid};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Seek};
+use std::path::Path;
+
+/// Mempool file without XOR obfuscation (before Bitcoin Core 28.0).
+const MEMPOOL_DUMP_VERSION_NO_XOR_KEY: u64 = 1;
+/// Mempool file with XOR obfuscation.
+const MEMPOOL_DUMP_VERSION: u64 = 2;
+
+/// A transaction persisted in `mempool.dat`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MempoolEntry {
+    pub tx: Transaction,
+    /// Time the transaction entered the mempool, in seconds since epoch.
+    pub time: i64,
+    /// Fee delta set by `prioritisetransaction`, in satoshi.
+    pub fee_delta: i64,
+}
+
+/// Content of `mempool.dat`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MempoolFile {
+    pub version: u64,
+    /// Transactions in the mempool.
+    pub entries: Vec<MempoolEntry>,
+    /// Fee deltas of transactions that are not in the mempool (`mapDeltas`).
+    pub fee_deltas: BTreeMap<Txid, i64>,
+    /// Transactions submitted locally but not yet announced to any peer.
+    pub unbroadcast_txids: BTreeSet<Txid>,
+}
+
+impl MempoolFile {
+    /// Read and decode a `mempool.dat` file.
+    ///
+    /// # Arguments
+    ///
+    /// `path`: Path of `bitcoin_core_data_dir/mempool.dat`.
+    pub fn read(path: &Path) -> Result<Self> {
+        let mut file = std::fs::File::open(path)?;
+        let mut buf = [0u8; 8];
+        file.read_exact(&mut buf)?;
+        let version = u64::from_le_bytes(buf);
+
+        let xor_mask = match version {
+            MEMPOOL_DUMP_VERSION_NO_XOR_KEY => None,
+            MEMPOOL_DUMP_VERSION => {
+                // the XOR key is serialized as a byte vector.
+                let mut len = [0u8; 1];
+                file.read_exact(&mut len)?;
+                if len[0] as usize != XOR_MASK_LEN {
+                    return Err(Error::InvalidMempoolFile(format!(
+                        "unexpected XOR key length {}",
+                        len[0]
+                    )));
+                }
+                let mut mask = [0u8; XOR_MASK_LEN];
+                file.read_exact(&mut mask)?;
+                Some(mask)
+            }
+            v => {
+                return Err(Error::InvalidMempoolFile(format!(
+                    "unsupported version {v}"
+                )))
+            }
+        };
+
+        // the XOR key is applied based on the position in the file,
+        // querying the position synchronizes it with the reader.
+        let mut r = XorReader::new(file, xor_mask);
+        r.stream_position()?;
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+
+        let mut reader = Cursor::new(data);
+        let count = u64::consensus_decode(&mut reader)?;
+        let mut entries = Vec::with_capacity(count.min(1 << 20) as usize);
+        for _ in 0..count {
+            let tx = Transaction::consensus_decode(&mut reader)?;
+            let time = i64::consensus_decode(&mut reader)?;
+            let fee_delta = i64::consensus_decode(&mut reader)?;
+            entries.push(MempoolEntry {
+                tx,
+                time,
+                fee_delta,
+            });
+        }
+
+        let n_deltas = VarInt::consensus_decode(&mut reader)?.0;
+        let mut fee_deltas = BTreeMap::new();
+        for _ in 0..n_deltas {
+            let txid = Txid::consensus_decode(&mut reader)?;
+            let delta = i64::consensus_decode(&mut reader)?;
+            fee_deltas.insert(txid, delta);
+        }
+
+        let n_unbroadcast = VarInt::consensus_decode(&mut reader)?.0;
+        let mut unbroadcast_txids = BTreeSet::new();
+        for _ in 0..n_unbroadcast {
+            unbroadcast_txids.insert(Txid::consensus_decode(&mut reader)?);
+        }
+
+        Ok(Self {
+            version,
+            entries,
+            fee_deltas,
+            unbroadcast_txids,
+        })
+    }
+}
+
+/// A mempool transaction with its inputs connected to the spent outputs.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ConnectedMempoolEntry {
+    pub txid: Txid,
+    pub tx: Transaction,
+    pub time: i64,
+    pub fee_delta: i64,
+    /// Outputs spent by each input, `None` if it cannot be found.
+    ///
+    /// Outputs are looked up in the other mempool transactions and in tx-index.
+    pub prevouts: Vec<Option<TxOut>>,
+    /// Virtual size in vbytes.
+    pub vsize: u64,
+    /// Fee in satoshi, `None` unless all inputs are connected.
+    pub fee: Option<u64>,
+}
+
+impl ConnectedMempoolEntry {
+    pub(crate) fn new(entry: MempoolEntry, prevouts: Vec<Option<TxOut>>) -> Self {
+        let input_value = prevouts
+            .iter()
+            .map(|p| p.as_ref().map(|o| o.value.to_sat()))
+            .sum::<Option<u64>>();
+        let output_value: u64 = entry.tx.output.iter().map(|o| o.value.to_sat()).sum();
+        Self {
+            txid: entry.tx.compute_txid(),
+            vsize: entry.tx.weight().to_vbytes_ceil(),
+            fee: input_value.and_then(|v| v.checked_sub(output_value)),
+            tx: entry.tx,
+            time: entry.time,
+            fee_delta: entry.fee_delta,
+            prevouts,
+        }
+    }
+
+    /// Fee rate in sat/vB, `None` unless all inputs are connected.
+    pub fn fee_rate(&self) -> Option<f64> {
+        self.fee.map(|fee| fee as f64 / self.vsize as f64)
+    }
+
+    /// Fee rate including the `prioritisetransaction` delta, as used by
+    /// the block template of the node.
+    pub fn modified_fee_rate(&self) -> Option<f64> {
+        self.fee
+            .map(|fee| (fee as i64 + self.fee_delta) as f64 / self.vsize as f64)
+    }
+}
+
+/// Index all outputs created by mempool transactions, so that inputs
+/// spending unconfirmed outputs can be connected.
+pub(crate) fn mempool_outputs(entries: &[MempoolEntry]) -> BTreeMap<(Txid, u32), TxOut> {
+    let mut outputs = BTreeMap::new();
+    for entry in entries {
+        let txid = entry.tx.compute_txid();
+        for (n, o) in (0_u32..).zip(entry.tx.output.iter()) {
+            outputs.insert((txid, n), o.clone());
+        }
+    }
+    outputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Obfuscate `data` as if it starts at `offset` in the file.
+    fn xor(data: &mut [u8], mask: &[u8; XOR_MASK_LEN], offset: usize) {
+        for (i, b) in data.iter_mut().enumerate() {
+            *b ^= mask[(offset + i) % XOR_MASK_LEN];
+        }
+    }
+
+    #[test]
+    fn test_read_mempool_file() {
+        use bitcoin::consensus::Encodable;
+
+        let tx = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![TxOut::NULL],
+        };
+        let txid = tx.compute_txid();
+
+        let mut body = Vec::new();
+        1_u64.consensus_encode(&mut body).unwrap();
+        tx.consensus_encode(&mut body).unwrap();
+        1_700_000_000_i64.consensus_encode(&mut body).unwrap();
+        (-500_i64).consensus_encode(&mut body).unwrap();
+        // mapDeltas
+        VarInt(1).consensus_encode(&mut body).unwrap();
+        txid.consensus_encode(&mut body).unwrap();
+        1000_i64.consensus_encode(&mut body).unwrap();
+        // unbroadcast txids
+        VarInt(1).consensus_encode(&mut body).unwrap();
+        txid.consensus_encode(&mut body).unwrap();
+
+        let mask = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
+        let mut header = Vec::new();
+        header.extend(MEMPOOL_DUMP_VERSION.to_le_bytes());
+        header.push(XOR_MASK_LEN as u8);
+        header.extend(mask);
+        xor(&mut body, &mask, header.len());
+
+        let path = std::env::temp_dir().join(format!("mempool-{}.dat", std::process::id()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(&header).unwrap();
+        file.write_all(&body).unwrap();
+        drop(file);
+
+        let mempool = MempoolFile::read(&path).unwrap();
+        assert_eq!(mempool.version, MEMPOOL_DUMP_VERSION);
+        assert_eq!(mempool.entries.len(), 1);
+        assert_eq!(mempool.entries[0].tx, tx);
+        assert_eq!(mempool.entries[0].time, 1_700_000_000);
+        assert_eq!(mempool.entries[0].fee_delta, -500);
+        assert_eq!(mempool.fee_deltas.get(&txid), Some(&1000));
+        assert!(mempool.unbroadcast_txids.contains(&txid));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+//! A pool of memory-mapped blk files.
+//!
+//! Random reads (`get_transaction`, connecting inputs through the txindex)
+//! open, seek and read a blk file for every call. With a [`MmapPool`],
+//! the most recently used files stay mapped, and reading from them is a
+//! memory copy.
+
+use crate::parser::error::Result;
+use memmap2::Mmap;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// The least recently used blk files, mapped in memory.
+#[derive(Debug)]
+pub(crate) struct MmapPool {
+    capacity: usize,
+    /// most recently used first.
+    files: Mutex<VecDeque<(i32, Arc<Mmap>)>>,
+}
+
+impl MmapPool {
+    /// Keep at most `capacity` files mapped (at least one).
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            files: Mutex::new(VecDeque::with_capacity(capacity.max(1))),
+        }
+    }
+
+    /// The mapping of blk file `n_file`, mapping `path` if it is not in the pool.
+    pub(crate) fn get(&self, n_file: i32, path: &Path) -> Result<Arc<Mmap>> {
+        if let Some(map) = self.lookup(n_file)? {
+            return Ok(map);
+        }
+        let file = File::open(path)?;
+        // SAFETY: Bitcoin Core never modifies a block once written. It
+        // preallocates blk files and truncates the unused tail when it
+        // finalizes one, and blocks are only read below that tail. A file
+        // still being written keeps the length it had when mapped, reading
+        // past it fails and the caller remaps (see `remap`). Pruning deletes
+        // whole files; reading a pruned datadir is not supported.
+        let map = Arc::new(unsafe { Mmap::map(&file)? });
+
+        let mut files = self.files.lock()?;
+        // another thread may have mapped the same file meanwhile.
+        if let Some(i) = files.iter().position(|(n, _)| *n == n_file) {
+            let entry = files.remove(i).unwrap();
+            let map = entry.1.clone();
+            files.push_front(entry);
+            return Ok(map);
+        }
+        if files.len() == self.capacity {
+            files.pop_back();
+        }
+        files.push_front((n_file, map.clone()));
+        Ok(map)
+    }
+
+    /// Map `path` again, replacing the mapping of `n_file`, e.g. after the file grew.
+    pub(crate) fn remap(&self, n_file: i32, path: &Path) -> Result<Arc<Mmap>> {
+        self.files.lock()?.retain(|(n, _)| *n != n_file);
+        self.get(n_file, path)
+    }
+
+    fn lookup(&self, n_file: i32) -> Result<Option<Arc<Mmap>>> {
+        let mut files = self.files.lock()?;
+        Ok(match files.iter().position(|(n, _)| *n == n_file) {
+            Some(i) => {
+                let entry = files.remove(i).unwrap();
+                let map = entry.1.clone();
+                files.push_front(entry);
+                Some(map)
+            }
+            None => None,
+        })
+    }
+
+    #[cfg(test)]
+    fn mapped(&self) -> Vec<i32> {
+        self.files.lock().unwrap().iter().map(|(n, _)| *n).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru() {
+        let paths: Vec<_> = (0..3)
+            .map(|n| {
+                let name = format!("mmap-pool-{}-blk{n:05}.dat", std::process::id());
+                let path = std::env::temp_dir().join(name);
+                std::fs::write(&path, [n as u8; 16]).unwrap();
+                path
+            })
+            .collect();
+
+        let pool = MmapPool::new(2);
+        assert_eq!(pool.get(0, &paths[0]).unwrap()[..], [0; 16]);
+        assert_eq!(pool.get(1, &paths[1]).unwrap()[..], [1; 16]);
+        assert_eq!(pool.mapped(), vec![1, 0]);
+        pool.get(0, &paths[0]).unwrap();
+        assert_eq!(pool.mapped(), vec![0, 1]);
+        assert_eq!(pool.get(2, &paths[2]).unwrap()[..], [2; 16]);
+        assert_eq!(pool.mapped(), vec![2, 0]);
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
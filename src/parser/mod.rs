@@ -1,10 +1,21 @@
 //! This module defines how to parse binary data on disk to Block structs.
 
+pub mod asm;
 pub mod blk_file;
 pub mod block_index;
 pub mod block_types;
 pub mod error;
+pub mod fee_estimates;
+pub mod header_chain;
+pub mod input;
+pub mod mempool;
+#[cfg(feature = "mmap")]
+pub(crate) mod mmap;
+pub mod muhash;
+pub mod policy;
 pub mod reader;
+pub mod rpc;
 pub mod script;
 pub mod tx_index;
-pub(crate) mod xor;
+pub mod undo;
+pub mod xor;
@@ -0,0 +1,322 @@
+//! MuHash3072, the rolling set hash used by Bitcoin Core for the UTXO set.
+//!
+//! Elements are hashed into the multiplicative group of integers modulo
+//! the prime `2^3072 - 1103717`. Adding an element multiplies the numerator,
+//! removing it multiplies the denominator, so the hash of a set can be
+//! updated incrementally and in any order.
+//!
+//! Translated from Bitcoin Core:
+//! [muhash.cpp](https://github.com/bitcoin/bitcoin/blob/master/src/crypto/muhash.cpp).
+
+use bitcoin::hashes::{sha256, Hash};
+use std::convert::TryInto;
+use std::fmt;
+
+/// Number of 64-bit limbs in a [`Num3072`].
+const LIMBS: usize = 48;
+/// Size in bytes of a serialized [`Num3072`].
+const BYTE_SIZE: usize = 384;
+/// The modulus is `2^3072 - MAX_PRIME_DIFF`.
+const MAX_PRIME_DIFF: u64 = 1103717;
+
+/// An integer modulo `2^3072 - 1103717`, as little-endian limbs.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Num3072([u64; LIMBS]);
+
+impl Num3072 {
+    fn one() -> Self {
+        let mut limbs = [0u64; LIMBS];
+        limbs[0] = 1;
+        Self(limbs)
+    }
+
+    fn from_bytes(bytes: &[u8; BYTE_SIZE]) -> Self {
+        let mut limbs = [0u64; LIMBS];
+        for (limb, chunk) in limbs.iter_mut().zip(bytes.chunks_exact(8)) {
+            *limb = u64::from_le_bytes(chunk.try_into().expect("chunk has 8 bytes"));
+        }
+        let mut num = Self(limbs);
+        if num.is_overflow() {
+            num.full_reduce();
+        }
+        num
+    }
+
+    fn to_bytes(self) -> [u8; BYTE_SIZE] {
+        let mut bytes = [0u8; BYTE_SIZE];
+        for (chunk, limb) in bytes.chunks_exact_mut(8).zip(self.0.iter()) {
+            chunk.copy_from_slice(&limb.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Whether this number is not fully reduced, i.e., `self >= modulus`.
+    fn is_overflow(&self) -> bool {
+        self.0[0] > u64::MAX - MAX_PRIME_DIFF && self.0[1..].iter().all(|l| *l == u64::MAX)
+    }
+
+    /// Subtract the modulus, only valid when `is_overflow()`.
+    fn full_reduce(&mut self) {
+        // adding `MAX_PRIME_DIFF` and dropping the carry out of the top
+        // limb is the same as subtracting `2^3072 - MAX_PRIME_DIFF`.
+        self.add_small(MAX_PRIME_DIFF as u128);
+    }
+
+    /// Add a small value, returning the carry out of the top limb.
+    fn add_small(&mut self, mut carry: u128) -> u128 {
+        for limb in self.0.iter_mut() {
+            if carry == 0 {
+                break;
+            }
+            let t = *limb as u128 + carry;
+            *limb = t as u64;
+            carry = t >> 64;
+        }
+        carry
+    }
+
+    /// `self = self * other mod p`.
+    fn multiply(&mut self, other: &Self) {
+        let mut product = [0u64; 2 * LIMBS];
+        for (i, a) in self.0.iter().enumerate() {
+            let mut carry = 0u128;
+            for (j, b) in other.0.iter().enumerate() {
+                let t = product[i + j] as u128 + (*a as u128) * (*b as u128) + carry;
+                product[i + j] = t as u64;
+                carry = t >> 64;
+            }
+            product[i + LIMBS] = carry as u64;
+        }
+
+        // hi * 2^3072 + lo = hi * MAX_PRIME_DIFF + lo (mod p)
+        let mut carry = 0u128;
+        for i in 0..LIMBS {
+            let t =
+                product[i] as u128 + product[i + LIMBS] as u128 * MAX_PRIME_DIFF as u128 + carry;
+            self.0[i] = t as u64;
+            carry = t >> 64;
+        }
+        while carry > 0 {
+            carry = self.add_small(carry * MAX_PRIME_DIFF as u128);
+        }
+        if self.is_overflow() {
+            self.full_reduce();
+        }
+    }
+
+    /// Modular inverse, computed as `self^(p - 2)`.
+    fn inverse(&self) -> Self {
+        // p - 2 = 2^3072 - MAX_PRIME_DIFF - 2: all bits set except in the lowest limb.
+        let low_limb = u64::MAX - MAX_PRIME_DIFF - 1;
+        let mut result = Self::one();
+        for i in (0..LIMBS).rev() {
+            let limb = if i == 0 { low_limb } else { u64::MAX };
+            for bit in (0..64).rev() {
+                let squared = result;
+                result.multiply(&squared);
+                if (limb >> bit) & 1 == 1 {
+                    result.multiply(self);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl fmt::Debug for Num3072 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for limb in self.0.iter().rev() {
+            write!(f, "{limb:016x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Map a byte string to a group element: ChaCha20 keystream keyed by its SHA256.
+fn to_num3072(data: &[u8]) -> Num3072 {
+    let key = sha256::Hash::hash(data).to_byte_array();
+    let mut bytes = [0u8; BYTE_SIZE];
+    for (counter, block) in bytes.chunks_exact_mut(64).enumerate() {
+        block.copy_from_slice(&chacha20_block(&key, counter as u32));
+    }
+    Num3072::from_bytes(&bytes)
+}
+
+/// One ChaCha20 block with an all-zero nonce (RFC 8439).
+fn chacha20_block(key: &[u8; 32], counter: u32) -> [u8; 64] {
+    #[inline(always)]
+    fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+        s[a] = s[a].wrapping_add(s[b]);
+        s[d] = (s[d] ^ s[a]).rotate_left(16);
+        s[c] = s[c].wrapping_add(s[d]);
+        s[b] = (s[b] ^ s[c]).rotate_left(12);
+        s[a] = s[a].wrapping_add(s[b]);
+        s[d] = (s[d] ^ s[a]).rotate_left(8);
+        s[c] = s[c].wrapping_add(s[d]);
+        s[b] = (s[b] ^ s[c]).rotate_left(7);
+    }
+
+    let mut input = [0u32; 16];
+    // "expand 32-byte k"
+    input[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+    for (i, chunk) in key.chunks_exact(4).enumerate() {
+        input[4 + i] = u32::from_le_bytes(chunk.try_into().expect("chunk has 4 bytes"));
+    }
+    input[12] = counter;
+
+    let mut state = input;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+
+    let mut out = [0u8; 64];
+    for (i, chunk) in out.chunks_exact_mut(4).enumerate() {
+        chunk.copy_from_slice(&state[i].wrapping_add(input[i]).to_le_bytes());
+    }
+    out
+}
+
+/// A finalized MuHash3072 digest.
+///
+/// Displayed in reversed byte order, like `uint256` values in Bitcoin Core
+/// (e.g., the `muhash` field of `gettxoutsetinfo`).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct MuHashDigest(pub [u8; 32]);
+
+impl fmt::Display for MuHashDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0.iter().rev() {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Rolling hash of a set of byte strings.
+///
+/// Cloning is cheap (768 bytes), finalizing requires a modular inversion
+/// and takes a few milliseconds.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MuHash3072 {
+    numerator: Num3072,
+    denominator: Num3072,
+}
+
+impl Default for MuHash3072 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MuHash3072 {
+    /// The hash of the empty set.
+    pub fn new() -> Self {
+        Self {
+            numerator: Num3072::one(),
+            denominator: Num3072::one(),
+        }
+    }
+
+    /// Add an element to the set.
+    pub fn insert(&mut self, data: &[u8]) {
+        self.numerator.multiply(&to_num3072(data));
+    }
+
+    /// Remove an element from the set.
+    pub fn remove(&mut self, data: &[u8]) {
+        self.denominator.multiply(&to_num3072(data));
+    }
+
+    /// Add all elements of `other` to this set (`operator*=` in Core).
+    pub fn combine(&mut self, other: &Self) {
+        self.numerator.multiply(&other.numerator);
+        self.denominator.multiply(&other.denominator);
+    }
+
+    /// Remove all elements of `other` from this set (`operator/=` in Core).
+    pub fn divide(&mut self, other: &Self) {
+        self.numerator.multiply(&other.denominator);
+        self.denominator.multiply(&other.numerator);
+    }
+
+    /// Compute the digest of the set.
+    pub fn finalize(&self) -> MuHashDigest {
+        let mut num = self.numerator;
+        num.multiply(&self.denominator.inverse());
+        MuHashDigest(sha256::Hash::hash(&num.to_bytes()).to_byte_array())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_int(i: u8) -> MuHash3072 {
+        let mut tmp = [0u8; 32];
+        tmp[0] = i;
+        let mut muhash = MuHash3072::new();
+        muhash.insert(&tmp);
+        muhash
+    }
+
+    #[test]
+    fn test_chacha20_block() {
+        // RFC 8439 appendix A.1, test vector #1
+        let out = chacha20_block(&[0u8; 32], 0);
+        assert_eq!(
+            out[..16],
+            [
+                0x76, 0xb8, 0xe0, 0xad, 0xa0, 0xf1, 0x3d, 0x90, 0x40, 0x5d, 0x6a, 0xe5, 0x53, 0x86,
+                0xbd, 0x28
+            ]
+        );
+    }
+
+    #[test]
+    fn test_muhash() {
+        // test vectors from Bitcoin Core `crypto_tests.cpp`
+        let mut acc = from_int(0);
+        acc.combine(&from_int(1));
+        acc.divide(&from_int(2));
+        assert_eq!(
+            acc.finalize().to_string(),
+            "10d312b100cbd32ada024a6646e40d3482fcff103668d2625f10002a607d5863"
+        );
+
+        let mut acc2 = from_int(0);
+        let mut tmp = [0u8; 32];
+        tmp[0] = 1;
+        acc2.insert(&tmp);
+        tmp[0] = 2;
+        acc2.remove(&tmp);
+        assert_eq!(acc.finalize(), acc2.finalize());
+    }
+
+    #[test]
+    fn test_muhash_empty() {
+        // `gettxoutsetinfo muhash` of Bitcoin Core at the genesis block,
+        // whose output is not in the UTXO set.
+        assert_eq!(
+            MuHash3072::new().finalize().to_string(),
+            "dd5ad2a105c2d29495f577245c357409002329b9f4d6182c0af3dc2f462555c8"
+        );
+    }
+
+    #[test]
+    fn test_muhash_order_independent() {
+        let mut a = MuHash3072::new();
+        let mut b = MuHash3072::new();
+        a.insert(b"x");
+        a.insert(b"y");
+        a.remove(b"x");
+        b.insert(b"y");
+        assert_eq!(a.finalize(), b.finalize());
+    }
+}
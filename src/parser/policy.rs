@@ -0,0 +1,700 @@
+//! Signature operation counting and standardness rules.
+//!
+//! Sigops are counted as in consensus (`GetTransactionSigOpCost`), the
+//! standardness checks follow the default relay policy of Bitcoin Core 29
+//! (`IsStandardTx`, `AreInputsStandard` and `IsWitnessStandard`).
+//! Blocks may contain non-standard transactions, as policy only applies to relay.
+//!
+//! Translated from Bitcoin Core:
+//! [policy.cpp](https://github.com/bitcoin/bitcoin/blob/master/src/policy/policy.cpp),
+//! [tx_verify.cpp](https://github.com/bitcoin/bitcoin/blob/master/src/consensus/tx_verify.cpp) and
+//! [solver.cpp](https://github.com/bitcoin/bitcoin/blob/master/src/script/solver.cpp).
+
+use crate::parser::input::taproot_witness;
+use crate::parser::script::is_unspendable;
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::consensus::encode::VarInt;
+use bitcoin::{Script, Transaction, TxIn, TxOut};
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of keys in a multisig script.
+pub(crate) const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+const WITNESS_SCALE_FACTOR: u64 = 4;
+const TX_MIN_STANDARD_VERSION: i32 = 1;
+const TX_MAX_STANDARD_VERSION: i32 = 3;
+const MAX_STANDARD_TX_WEIGHT: u64 = 400_000;
+const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+const MAX_OP_RETURN_RELAY: usize = 83;
+const MAX_P2SH_SIGOPS: u32 = 15;
+const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+const MAX_STANDARD_P2WSH_STACK_ITEMS: usize = 100;
+const MAX_STANDARD_P2WSH_STACK_ITEM_SIZE: usize = 80;
+const MAX_STANDARD_TAPSCRIPT_STACK_ITEM_SIZE: usize = 80;
+/// Fee rate in sat/kvB below which spending an output costs more than its value.
+const DUST_RELAY_TX_FEE: u64 = 3000;
+const TAPROOT_LEAF_TAPSCRIPT: u8 = 0xc0;
+/// Dust outputs allowed in a transaction, which must then pay no fee.
+const MAX_DUST_OUTPUTS_PER_TX: usize = 1;
+
+/// Output types, as determined by `Solver` in Bitcoin Core.
+///
+/// Displayed as the `scriptPubKey.type` names of the RPCs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TxoutType {
+    NonStandard,
+    Anchor,
+    PubKey,
+    PubKeyHash,
+    ScriptHash,
+    MultiSig,
+    NullData,
+    WitnessV0KeyHash,
+    WitnessV0ScriptHash,
+    WitnessV1Taproot,
+    WitnessUnknown,
+}
+
+impl std::fmt::Display for TxoutType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Self::NonStandard => "nonstandard",
+            Self::Anchor => "anchor",
+            Self::PubKey => "pubkey",
+            Self::PubKeyHash => "pubkeyhash",
+            Self::ScriptHash => "scripthash",
+            Self::MultiSig => "multisig",
+            Self::NullData => "nulldata",
+            Self::WitnessV0KeyHash => "witness_v0_keyhash",
+            Self::WitnessV0ScriptHash => "witness_v0_scripthash",
+            Self::WitnessV1Taproot => "witness_v1_taproot",
+            Self::WitnessUnknown => "witness_unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl TxoutType {
+    /// Classify a script public key.
+    pub fn solve(script: &Script) -> Self {
+        if script.is_p2sh() {
+            return Self::ScriptHash;
+        }
+        if script.is_witness_program() {
+            let program_len = script.len() - 2;
+            return match (script.witness_version().map(|v| v.to_num()), program_len) {
+                (Some(0), 20) => Self::WitnessV0KeyHash,
+                (Some(0), 32) => Self::WitnessV0ScriptHash,
+                (Some(0), _) => Self::NonStandard,
+                (Some(1), 32) => Self::WitnessV1Taproot,
+                _ if script.as_bytes() == [0x51, 0x02, 0x4e, 0x73] => Self::Anchor,
+                _ => Self::WitnessUnknown,
+            };
+        }
+        if script.is_op_return() {
+            return if is_push_only(Script::from_bytes(&script.as_bytes()[1..])) {
+                Self::NullData
+            } else {
+                Self::NonStandard
+            };
+        }
+        if p2pk_public_key(script).is_some() {
+            return Self::PubKey;
+        }
+        if script.is_p2pkh() {
+            return Self::PubKeyHash;
+        }
+        if multisig_keys(script).is_some() {
+            return Self::MultiSig;
+        }
+        Self::NonStandard
+    }
+}
+
+/// Why a transaction would not be relayed by Bitcoin Core.
+///
+/// Variants are ordered as they are checked, and displayed as
+/// the reject reasons of Bitcoin Core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum NonStandardReason {
+    /// Version is not 1, 2 or 3.
+    Version,
+    /// Weight above 400,000.
+    TxSize,
+    /// A scriptSig larger than 1650 bytes.
+    ScriptSigSize,
+    /// A scriptSig with other opcodes than pushes.
+    ScriptSigNotPushOnly,
+    /// A non-standard output, or an oversized OP_RETURN output,
+    /// or a bare multisig with more than 3 keys.
+    ScriptPubKey,
+    /// More than one output below the dust threshold, or
+    /// a dust output in a transaction paying a fee.
+    Dust,
+    /// More than one OP_RETURN output.
+    MultiOpReturn,
+    /// Spending a non-standard output, or a P2SH script with more than 15 sigops.
+    NonStandardInputs,
+    /// A witness that does not follow the policy limits.
+    NonStandardWitness,
+}
+
+impl std::fmt::Display for NonStandardReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let reason = match self {
+            Self::Version => "version",
+            Self::TxSize => "tx-size",
+            Self::ScriptSigSize => "scriptsig-size",
+            Self::ScriptSigNotPushOnly => "scriptsig-not-pushonly",
+            Self::ScriptPubKey => "scriptpubkey",
+            Self::Dust => "dust",
+            Self::MultiOpReturn => "multi-op-return",
+            Self::NonStandardInputs => "bad-txns-nonstandard-inputs",
+            Self::NonStandardWitness => "bad-witness-nonstandard",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// Count the sigops of a script (`CScript::GetSigOpCount`).
+///
+/// Unless `accurate`, every `OP_CHECKMULTISIG` counts as 20 sigops.
+pub fn sigop_count(script: &Script, accurate: bool) -> u32 {
+    let mut count = 0;
+    let mut last_op = None;
+    for ins in script.instructions() {
+        let op = match ins {
+            Ok(Instruction::Op(op)) => op.to_u8(),
+            Ok(Instruction::PushBytes(_)) => {
+                last_op = None;
+                continue;
+            }
+            Err(_) => break,
+        };
+        match op {
+            // OP_CHECKSIG, OP_CHECKSIGVERIFY
+            0xac | 0xad => count += 1,
+            // OP_CHECKMULTISIG, OP_CHECKMULTISIGVERIFY
+            0xae | 0xaf => match last_op {
+                Some(n @ 0x51..=0x60) if accurate => count += (n - 0x50) as u32,
+                _ => count += MAX_PUBKEYS_PER_MULTISIG as u32,
+            },
+            _ => {}
+        }
+        last_op = Some(op);
+    }
+    count
+}
+
+/// Sigops in the scripts of a transaction, without looking at
+/// spent outputs (`GetLegacySigOpCount`).
+pub fn legacy_sigop_count(tx: &Transaction) -> u32 {
+    let input: u32 = tx
+        .input
+        .iter()
+        .map(|i| sigop_count(&i.script_sig, false))
+        .sum();
+    let output: u32 = tx
+        .output
+        .iter()
+        .map(|o| sigop_count(&o.script_pubkey, false))
+        .sum();
+    input + output
+}
+
+/// Total sigop cost of a transaction (BIP141), given the spent outputs
+/// in the order of the inputs.
+///
+/// Coinbase transactions only have legacy sigops.
+pub fn sigop_cost(tx: &Transaction, prevouts: &[&Script]) -> u64 {
+    let legacy = legacy_sigop_count(tx) as u64 * WITNESS_SCALE_FACTOR;
+    if tx.is_coinbase() {
+        return legacy;
+    }
+    legacy
+        + tx.input
+            .iter()
+            .zip(prevouts)
+            .map(|(tx_in, prevout)| input_sigop_cost(tx_in, prevout))
+            .sum::<u64>()
+}
+
+/// Sigop cost of the P2SH and witness scripts executed by an input.
+pub(crate) fn input_sigop_cost(tx_in: &TxIn, prevout: &Script) -> u64 {
+    let p2sh = if prevout.is_p2sh() {
+        last_push(&tx_in.script_sig)
+            .map(|redeem| sigop_count(Script::from_bytes(redeem), true))
+            .unwrap_or(0)
+    } else {
+        0
+    };
+    p2sh as u64 * WITNESS_SCALE_FACTOR + witness_sigop_count(tx_in, prevout) as u64
+}
+
+/// `CountWitnessSigOps`, only segwit v0 scripts count sigops.
+fn witness_sigop_count(tx_in: &TxIn, prevout: &Script) -> u32 {
+    let program = if prevout.is_witness_program() {
+        prevout
+    } else if prevout.is_p2sh() {
+        match last_push(&tx_in.script_sig).map(Script::from_bytes) {
+            Some(redeem) if redeem.is_witness_program() => redeem,
+            _ => return 0,
+        }
+    } else {
+        return 0;
+    };
+    if program.as_bytes()[0] != 0x00 {
+        return 0;
+    }
+    match program.len() - 2 {
+        20 => 1,
+        32 => tx_in
+            .witness
+            .last()
+            .map(|script| sigop_count(Script::from_bytes(script), true))
+            .unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Check the rules of `IsStandardTx`, which do not need the spent outputs.
+pub fn check_standard_tx(tx: &Transaction) -> Result<(), NonStandardReason> {
+    if tx.version.0 < TX_MIN_STANDARD_VERSION || tx.version.0 > TX_MAX_STANDARD_VERSION {
+        return Err(NonStandardReason::Version);
+    }
+    if tx.weight().to_wu() > MAX_STANDARD_TX_WEIGHT {
+        return Err(NonStandardReason::TxSize);
+    }
+    for tx_in in &tx.input {
+        if tx_in.script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+            return Err(NonStandardReason::ScriptSigSize);
+        }
+        if !is_push_only(&tx_in.script_sig) {
+            return Err(NonStandardReason::ScriptSigNotPushOnly);
+        }
+    }
+    let mut data_out = 0;
+    for out in &tx.output {
+        match TxoutType::solve(&out.script_pubkey) {
+            TxoutType::NonStandard => return Err(NonStandardReason::ScriptPubKey),
+            TxoutType::NullData => {
+                if out.script_pubkey.len() > MAX_OP_RETURN_RELAY {
+                    return Err(NonStandardReason::ScriptPubKey);
+                }
+                data_out += 1;
+            }
+            TxoutType::MultiSig => {
+                let (_, keys) = multisig_keys(&out.script_pubkey).unwrap();
+                if keys.len() > 3 {
+                    return Err(NonStandardReason::ScriptPubKey);
+                }
+            }
+            _ => {}
+        }
+    }
+    if dust_outputs(
+        tx.output
+            .iter()
+            .map(|o| (o.value.to_sat(), o.script_pubkey.as_script())),
+    ) > MAX_DUST_OUTPUTS_PER_TX
+    {
+        return Err(NonStandardReason::Dust);
+    }
+    if data_out > 1 {
+        return Err(NonStandardReason::MultiOpReturn);
+    }
+    Ok(())
+}
+
+/// Check the rules of `AreInputsStandard` and `IsWitnessStandard` for one input.
+pub fn check_standard_input(tx_in: &TxIn, prevout: &Script) -> Result<(), NonStandardReason> {
+    let redeem_script = match TxoutType::solve(prevout) {
+        TxoutType::NonStandard | TxoutType::WitnessUnknown => {
+            return Err(NonStandardReason::NonStandardInputs)
+        }
+        TxoutType::ScriptHash => {
+            let redeem = last_push(&tx_in.script_sig)
+                .filter(|_| is_push_only(&tx_in.script_sig))
+                .map(Script::from_bytes)
+                .ok_or(NonStandardReason::NonStandardInputs)?;
+            if sigop_count(redeem, true) > MAX_P2SH_SIGOPS {
+                return Err(NonStandardReason::NonStandardInputs);
+            }
+            Some(redeem)
+        }
+        _ => None,
+    };
+
+    if tx_in.witness.is_empty() {
+        return Ok(());
+    }
+    let program = redeem_script.unwrap_or(prevout);
+    if program.as_bytes() == [0x51, 0x02, 0x4e, 0x73] || !program.is_witness_program() {
+        return Err(NonStandardReason::NonStandardWitness);
+    }
+    let version = program.as_bytes()[0];
+    let program_len = program.len() - 2;
+    if version == 0x00 && program_len == 32 {
+        let witness_script = tx_in.witness.last().unwrap();
+        if witness_script.len() > MAX_STANDARD_P2WSH_SCRIPT_SIZE
+            || tx_in.witness.len() - 1 > MAX_STANDARD_P2WSH_STACK_ITEMS
+            || tx_in
+                .witness
+                .iter()
+                .take(tx_in.witness.len() - 1)
+                .any(|item| item.len() > MAX_STANDARD_P2WSH_STACK_ITEM_SIZE)
+        {
+            return Err(NonStandardReason::NonStandardWitness);
+        }
+    }
+    // OP_1 <32 bytes>, not wrapped in P2SH
+    if version == 0x51 && program_len == 32 && redeem_script.is_none() {
+        let stack = taproot_witness(tx_in);
+        if stack.len() != tx_in.witness.len() {
+            // the annex is reserved for future upgrades
+            return Err(NonStandardReason::NonStandardWitness);
+        }
+        if stack.len() >= 2 {
+            let control_block = stack[stack.len() - 1];
+            if control_block.first().map(|b| b & 0xfe) == Some(TAPROOT_LEAF_TAPSCRIPT)
+                && stack[..stack.len() - 2]
+                    .iter()
+                    .any(|item| item.len() > MAX_STANDARD_TAPSCRIPT_STACK_ITEM_SIZE)
+            {
+                return Err(NonStandardReason::NonStandardWitness);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check all standardness rules, given the spent outputs in the order of the inputs.
+///
+/// Coinbase transactions are not subject to standardness.
+pub fn check_standard(tx: &Transaction, prevouts: &[TxOut]) -> Result<(), NonStandardReason> {
+    if tx.is_coinbase() {
+        return Ok(());
+    }
+    check_standard_tx(tx)?;
+    let mut reason = Ok(());
+    for (tx_in, prevout) in tx.input.iter().zip(prevouts) {
+        reason = first_reason(reason, check_standard_input(tx_in, &prevout.script_pubkey));
+    }
+    reason?;
+    let input: u64 = prevouts.iter().map(|o| o.value.to_sat()).sum();
+    let output: u64 = tx.output.iter().map(|o| o.value.to_sat()).sum();
+    check_ephemeral_dust(
+        tx.output
+            .iter()
+            .map(|o| (o.value.to_sat(), o.script_pubkey.as_script())),
+        input.saturating_sub(output),
+    )
+}
+
+/// A single dust output is only allowed in a transaction paying no fee
+/// (`PreCheckEphemeralTx`).
+pub(crate) fn check_ephemeral_dust<'a>(
+    outputs: impl Iterator<Item = (u64, &'a Script)>,
+    fee: u64,
+) -> Result<(), NonStandardReason> {
+    if fee > 0 && dust_outputs(outputs) > 0 {
+        Err(NonStandardReason::Dust)
+    } else {
+        Ok(())
+    }
+}
+
+/// The reason Bitcoin Core reports first: all inputs are checked
+/// by `AreInputsStandard` before any witness.
+pub(crate) fn first_reason(
+    a: Result<(), NonStandardReason>,
+    b: Result<(), NonStandardReason>,
+) -> Result<(), NonStandardReason> {
+    match (a, b) {
+        (Err(a), Err(b)) => Err(a.min(b)),
+        (Err(a), Ok(())) | (Ok(()), Err(a)) => Err(a),
+        (Ok(()), Ok(())) => Ok(()),
+    }
+}
+
+/// Number of `(value, script_pubkey)` outputs that are dust.
+fn dust_outputs<'a>(outputs: impl Iterator<Item = (u64, &'a Script)>) -> usize {
+    outputs
+        .filter(|(value, script)| is_dust(*value, script))
+        .count()
+}
+
+/// Whether spending an output costs more than its value at the dust relay fee (`IsDust`).
+fn is_dust(value: u64, script_pubkey: &Script) -> bool {
+    if is_unspendable(script_pubkey) {
+        return false;
+    }
+    let len = script_pubkey.len();
+    let mut size = 8 + VarInt(len as u64).size() + len;
+    size += if script_pubkey.is_witness_program() {
+        // outpoint, scriptSig length, sequence and a discounted witness
+        32 + 4 + 1 + 107 / WITNESS_SCALE_FACTOR as usize + 4
+    } else {
+        32 + 4 + 1 + 107 + 4
+    };
+    value < DUST_RELAY_TX_FEE * size as u64 / 1000
+}
+
+/// Last item pushed by a push-only script (`OP_n` push nothing),
+/// `None` if the script is empty or has other opcodes.
+fn last_push(script: &Script) -> Option<&[u8]> {
+    let mut last = None;
+    for ins in script.instructions() {
+        match ins {
+            Ok(Instruction::PushBytes(data)) => last = Some(data.as_bytes()),
+            Ok(Instruction::Op(op)) if op.to_u8() <= 0x60 => last = Some(&[]),
+            _ => return None,
+        }
+    }
+    last
+}
+
+/// `CScript::IsPushOnly`, `OP_RESERVED` counts as a push.
+pub(crate) fn is_push_only(script: &Script) -> bool {
+    script.instructions().all(|ins| match ins {
+        Ok(Instruction::PushBytes(_)) => true,
+        Ok(Instruction::Op(op)) => op.to_u8() <= 0x60,
+        Err(_) => false,
+    })
+}
+
+/// Whether `key` has the size implied by its prefix (`CPubKey::ValidSize`).
+fn is_valid_key_size(key: &[u8]) -> bool {
+    match key.first() {
+        Some(0x02) | Some(0x03) => key.len() == 33,
+        Some(0x04) | Some(0x06) | Some(0x07) => key.len() == 65,
+        _ => false,
+    }
+}
+
+/// Public key of `<pubkey> OP_CHECKSIG`.
+pub(crate) fn p2pk_public_key(script: &Script) -> Option<&[u8]> {
+    let bytes = script.as_bytes();
+    let key = match bytes.len() {
+        35 if bytes[0] == 33 && bytes[34] == 0xac => &bytes[1..34],
+        67 if bytes[0] == 65 && bytes[66] == 0xac => &bytes[1..66],
+        _ => return None,
+    };
+    is_valid_key_size(key).then_some(key)
+}
+
+/// Required signatures and keys of `OP_m <pubkey>... OP_n OP_CHECKMULTISIG`.
+pub(crate) fn multisig_keys(script: &Script) -> Option<(u8, Vec<&[u8]>)> {
+    let ops = script
+        .instructions()
+        .collect::<Result<Vec<Instruction>, _>>()
+        .ok()?;
+    let small_int = |ins: &Instruction| match ins {
+        Instruction::Op(op) if (0x51..=0x60).contains(&op.to_u8()) => Some(op.to_u8() - 0x50),
+        _ => None,
+    };
+    if ops.len() < 4 {
+        return None;
+    }
+    match ops.last()? {
+        Instruction::Op(op) if op.to_u8() == 0xae => {}
+        _ => return None,
+    }
+    let required = small_int(&ops[0])?;
+    let total = small_int(&ops[ops.len() - 2])? as usize;
+    let keys = ops[1..ops.len() - 2]
+        .iter()
+        .map(|ins| match ins {
+            Instruction::PushBytes(key) if is_valid_key_size(key.as_bytes()) => {
+                Some(key.as_bytes())
+            }
+            _ => None,
+        })
+        .collect::<Option<Vec<&[u8]>>>()?;
+    if keys.len() != total || total > MAX_PUBKEYS_PER_MULTISIG || required as usize > total {
+        return None;
+    }
+    Some((required, keys))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, Txid, Witness};
+
+    fn script(hex: &str) -> ScriptBuf {
+        ScriptBuf::from_hex(hex).unwrap()
+    }
+
+    fn output(hex: &str, value: u64) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: script(hex),
+        }
+    }
+
+    /// A non-coinbase input.
+    fn input() -> TxIn {
+        TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0),
+            ..Default::default()
+        }
+    }
+
+    fn tx(input: Vec<TxIn>, output: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input,
+            output,
+        }
+    }
+
+    const P2PKH: &str = "76a91412ab8dc588ca9d5787dde7eb29569da63c3a238c88ac";
+    const P2WPKH: &str = "0014751e76e8199196d454941c45d1b3a323f1433bd6";
+    const KEY: &str = "022df8750480ad5b26950b25c7ba79d3e37d75f640f8e5d9bcd5b150a0f85014da";
+
+    #[test]
+    fn test_txout_type() {
+        let cases = [
+            (P2PKH, "pubkeyhash"),
+            (
+                "a914748284390f9e263a4b766a75d0633c50426eb87587",
+                "scripthash",
+            ),
+            (P2WPKH, "witness_v0_keyhash"),
+            ("0013751e76e8199196d454941c45d1b3a323f1433b", "nonstandard"),
+            ("51024e73", "anchor"),
+            ("5210751e76e8199196d454941c45d1b3a323", "witness_unknown"),
+            ("6a0b68656c6c6f20776f726c64", "nulldata"),
+            ("6aac", "nonstandard"),
+            ("ac", "nonstandard"),
+        ];
+        for (hex, expected) in cases.iter() {
+            assert_eq!(
+                TxoutType::solve(&script(hex)).to_string(),
+                *expected,
+                "{}",
+                hex
+            );
+        }
+    }
+
+    #[test]
+    fn test_sigop_count() {
+        let multisig = script(&format!("5221{}21{}52ae", KEY, KEY));
+        assert_eq!(sigop_count(&multisig, true), 2);
+        assert_eq!(sigop_count(&multisig, false), 20);
+        assert_eq!(sigop_count(&script(P2PKH), false), 1);
+        // a truncated push stops counting
+        assert_eq!(sigop_count(&script("acac4c"), false), 2);
+
+        // P2SH-wrapped multisig: 2 * 4 for the redeem script
+        let redeem = multisig.as_bytes();
+        let mut script_sig = vec![0x00, 0x4c, redeem.len() as u8];
+        script_sig.extend_from_slice(redeem);
+        let tx_in = TxIn {
+            script_sig: ScriptBuf::from_bytes(script_sig),
+            ..input()
+        };
+        let p2sh = ScriptBuf::new_p2sh(&multisig.script_hash());
+        assert_eq!(input_sigop_cost(&tx_in, &p2sh), 8);
+
+        // P2WSH: witness sigops are not scaled
+        let tx_in = TxIn {
+            witness: Witness::from_slice(&[vec![], multisig.to_bytes()]),
+            ..input()
+        };
+        let p2wsh = ScriptBuf::new_p2wsh(&multisig.wscript_hash());
+        assert_eq!(input_sigop_cost(&tx_in, &p2wsh), 2);
+        assert_eq!(input_sigop_cost(&tx_in, &script(P2WPKH)), 1);
+
+        let tx = tx(vec![tx_in], vec![output(P2PKH, 1000)]);
+        assert_eq!(legacy_sigop_count(&tx), 1);
+        assert_eq!(sigop_cost(&tx, &[p2wsh.as_script()]), 4 + 2);
+    }
+
+    #[test]
+    fn test_check_standard_tx() {
+        assert_eq!(
+            check_standard_tx(&tx(vec![input()], vec![output(P2PKH, 546)])),
+            Ok(())
+        );
+        // one dust output is allowed if the transaction pays no fee
+        let dust = tx(
+            vec![input()],
+            vec![output(P2PKH, 545), output(P2WPKH, 1000)],
+        );
+        assert_eq!(check_standard_tx(&dust), Ok(()));
+        assert_eq!(check_standard(&dust, &[output(P2PKH, 1545)]), Ok(()));
+        assert_eq!(
+            check_standard(&dust, &[output(P2PKH, 1546)]),
+            Err(NonStandardReason::Dust)
+        );
+        assert_eq!(
+            check_standard_tx(&tx(
+                vec![input()],
+                vec![output(P2PKH, 545), output(P2WPKH, 293)]
+            )),
+            Err(NonStandardReason::Dust)
+        );
+        let op_return = output("6a0b68656c6c6f20776f726c64", 0);
+        assert_eq!(
+            check_standard_tx(&tx(vec![input()], vec![op_return.clone(), op_return])),
+            Err(NonStandardReason::MultiOpReturn)
+        );
+        let bare_multisig = format!("51{}54ae", format!("21{}", KEY).repeat(4));
+        assert_eq!(
+            check_standard_tx(&tx(vec![input()], vec![output(&bare_multisig, 1000)])),
+            Err(NonStandardReason::ScriptPubKey)
+        );
+        let non_push = TxIn {
+            script_sig: script("0076"),
+            ..input()
+        };
+        assert_eq!(
+            check_standard_tx(&tx(vec![non_push], vec![output(P2PKH, 1000)])),
+            Err(NonStandardReason::ScriptSigNotPushOnly)
+        );
+        let oversized = TxIn {
+            script_sig: ScriptBuf::from_bytes(vec![0x51; 1651]),
+            ..input()
+        };
+        assert_eq!(
+            check_standard_tx(&tx(vec![oversized], vec![output(P2PKH, 1000)])),
+            Err(NonStandardReason::ScriptSigSize)
+        );
+    }
+
+    #[test]
+    fn test_check_standard_input() {
+        let mut tx_in = input();
+        assert_eq!(
+            check_standard_input(&tx_in, &script("ac")),
+            Err(NonStandardReason::NonStandardInputs)
+        );
+        assert_eq!(check_standard_input(&tx_in, &script(P2PKH)), Ok(()));
+        // witness for a legacy output
+        tx_in.witness = Witness::from_slice(&[vec![0x01]]);
+        assert_eq!(
+            check_standard_input(&tx_in, &script(P2PKH)),
+            Err(NonStandardReason::NonStandardWitness)
+        );
+        // taproot key path with an annex
+        let p2tr = script("5120a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c");
+        tx_in.witness = Witness::from_slice(&[vec![0x01; 64]]);
+        assert_eq!(check_standard_input(&tx_in, &p2tr), Ok(()));
+        tx_in.witness = Witness::from_slice(&[vec![0x01; 64], vec![0x50]]);
+        assert_eq!(
+            check_standard_input(&tx_in, &p2tr),
+            Err(NonStandardReason::NonStandardWitness)
+        );
+        assert_eq!(
+            first_reason(
+                Err(NonStandardReason::NonStandardWitness),
+                Err(NonStandardReason::NonStandardInputs)
+            ),
+            Err(NonStandardReason::NonStandardInputs)
+        );
+    }
+}
@@ -108,4 +108,4 @@ pub trait BlockchainRead: bitcoin::io::BufRead {
 impl BlockchainRead for Cursor<&[u8]> {}
 impl BlockchainRead for Cursor<Vec<u8>> {}
 impl BlockchainRead for BufReader<File> {}
-impl BlockchainRead for XorReader<File> {}
+impl<R: std::io::Read> BlockchainRead for XorReader<R> {}
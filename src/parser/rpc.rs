@@ -0,0 +1,528 @@
+//! Render blocks and transactions as the JSON returned by Bitcoin Core RPCs.
+//!
+//! [`RpcBlock`] matches `getblock <hash> 2` (or `3` with prevouts), and
+//! [`RpcTransaction`] matches `getrawtransaction <txid> true`.
+//! Field names follow the RPC, so that the structs serialize to the same JSON.
+//!
+//! Translated from Bitcoin Core:
+//! [core_write.cpp](https://github.com/bitcoin/bitcoin/blob/master/src/core_write.cpp),
+//! [blockchain.cpp](https://github.com/bitcoin/bitcoin/blob/master/src/rpc/blockchain.cpp) and
+//! [descriptor.cpp](https://github.com/bitcoin/bitcoin/blob/master/src/script/descriptor.cpp).
+
+use crate::parser::asm::script_to_asm;
+use crate::parser::block_index::{difficulty, BlockIndex};
+use crate::parser::block_types::full_block::block_sizes;
+use crate::parser::policy::{multisig_keys, p2pk_public_key, TxoutType};
+use crate::parser::undo::{BlockUndo, TxUndo};
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::hex::DisplayHex;
+use bitcoin::key::XOnlyPublicKey;
+use bitcoin::{
+    Address, Amount, Block, BlockHash, Network, Script, Transaction, TxMerkleNode, Txid,
+};
+use serde::ser::Error as _;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::value::RawValue;
+
+/// Level of detail for transactions in [`RpcBlock`], as `verbosity` of `getblock`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TxVerbosity {
+    /// `getblock <hash> 2`: decoded transactions, with fees if undo data is available.
+    ShowDetails,
+    /// `getblock <hash> 3`: also show the output spent by each input.
+    ShowDetailsAndPrevout,
+}
+
+/// A block as returned by `getblock`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct RpcBlock {
+    pub hash: BlockHash,
+    pub confirmations: i64,
+    pub height: usize,
+    pub version: i32,
+    #[serde(rename = "versionHex")]
+    pub version_hex: String,
+    pub merkleroot: TxMerkleNode,
+    pub time: u32,
+    pub mediantime: u32,
+    pub nonce: u32,
+    pub bits: String,
+    pub target: String,
+    pub difficulty: f64,
+    pub chainwork: String,
+    #[serde(rename = "nTx")]
+    pub n_tx: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previousblockhash: Option<BlockHash>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nextblockhash: Option<BlockHash>,
+    pub strippedsize: u64,
+    pub size: u64,
+    pub weight: u64,
+    pub tx: Vec<RpcTransaction>,
+}
+
+/// A transaction as returned by `getrawtransaction` or within `getblock`.
+///
+/// `fee` is only known with undo data. The block fields are only set by
+/// `getrawtransaction`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct RpcTransaction {
+    pub txid: Txid,
+    pub hash: bitcoin::Wtxid,
+    pub version: i32,
+    pub size: usize,
+    pub vsize: usize,
+    pub weight: u64,
+    pub locktime: u32,
+    pub vin: Vec<RpcVin>,
+    pub vout: Vec<RpcVout>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_optional_amount"
+    )]
+    pub fee: Option<f64>,
+    pub hex: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blockhash: Option<BlockHash>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmations: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocktime: Option<u32>,
+}
+
+/// A transaction input. Coinbase inputs only have `coinbase`,
+/// `txinwitness` and `sequence`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct RpcVin {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coinbase: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub txid: Option<Txid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vout: Option<u32>,
+    #[serde(rename = "scriptSig", skip_serializing_if = "Option::is_none")]
+    pub script_sig: Option<RpcScriptSig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub txinwitness: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prevout: Option<RpcPrevout>,
+    pub sequence: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct RpcScriptSig {
+    pub asm: String,
+    pub hex: String,
+}
+
+/// The output spent by an input, only shown with [`TxVerbosity::ShowDetailsAndPrevout`].
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct RpcPrevout {
+    /// Whether the output was created by a coinbase transaction.
+    pub generated: bool,
+    pub height: u32,
+    #[serde(serialize_with = "serialize_amount")]
+    pub value: f64,
+    #[serde(rename = "scriptPubKey")]
+    pub script_pubkey: RpcScriptPubKey,
+}
+
+/// A transaction output, `value` in BTC.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct RpcVout {
+    #[serde(serialize_with = "serialize_amount")]
+    pub value: f64,
+    pub n: u32,
+    #[serde(rename = "scriptPubKey")]
+    pub script_pubkey: RpcScriptPubKey,
+}
+
+/// Write an amount in BTC with 8 decimals, e.g. `0.00000001` instead of `1e-8`,
+/// as `ValueFromAmount` does. Other formats than JSON get the `f64`.
+fn serialize_amount<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    if !serializer.is_human_readable() {
+        return serializer.serialize_f64(*value);
+    }
+    let sats = (value * 100_000_000.0).round() as i64;
+    let sign = if sats < 0 { "-" } else { "" };
+    let sats = sats.unsigned_abs();
+    let number = format!("{}{}.{:08}", sign, sats / 100_000_000, sats % 100_000_000);
+    RawValue::from_string(number)
+        .map_err(S::Error::custom)?
+        .serialize(serializer)
+}
+
+fn serialize_optional_amount<S: Serializer>(
+    value: &Option<f64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serialize_amount(value, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct RpcScriptPubKey {
+    pub asm: String,
+    /// Inferred output descriptor with checksum.
+    pub desc: String,
+    pub hex: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Output type name, e.g. `witness_v0_keyhash`.
+    #[serde(rename = "type")]
+    pub script_type: String,
+}
+
+impl RpcBlock {
+    /// Render `block` at `height` of the chain in `block_index`.
+    ///
+    /// Without `undo`, transactions have no `fee` nor `prevout`.
+    pub fn new(
+        block: &Block,
+        height: usize,
+        block_index: &BlockIndex,
+        undo: Option<&BlockUndo>,
+        verbosity: TxVerbosity,
+    ) -> Self {
+        let header = &block.header;
+        let (size, strippedsize, weight) = block_sizes(block);
+        let tx = block
+            .txdata
+            .iter()
+            .enumerate()
+            .map(|(i, tx)| {
+                // undo data does not include the coinbase
+                let tx_undo = undo.and_then(|u| i.checked_sub(1).and_then(|i| u.txdata.get(i)));
+                RpcTransaction::new(tx, tx_undo, verbosity)
+            })
+            .collect();
+        Self {
+            hash: block.block_hash(),
+            confirmations: confirmations(block_index, height),
+            height,
+            version: header.version.to_consensus(),
+            version_hex: format!("{:08x}", header.version.to_consensus()),
+            merkleroot: header.merkle_root,
+            time: header.time,
+            mediantime: block_index.median_time_past(height).unwrap_or(header.time),
+            nonce: header.nonce,
+            bits: format!("{:08x}", header.bits.to_consensus()),
+            target: header.target().to_be_bytes().to_lower_hex_string(),
+            difficulty: difficulty(header.bits.to_consensus()),
+            chainwork: block_index
+                .chain_work(height)
+                .unwrap_or_else(|| header.work())
+                .to_be_bytes()
+                .to_lower_hex_string(),
+            n_tx: block.txdata.len(),
+            previousblockhash: (height > 0).then_some(header.prev_blockhash),
+            nextblockhash: block_index
+                .records
+                .get(height + 1)
+                .map(|r| r.block_header.block_hash()),
+            strippedsize,
+            size,
+            weight,
+            tx,
+        }
+    }
+}
+
+impl RpcTransaction {
+    /// Render `tx` without block information.
+    ///
+    /// `undo` holds the outputs spent by `tx`, it is needed for `fee` and `prevout`.
+    pub fn new(tx: &Transaction, undo: Option<&TxUndo>, verbosity: TxVerbosity) -> Self {
+        let is_coinbase = tx.is_coinbase();
+        let undo = undo.filter(|u| !is_coinbase && u.prevouts.len() == tx.input.len());
+        let vin = tx
+            .input
+            .iter()
+            .enumerate()
+            .map(|(i, tx_in)| {
+                let txinwitness = (!tx_in.witness.is_empty()).then(|| {
+                    tx_in
+                        .witness
+                        .iter()
+                        .map(|w| w.to_lower_hex_string())
+                        .collect()
+                });
+                let prevout = match (undo, verbosity) {
+                    (Some(undo), TxVerbosity::ShowDetailsAndPrevout) => {
+                        let coin = &undo.prevouts[i];
+                        Some(RpcPrevout {
+                            generated: coin.is_coinbase,
+                            height: coin.height,
+                            value: coin.out.value.to_btc(),
+                            script_pubkey: RpcScriptPubKey::new(&coin.out.script_pubkey),
+                        })
+                    }
+                    _ => None,
+                };
+                if is_coinbase {
+                    RpcVin {
+                        coinbase: Some(tx_in.script_sig.to_hex_string()),
+                        txid: None,
+                        vout: None,
+                        script_sig: None,
+                        txinwitness,
+                        prevout: None,
+                        sequence: tx_in.sequence.to_consensus_u32(),
+                    }
+                } else {
+                    RpcVin {
+                        coinbase: None,
+                        txid: Some(tx_in.previous_output.txid),
+                        vout: Some(tx_in.previous_output.vout),
+                        script_sig: Some(RpcScriptSig {
+                            asm: script_to_asm(&tx_in.script_sig, true),
+                            hex: tx_in.script_sig.to_hex_string(),
+                        }),
+                        txinwitness,
+                        prevout,
+                        sequence: tx_in.sequence.to_consensus_u32(),
+                    }
+                }
+            })
+            .collect();
+        let vout = tx
+            .output
+            .iter()
+            .enumerate()
+            .map(|(n, out)| RpcVout {
+                value: out.value.to_btc(),
+                n: n as u32,
+                script_pubkey: RpcScriptPubKey::new(&out.script_pubkey),
+            })
+            .collect();
+        let fee = undo.map(|undo| {
+            let input: u64 = undo.prevouts.iter().map(|c| c.out.value.to_sat()).sum();
+            let output: u64 = tx.output.iter().map(|o| o.value.to_sat()).sum();
+            Amount::from_sat(input.saturating_sub(output)).to_btc()
+        });
+        Self {
+            txid: tx.compute_txid(),
+            hash: tx.compute_wtxid(),
+            version: tx.version.0,
+            size: tx.total_size(),
+            vsize: tx.vsize(),
+            weight: tx.weight().to_wu(),
+            locktime: tx.lock_time.to_consensus_u32(),
+            vin,
+            vout,
+            fee,
+            hex: serialize_hex(tx),
+            blockhash: None,
+            confirmations: None,
+            time: None,
+            blocktime: None,
+        }
+    }
+
+    /// Add the block fields shown by `getrawtransaction` for confirmed transactions.
+    pub fn with_block(mut self, height: usize, block_index: &BlockIndex) -> Self {
+        if let Some(record) = block_index.records.get(height) {
+            self.blockhash = Some(record.block_header.block_hash());
+            self.confirmations = Some(confirmations(block_index, height));
+            self.time = Some(record.block_header.time);
+            self.blocktime = Some(record.block_header.time);
+        }
+        self
+    }
+}
+
+impl RpcScriptPubKey {
+    pub fn new(script: &Script) -> Self {
+        let script_type = TxoutType::solve(script);
+        let address = match script_type {
+            TxoutType::PubKey
+            | TxoutType::MultiSig
+            | TxoutType::NullData
+            | TxoutType::NonStandard => None,
+            _ => Address::from_script(script, Network::Bitcoin)
+                .ok()
+                .map(|a| a.to_string()),
+        };
+        Self {
+            asm: script_to_asm(script, false),
+            desc: add_checksum(&infer_descriptor(script, script_type, address.as_deref())),
+            hex: script.to_hex_string(),
+            address,
+            script_type: script_type.to_string(),
+        }
+    }
+}
+
+/// Confirmations of the block at `height`, counting the tip as 1.
+fn confirmations(block_index: &BlockIndex, height: usize) -> i64 {
+    block_index.records.len() as i64 - height as i64
+}
+
+/// Descriptor inferred without any wallet information (`InferDescriptor`),
+/// without checksum.
+fn infer_descriptor(script: &Script, script_type: TxoutType, address: Option<&str>) -> String {
+    match script_type {
+        TxoutType::PubKey => {
+            if let Some(key) = p2pk_public_key(script) {
+                return format!("pk({})", key.to_lower_hex_string());
+            }
+        }
+        TxoutType::MultiSig => {
+            if let Some((required, keys)) = multisig_keys(script) {
+                let keys: Vec<String> = keys.iter().map(|k| k.to_lower_hex_string()).collect();
+                return format!("multi({},{})", required, keys.join(","));
+            }
+        }
+        TxoutType::WitnessV1Taproot => {
+            if let Ok(key) = XOnlyPublicKey::from_slice(&script.as_bytes()[2..]) {
+                return format!("rawtr({})", key);
+            }
+        }
+        _ => {}
+    }
+    match address {
+        Some(address) => format!("addr({})", address),
+        None => format!("raw({})", script.to_hex_string()),
+    }
+}
+
+/// Append the descriptor checksum (BIP380).
+fn add_checksum(desc: &str) -> String {
+    const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}\
+                                 IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~\
+                                 ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+    const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+    fn poly_mod(c: u64, val: u64) -> u64 {
+        let c0 = c >> 35;
+        let mut c = ((c & 0x7ffffffff) << 5) ^ val;
+        for (bit, generator) in [
+            0xf5dee51989,
+            0xa9fdca3312,
+            0x1bab10e32d,
+            0x3706b1677a,
+            0x644d626ffd,
+        ]
+        .iter()
+        .enumerate()
+        {
+            if c0 & (1 << bit) != 0 {
+                c ^= generator;
+            }
+        }
+        c
+    }
+
+    let mut c = 1;
+    let mut cls = 0;
+    let mut cls_count = 0;
+    for ch in desc.chars() {
+        // descriptors are ascii, the charset covers everything we produce
+        let pos = match INPUT_CHARSET.find(ch) {
+            Some(pos) => pos as u64,
+            None => return desc.to_string(),
+        };
+        c = poly_mod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        cls_count += 1;
+        if cls_count == 3 {
+            c = poly_mod(c, cls);
+            cls = 0;
+            cls_count = 0;
+        }
+    }
+    if cls_count > 0 {
+        c = poly_mod(c, cls);
+    }
+    for _ in 0..8 {
+        c = poly_mod(c, 0);
+    }
+    c ^= 1;
+    let checksum: String = (0..8)
+        .map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+        .collect();
+    format!("{}#{}", desc, checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::ScriptBuf;
+
+    fn script(hex: &str) -> ScriptBuf {
+        ScriptBuf::from_hex(hex).unwrap()
+    }
+
+    #[test]
+    fn test_descriptor_checksum() {
+        assert_eq!(add_checksum("raw(deadbeef)"), "raw(deadbeef)#89f8spxm");
+    }
+
+    #[test]
+    fn test_script_pubkey() {
+        // output of the genesis coinbase
+        let key = "04678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5f";
+        let spk = RpcScriptPubKey::new(&script(&format!("41{}ac", key)));
+        assert_eq!(spk.script_type, "pubkey");
+        assert_eq!(spk.address, None);
+        assert_eq!(spk.asm, format!("{} OP_CHECKSIG", key));
+        assert!(spk.desc.starts_with(&format!("pk({})#", key)));
+
+        let spk = RpcScriptPubKey::new(&script("0014751e76e8199196d454941c45d1b3a323f1433bd6"));
+        assert_eq!(spk.script_type, "witness_v0_keyhash");
+        assert_eq!(
+            spk.address.as_deref(),
+            Some("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4")
+        );
+        assert!(spk
+            .desc
+            .starts_with("addr(bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4)#"));
+
+        let spk = RpcScriptPubKey::new(&script("6a0b68656c6c6f20776f726c64"));
+        assert_eq!(spk.script_type, "nulldata");
+        assert_eq!(spk.address, None);
+        assert!(spk.desc.starts_with("raw(6a0b68656c6c6f20776f726c64)#"));
+    }
+
+    #[test]
+    fn test_multisig() {
+        let key = "022df8750480ad5b26950b25c7ba79d3e37d75f640f8e5d9bcd5b150a0f85014da";
+        let spk = RpcScriptPubKey::new(&script(&format!("5121{}21{}52ae", key, key)));
+        assert_eq!(spk.script_type, "multisig");
+        assert!(spk.desc.starts_with(&format!("multi(1,{},{})#", key, key)));
+        // m > n
+        let spk = RpcScriptPubKey::new(&script(&format!("5321{}21{}52ae", key, key)));
+        assert_eq!(spk.script_type, "nonstandard");
+    }
+
+    #[test]
+    fn test_amount_decimals() {
+        let vout = |value| RpcVout {
+            value,
+            n: 0,
+            script_pubkey: RpcScriptPubKey::new(&script("6a")),
+        };
+        for (value, json) in [
+            (1e-8, "0.00000001"),
+            (0.1, "0.10000000"),
+            (50.0, "50.00000000"),
+            (20999999.9769, "20999999.97690000"),
+        ] {
+            let serialized = serde_json::to_string(&vout(value)).unwrap();
+            assert!(serialized.starts_with(&format!(r#"{{"value":{},"#, json)));
+            let parsed: RpcVout = serde_json::from_str(&serialized).unwrap();
+            assert_eq!(parsed, vout(value));
+        }
+    }
+
+    #[test]
+    fn test_difficulty() {
+        assert_eq!(difficulty(0x1d00ffff), 1.0);
+        assert!((difficulty(0x1b0404cb) - 16307.420938523983).abs() < 1e-6);
+    }
+}
@@ -1,5 +1,6 @@
 //! Add multi-sig pattern recognition and decode addresses from multi-sig script.
 
+use crate::parser::undo::MAX_SCRIPT_SIZE;
 use bitcoin::blockdata::opcodes::{all, Opcode};
 use bitcoin::blockdata::script::Instruction;
 use bitcoin::hashes::{hash160, Hash};
@@ -20,7 +21,14 @@ pub enum ScriptType {
     Pay2ScriptHash,
     Pay2WitnessPublicKeyHash,
     Pay2WitnessScriptHash,
-    WitnessProgram,
+    /// Segwit version 1 output with a 32-byte program (BIP341).
+    Pay2Taproot,
+    /// Anyone-can-spend anchor output `OP_1 <0x4e73>`.
+    Pay2Anchor,
+    /// Witness program of a version without defined semantics.
+    WitnessUnknown {
+        version: u8,
+    },
     NotRecognised,
 }
 
@@ -34,14 +42,16 @@ impl std::fmt::Display for ScriptType {
             Self::Pay2ScriptHash => write!(f, "Pay2ScriptHash"),
             Self::Pay2WitnessPublicKeyHash => write!(f, "Pay2WitnessPublicKeyHash"),
             Self::Pay2WitnessScriptHash => write!(f, "Pay2WitnessScriptHash"),
-            Self::WitnessProgram => write!(f, "WitnessProgram"),
+            Self::Pay2Taproot => write!(f, "Pay2Taproot"),
+            Self::Pay2Anchor => write!(f, "Pay2Anchor"),
+            Self::WitnessUnknown { version } => write!(f, "WitnessUnknown({version})"),
             Self::NotRecognised => write!(f, "NotRecognised"),
         }
     }
 }
 
 /// `ScriptInfo` stores a list of addresses extracted from ScriptPubKey.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct ScriptInfo {
     pub addresses: Vec<Address>,
     pub pattern: ScriptType,
@@ -74,8 +84,12 @@ pub fn evaluate_script(script: &Script, net: Network) -> ScriptInfo {
         ScriptInfo::new(address, ScriptType::Pay2WitnessPublicKeyHash)
     } else if script.is_p2wsh() {
         ScriptInfo::new(address, ScriptType::Pay2WitnessScriptHash)
-    } else if script.is_witness_program() {
-        ScriptInfo::new(address, ScriptType::WitnessProgram)
+    } else if script.is_p2tr() {
+        ScriptInfo::new(address, ScriptType::Pay2Taproot)
+    } else if is_p2a(script) {
+        ScriptInfo::new(address, ScriptType::Pay2Anchor)
+    } else if let Some(version) = witness_unknown_version(script) {
+        ScriptInfo::new(address, ScriptType::WitnessUnknown { version })
     } else if script.is_op_return() {
         ScriptInfo::new(address, ScriptType::OpReturn)
     } else if is_multisig(script) {
@@ -85,6 +99,67 @@ pub fn evaluate_script(script: &Script, net: Network) -> ScriptInfo {
     }
 }
 
+/// Pay-to-anchor output: `OP_1 <0x4e73>`.
+#[inline]
+fn is_p2a(script: &Script) -> bool {
+    script.as_bytes() == [0x51, 0x02, 0x4e, 0x73]
+}
+
+/// Version of a witness program that is neither version 0 nor a known
+/// version 1 output.
+///
+/// Version 0 programs of other lengths than 20 or 32 bytes are invalid,
+/// and are not recognised.
+#[inline]
+fn witness_unknown_version(script: &Script) -> Option<u8> {
+    if !script.is_witness_program() {
+        return None;
+    }
+    match script.witness_version()?.to_num() {
+        0 => None,
+        version => Some(version),
+    }
+}
+
+/// Outputs that are never added to the UTXO set (`CScript::IsUnspendable`).
+#[inline]
+pub(crate) fn is_unspendable(script: &Script) -> bool {
+    script.is_op_return() || script.len() > MAX_SCRIPT_SIZE
+}
+
+/// Parameters of a `m-of-n` multisig script.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct MultiSig {
+    /// Number of signatures required (`m`).
+    pub required: u8,
+    /// Number of public keys (`n`).
+    pub total: u8,
+    /// Public keys of the script, invalid keys are skipped.
+    pub public_keys: Vec<PublicKey>,
+}
+
+/// Decode the parameters of a multisig script, `None` if it is not multisig.
+pub fn parse_multisig(script: &Script) -> Option<MultiSig> {
+    if !is_multisig(script) {
+        return None;
+    }
+    let ops: Vec<Instruction> = script.instructions().filter_map(|o| o.ok()).collect();
+    let required = get_num_keys(ops.first()?)? as u8;
+    let total = get_num_keys(ops.get(ops.len() - 2)?)? as u8;
+    let public_keys = ops[1..ops.len() - 2]
+        .iter()
+        .filter_map(|op| match op {
+            PushBytes(data) => PublicKey::from_slice(data.as_bytes()).ok(),
+            Op(_) => None,
+        })
+        .collect();
+    Some(MultiSig {
+        required,
+        total,
+        public_keys,
+    })
+}
+
 /// translated from Bitcoinj:
 /// [isSentToMultisig()](https://github.com/bitcoinj/bitcoinj/blob/d3d5edbcbdb91b25de4df3b6ed6740d7e2329efc/core/src/main/java/org/bitcoinj/script/ScriptPattern.java#L225:L246)
 fn is_multisig(script: &Script) -> bool {
@@ -238,7 +313,7 @@ impl Cmp for bitcoin::blockdata::opcodes::Opcode {
 
 #[cfg(test)]
 mod tests {
-    use super::{evaluate_script, ScriptType};
+    use super::{evaluate_script, parse_multisig, ScriptType};
     use bitcoin::{Network, Script};
 
     #[test]
@@ -297,6 +372,15 @@ mod tests {
 
         let result = evaluate_script(Script::from_bytes(&bytes), Network::Bitcoin);
         assert_eq!(result.pattern, ScriptType::Pay2MultiSig);
+
+        let multisig = parse_multisig(Script::from_bytes(&bytes)).unwrap();
+        assert_eq!(multisig.required, 2);
+        assert_eq!(multisig.total, 3);
+        assert_eq!(multisig.public_keys.len(), 3);
+        assert_eq!(
+            multisig.public_keys[0].to_string(),
+            "022df8750480ad5b26950b25c7ba79d3e37d75f640f8e5d9bcd5b150a0f85014da"
+        );
     }
 
     #[test]
@@ -316,6 +400,59 @@ mod tests {
         assert_eq!(result.pattern, ScriptType::Pay2ScriptHash);
     }
 
+    #[test]
+    fn test_bitcoin_script_p2tr() {
+        // BIP86 first receiving address
+        let bytes = [
+            0x51_u8, 0x20, // OP_1, OP_PUSHBYTES_32
+            0xa6, 0x08, 0x69, 0xf0, 0xdb, 0xcf, 0x1d, 0xc6, 0x59, 0xc9, 0xce, 0xcb, 0xaf, 0x80,
+            0x50, 0x13, 0x5e, 0xa9, 0xe8, 0xcd, 0xc4, 0x87, 0x05, 0x3f, 0x1d, 0xc6, 0x88, 0x09,
+            0x49, 0xdc, 0x68, 0x4c,
+        ];
+        let result = evaluate_script(Script::from_bytes(&bytes), Network::Bitcoin);
+        assert_eq!(
+            result.addresses.first().unwrap().to_string(),
+            String::from("bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr")
+        );
+        assert_eq!(result.pattern, ScriptType::Pay2Taproot);
+    }
+
+    #[test]
+    fn test_bitcoin_script_p2a() {
+        // OP_1 OP_PUSHBYTES_2 4e73
+        let bytes = [0x51_u8, 0x02, 0x4e, 0x73];
+        let result = evaluate_script(Script::from_bytes(&bytes), Network::Bitcoin);
+        assert_eq!(
+            result.addresses.first().unwrap().to_string(),
+            String::from("bc1pfeessrawgf")
+        );
+        assert_eq!(result.pattern, ScriptType::Pay2Anchor);
+    }
+
+    #[test]
+    fn test_bitcoin_script_witness_unknown() {
+        // BIP350 test vector: OP_16 OP_PUSHBYTES_2 751e
+        let bytes = [0x60_u8, 0x02, 0x75, 0x1e];
+        let result = evaluate_script(Script::from_bytes(&bytes), Network::Bitcoin);
+        assert_eq!(
+            result.addresses.first().unwrap().to_string(),
+            String::from("bc1sw50qgdz25j")
+        );
+        assert_eq!(result.pattern, ScriptType::WitnessUnknown { version: 16 });
+
+        // version 1 program of 20 bytes
+        let mut bytes = vec![0x51_u8, 0x14];
+        bytes.extend([0x11; 20]);
+        let result = evaluate_script(Script::from_bytes(&bytes), Network::Bitcoin);
+        assert_eq!(result.pattern, ScriptType::WitnessUnknown { version: 1 });
+
+        // version 0 program of invalid length
+        let mut bytes = vec![0x00_u8, 0x18];
+        bytes.extend([0x11; 24]);
+        let result = evaluate_script(Script::from_bytes(&bytes), Network::Bitcoin);
+        assert_eq!(result.pattern, ScriptType::NotRecognised);
+    }
+
     #[test]
     fn test_bitcoin_script_non_standard() {
         // Raw output script: 736372697074
@@ -106,13 +106,15 @@ impl TxDB {
             return Ok(0);
         }
         let tx_pos = self.get_tx_position(txid)?;
-        match self
-            .file_pos_to_height
+        self.get_position_height(&tx_pos)
+            .ok_or(Error::CannotFindHeightForTransaction(txid))
+    }
+
+    /// Height of the block containing the transaction at `tx_pos`.
+    pub(crate) fn get_position_height(&self, tx_pos: &TransactionPosition) -> Option<usize> {
+        self.file_pos_to_height
             .get(&(tx_pos.n_file, tx_pos.n_data_pos))
-        {
-            Some(height) => Ok(*height as usize),
-            None => Err(Error::CannotFindHeightForTransaction(txid)),
-        }
+            .map(|height| *height as usize)
     }
 }
 
@@ -0,0 +1,230 @@
+//! Decode block undo data (`rev*.dat` files).
+//!
+//! For every block connected to the active chain, Bitcoin Core writes the
+//! outputs spent by that block into the undo files, so that the block can
+//! be disconnected again. Each spent output is stored together with the
+//! height of the block that created it and whether it was a coinbase output.
+//!
+//! Outputs are stored in Core's compressed format, see
+//! [compressor.h](https://github.com/bitcoin/bitcoin/blob/master/src/compressor.h).
+
+use crate::parser::error::{Error, Result};
+use crate::parser::reader::BlockchainRead;
+use bitcoin::blockdata::opcodes::all;
+use bitcoin::consensus::encode::VarInt;
+use bitcoin::consensus::{Decodable, Encodable};
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::{Amount, OutPoint, ScriptBuf, TxOut};
+use serde::{Deserialize, Serialize};
+
+/// Scripts larger than this are unspendable (`MAX_SCRIPT_SIZE` in Bitcoin Core).
+pub(crate) const MAX_SCRIPT_SIZE: usize = 10000;
+
+/// Number of special script encodings used by `ScriptCompression`.
+const N_SPECIAL_SCRIPTS: usize = 6;
+
+/// An unspent output together with the metadata kept by Bitcoin Core.
+///
+/// `O` is the format of the output, e.g. `FullTxOut` in connected blocks.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Coin<O = TxOut> {
+    pub out: O,
+    /// Height of the block containing the transaction that created this output.
+    pub height: u32,
+    /// Whether the output was created by a coinbase transaction.
+    pub is_coinbase: bool,
+}
+
+impl<O> Coin<O> {
+    /// Convert the output to another format.
+    pub fn map<P>(self, f: impl FnOnce(O) -> P) -> Coin<P> {
+        Coin {
+            out: f(self.out),
+            height: self.height,
+            is_coinbase: self.is_coinbase,
+        }
+    }
+}
+
+impl Coin {
+    /// Serialize `outpoint` and this coin as Bitcoin Core does when hashing
+    /// the UTXO set (`TxOutSer` in `kernel/coinstats.cpp`).
+    pub(crate) fn utxo_hash_preimage(&self, outpoint: &OutPoint) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(32 + 4 + 4 + 8 + 1 + self.out.script_pubkey.len());
+        outpoint
+            .consensus_encode(&mut bytes)
+            .expect("in-memory writers don't error");
+        let code = (self.height << 1) + self.is_coinbase as u32;
+        code.consensus_encode(&mut bytes)
+            .expect("in-memory writers don't error");
+        self.out
+            .consensus_encode(&mut bytes)
+            .expect("in-memory writers don't error");
+        bytes
+    }
+}
+
+/// Outputs spent by a single (non-coinbase) transaction, in input order.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct TxUndo {
+    pub prevouts: Vec<Coin>,
+}
+
+/// Undo data of a block, i.e., `CBlockUndo` in Bitcoin Core.
+///
+/// `txdata` has one entry for every transaction of the block except the coinbase.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+pub struct BlockUndo {
+    pub txdata: Vec<TxUndo>,
+}
+
+impl BlockUndo {
+    /// Decode `CBlockUndo` from the raw bytes stored in a rev file.
+    pub(crate) fn decode<R: BlockchainRead>(reader: &mut R) -> Result<Self> {
+        let n_tx = VarInt::consensus_decode(reader)?.0 as usize;
+        let mut txdata = Vec::with_capacity(n_tx);
+        for _ in 0..n_tx {
+            let n_prevouts = VarInt::consensus_decode(reader)?.0 as usize;
+            let mut prevouts = Vec::with_capacity(n_prevouts);
+            for _ in 0..n_prevouts {
+                prevouts.push(read_coin(reader)?);
+            }
+            txdata.push(TxUndo { prevouts });
+        }
+        Ok(Self { txdata })
+    }
+}
+
+/// Read a spent output (`TxInUndoFormatter` in Bitcoin Core).
+fn read_coin<R: BlockchainRead>(reader: &mut R) -> Result<Coin> {
+    let code = reader.read_varint()?;
+    let height = (code >> 1) as u32;
+    let is_coinbase = code & 1 == 1;
+    if height > 0 {
+        // Old versions stored the transaction version, which is now unused.
+        reader.read_varint()?;
+    }
+    let value = decompress_amount(reader.read_varint()? as u64);
+    let script_pubkey = read_compressed_script(reader)?;
+    Ok(Coin {
+        out: TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey,
+        },
+        height,
+        is_coinbase,
+    })
+}
+
+/// Read a script stored with `ScriptCompression`.
+fn read_compressed_script<R: BlockchainRead>(reader: &mut R) -> Result<ScriptBuf> {
+    let n_size = reader.read_varint()?;
+    let script = match n_size {
+        0x00 => {
+            let hash = reader.read_vec_u8(20)?;
+            let mut script = Vec::with_capacity(25);
+            script.extend([all::OP_DUP.to_u8(), all::OP_HASH160.to_u8(), 20]);
+            script.extend(hash);
+            script.extend([all::OP_EQUALVERIFY.to_u8(), all::OP_CHECKSIG.to_u8()]);
+            script
+        }
+        0x01 => {
+            let hash = reader.read_vec_u8(20)?;
+            let mut script = Vec::with_capacity(23);
+            script.extend([all::OP_HASH160.to_u8(), 20]);
+            script.extend(hash);
+            script.push(all::OP_EQUAL.to_u8());
+            script
+        }
+        0x02 | 0x03 => {
+            let x = reader.read_u256()?;
+            let mut script = Vec::with_capacity(35);
+            script.extend([33, n_size as u8]);
+            script.extend(x);
+            script.push(all::OP_CHECKSIG.to_u8());
+            script
+        }
+        0x04 | 0x05 => {
+            let x = reader.read_u256()?;
+            let mut compressed = [0u8; 33];
+            compressed[0] = n_size as u8 - 2;
+            compressed[1..].copy_from_slice(&x);
+            let pubkey = PublicKey::from_slice(&compressed).map_err(|e| {
+                Error::InvalidUndoData(format!("cannot decompress public key: {e}"))
+            })?;
+            let mut script = Vec::with_capacity(67);
+            script.push(65);
+            script.extend(pubkey.serialize_uncompressed());
+            script.push(all::OP_CHECKSIG.to_u8());
+            script
+        }
+        _ => {
+            let size = n_size - N_SPECIAL_SCRIPTS;
+            let script = reader.read_vec_u8(size as u32)?;
+            if size > MAX_SCRIPT_SIZE {
+                // Core replaces oversized scripts by a single OP_RETURN.
+                vec![all::OP_RETURN.to_u8()]
+            } else {
+                script
+            }
+        }
+    };
+    Ok(ScriptBuf::from_bytes(script))
+}
+
+/// Reverse `CompressAmount` of Bitcoin Core.
+fn decompress_amount(mut x: u64) -> u64 {
+    // x = 0  OR  x = 1+10*(9*n + d - 1) + e  OR  x = 1+10*(n - 1) + 9
+    if x == 0 {
+        return 0;
+    }
+    x -= 1;
+    // x = 10*(9*n + d - 1) + e
+    let mut e = x % 10;
+    x /= 10;
+    let mut n = if e < 9 {
+        // x = 9*n + d - 1
+        let d = (x % 9) + 1;
+        x /= 9;
+        // x = n
+        x * 10 + d
+    } else {
+        x + 1
+    };
+    while e > 0 {
+        n *= 10;
+        e -= 1;
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::io::Cursor;
+
+    #[test]
+    fn test_decompress_amount() {
+        // test vectors from Bitcoin Core `compress_tests.cpp`
+        assert_eq!(decompress_amount(0x0), 0);
+        assert_eq!(decompress_amount(0x1), 1);
+        assert_eq!(decompress_amount(0x7), 1_000_000);
+        assert_eq!(decompress_amount(0x9), 100_000_000);
+        assert_eq!(decompress_amount(0x32), 5_000_000_000);
+        assert_eq!(decompress_amount(0x1406f40), 21_000_000 * 100_000_000);
+    }
+
+    #[test]
+    fn test_read_coin() {
+        // height 120891, not coinbase, 110397 sat, P2PKH
+        let bytes = [
+            0x8d, 0xdf, 0x76, 0x00, 0xbb, 0xd1, 0x23, 0x00, 0x1d, 0x39, 0xd0, 0x1c, 0xcb, 0x7c,
+            0x5e, 0x5f, 0x43, 0x3c, 0x29, 0x3c, 0x6b, 0x8e, 0x4b, 0x02, 0x38, 0x7c, 0x52, 0x8e,
+        ];
+        let coin = read_coin(&mut Cursor::new(&bytes[..])).unwrap();
+        assert_eq!(coin.height, 120891);
+        assert!(!coin.is_coinbase);
+        assert_eq!(coin.out.value.to_sat(), 110397);
+        assert!(coin.out.script_pubkey.is_p2pkh());
+    }
+}
@@ -4,79 +4,157 @@
 //!
 //! - See https://github.com/bitcoin/bitcoin/pull/28052
 
-use std::io::{Read, Seek, SeekFrom};
+use std::convert::TryInto;
+use std::io::{BufRead, Read, Seek, SeekFrom};
 
 /// XOR mask length. It's the length of file `blocks/xor.dat`.
 pub const XOR_MASK_LEN: usize = 8;
 
-/// Transparent reader for XOR'd blk*.dat files.
+/// Size of the buffer of [`XorReader`], the default of [`std::io::BufReader`].
+const BUFFER_SIZE: usize = 8 * 1024;
+
+/// XOR `buf` in place with `mask`, `buf` starting at `pos` in the file.
+///
+/// The bytes before the first multiple of [`XOR_MASK_LEN`] are XOR'd one by one,
+/// the rest 8 bytes at a time with the mask as a word.
+pub fn xor_in_place(buf: &mut [u8], mask: &[u8; XOR_MASK_LEN], pos: u64) {
+    let misalignment = (pos % XOR_MASK_LEN as u64) as usize;
+    let head = ((XOR_MASK_LEN - misalignment) % XOR_MASK_LEN).min(buf.len());
+    let (head, body) = buf.split_at_mut(head);
+    for (i, x) in head.iter_mut().enumerate() {
+        *x ^= mask[misalignment + i];
+    }
+
+    // `body` starts at a multiple of the mask length: the mask applies as is.
+    let word = u64::from_ne_bytes(*mask);
+    let mut words = body.chunks_exact_mut(XOR_MASK_LEN);
+    for chunk in &mut words {
+        let x = u64::from_ne_bytes(chunk[..].try_into().unwrap()) ^ word;
+        chunk.copy_from_slice(&x.to_ne_bytes());
+    }
+    for (x, m) in words.into_remainder().iter_mut().zip(mask) {
+        *x ^= m;
+    }
+}
+
+/// Transparent buffered reader for XOR'd blk*.dat files.
+///
+/// Without a mask, it behaves as a [`std::io::BufReader`].
 pub struct XorReader<R: Read> {
     /// Inner reader.
     inner: R,
-    /// Stream position. This is expected to be synchronous with [`Seek::stream_position`],
-    /// but without a syscall to fetch it.
+    /// Stream position of the next byte returned, i.e. of `buffer[start]`.
+    ///
+    /// This is synchronous with [`Seek::stream_position`], but without a syscall to fetch it.
     pos: u64,
     /// XOR mask if one exists.
     mask: Option<[u8; XOR_MASK_LEN]>,
-    buffer: Vec<u8>,
+    /// Bytes read from `inner`, with the mask removed.
+    buffer: Box<[u8]>,
+    /// `buffer[start..end]` is not consumed yet.
+    start: usize,
+    end: usize,
 }
 
 impl<R: Read> XorReader<R> {
     /// Create a reader wrapper that performs XOR on reads.
+    ///
+    /// `reader` must be at the start of the stream, otherwise
+    /// seek it, or call [`Seek::stream_position`] on the `XorReader`.
     pub fn new(reader: R, xor_mask: Option<[u8; XOR_MASK_LEN]>) -> Self {
         Self {
             inner: reader,
             pos: 0,
             mask: xor_mask,
-            buffer: Vec::new(),
+            buffer: vec![0; BUFFER_SIZE].into_boxed_slice(),
+            start: 0,
+            end: 0,
         }
     }
+
+    fn discard_buffer(&mut self) {
+        self.start = 0;
+        self.end = 0;
+    }
+}
+
+/// Read from `inner` into `buf`, which starts at `pos` in the stream, and remove the mask.
+fn read_unmasked<R: Read>(
+    inner: &mut R,
+    mask: &Option<[u8; XOR_MASK_LEN]>,
+    pos: u64,
+    buf: &mut [u8],
+) -> std::io::Result<usize> {
+    let n = inner.read(buf)?;
+    if let Some(mask) = mask {
+        xor_in_place(&mut buf[..n], mask, pos);
+    }
+    Ok(n)
 }
 
 impl<R: Read> Read for XorReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let size = self.inner.read(buf)?;
-        if let Some(mask) = self.mask {
-            for x in &mut buf[..size] {
-                *x ^= mask[(self.pos % mask.len() as u64) as usize];
-                self.pos += 1;
-            }
+        // bypass the buffer for large reads, as `BufReader` does.
+        if self.start == self.end && buf.len() >= self.buffer.len() {
+            let n = read_unmasked(&mut self.inner, &self.mask, self.pos, buf)?;
+            self.pos += n as u64;
+            return Ok(n);
         }
-        Ok(size)
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
     }
 }
 
-impl<R: Read> std::io::BufRead for XorReader<R> {
+impl<R: Read> BufRead for XorReader<R> {
     fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
-        if self.buffer.is_empty() {
-            let mut temp_buf = vec![0; 4096]; // Or some appropriate buffer size
-            let n = self.inner.read(&mut temp_buf)?;
-            if n == 0 {
-                return Ok(&[]); // End of stream
-            }
-            // Apply XOR mask to the buffer
-            if let Some(mask) = &self.mask {
-                for i in temp_buf.iter_mut().take(n) {
-                    *i ^= mask[(self.pos % mask.len() as u64) as usize];
-                    self.pos += 1;
-                }
-            }
-            self.buffer = temp_buf[..n].to_vec(); // Store the modified buffer
+        if self.start == self.end {
+            self.discard_buffer();
+            self.end = read_unmasked(&mut self.inner, &self.mask, self.pos, &mut self.buffer)?;
         }
-        Ok(&self.buffer) // Return the current buffer
+        Ok(&self.buffer[self.start..self.end])
     }
 
     fn consume(&mut self, amt: usize) {
-        self.buffer.drain(..amt);
+        let amt = amt.min(self.end - self.start);
+        self.start += amt;
+        self.pos += amt as u64;
     }
 }
 
 impl<R: Seek + Read> Seek for XorReader<R> {
     fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
-        let result = self.inner.seek(pos);
-        // Just use a syscall to update the current position.
-        self.pos = self.inner.stream_position()?;
-        result
+        let remaining = (self.end - self.start) as i64;
+        // seeking inside the buffer keeps it.
+        let offset = match pos {
+            SeekFrom::Start(p) => Some(p as i64 - self.pos as i64),
+            SeekFrom::Current(n) => Some(n),
+            SeekFrom::End(_) => None,
+        };
+        if let Some(offset) = offset {
+            if -(self.start as i64) <= offset && offset <= remaining {
+                self.start = (self.start as i64 + offset) as usize;
+                self.pos = (self.pos as i64 + offset) as u64;
+                return Ok(self.pos);
+            }
+        }
+        // the inner reader is ahead of `self.pos` by the unconsumed bytes.
+        let pos = match pos {
+            SeekFrom::Current(n) => SeekFrom::Current(n - remaining),
+            pos => pos,
+        };
+        // on error, the inner reader has not moved: keep the buffer.
+        self.pos = self.inner.seek(pos)?;
+        self.discard_buffer();
+        Ok(self.pos)
+    }
+
+    fn stream_position(&mut self) -> std::io::Result<u64> {
+        let remaining = (self.end - self.start) as u64;
+        self.pos = self.inner.stream_position()? - remaining;
+        Ok(self.pos)
     }
 }
 
@@ -98,3 +176,118 @@ impl<R: Read> bitcoin::io::BufRead for XorReader<R> {
         std::io::BufRead::consume(self, amount)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const MASK: [u8; XOR_MASK_LEN] = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
+
+    fn xor_bytewise(buf: &mut [u8], mask: &[u8; XOR_MASK_LEN], pos: u64) {
+        for (i, x) in buf.iter_mut().enumerate() {
+            *x ^= mask[((pos + i as u64) % XOR_MASK_LEN as u64) as usize];
+        }
+    }
+
+    /// `len` bytes and their obfuscation with `MASK`.
+    fn data(len: usize) -> (Vec<u8>, Vec<u8>) {
+        let plain: Vec<u8> = (0..len).map(|i| (i * 7 + i / 256) as u8).collect();
+        let mut obfuscated = plain.clone();
+        xor_bytewise(&mut obfuscated, &MASK, 0);
+        (plain, obfuscated)
+    }
+
+    #[test]
+    fn test_xor_in_place() {
+        for pos in 0..2 * XOR_MASK_LEN as u64 {
+            for len in 0..40 {
+                let (plain, _) = data(len);
+                let mut expected = plain.clone();
+                xor_bytewise(&mut expected, &MASK, pos);
+                let mut actual = plain;
+                xor_in_place(&mut actual, &MASK, pos);
+                assert_eq!(actual, expected, "pos {pos}, len {len}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_parity() {
+        let (plain, obfuscated) = data(3 * BUFFER_SIZE + 13);
+        for chunk in [1, 3, 8, 1000, BUFFER_SIZE, 2 * BUFFER_SIZE + 5] {
+            let mut r = XorReader::new(Cursor::new(&obfuscated), Some(MASK));
+            let mut read = Vec::new();
+            let mut buf = vec![0; chunk];
+            loop {
+                let n = r.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                read.extend_from_slice(&buf[..n]);
+            }
+            assert_eq!(read, plain, "chunk {chunk}");
+
+            let mut r = XorReader::new(Cursor::new(&plain), None);
+            let mut read = Vec::new();
+            r.read_to_end(&mut read).unwrap();
+            assert_eq!(read, plain);
+        }
+    }
+
+    #[test]
+    fn test_seek() {
+        let (plain, obfuscated) = data(3 * BUFFER_SIZE);
+        let mut r = XorReader::new(Cursor::new(&obfuscated), Some(MASK));
+        let mut buf = [0u8; 5];
+        let mut check = |r: &mut XorReader<Cursor<&Vec<u8>>>, seek: SeekFrom, expected: u64| {
+            assert_eq!(r.seek(seek).unwrap(), expected);
+            r.read_exact(&mut buf).unwrap();
+            assert_eq!(buf[..], plain[expected as usize..expected as usize + 5]);
+            assert_eq!(r.stream_position().unwrap(), expected + 5);
+        };
+        // inside the buffer, forward and backward.
+        check(&mut r, SeekFrom::Start(3), 3);
+        check(&mut r, SeekFrom::Current(100), 108);
+        check(&mut r, SeekFrom::Current(-50), 63);
+        check(&mut r, SeekFrom::Start(0), 0);
+        // outside the buffer.
+        check(
+            &mut r,
+            SeekFrom::Start(BUFFER_SIZE as u64 + 1),
+            BUFFER_SIZE as u64 + 1,
+        );
+        check(&mut r, SeekFrom::Current(-2000), BUFFER_SIZE as u64 - 1994);
+        check(&mut r, SeekFrom::End(-7), 3 * BUFFER_SIZE as u64 - 7);
+        check(&mut r, SeekFrom::Current(-3 * BUFFER_SIZE as i64 + 2), 0);
+    }
+
+    #[test]
+    fn test_failed_seek() {
+        let (plain, obfuscated) = data(3 * BUFFER_SIZE);
+        let mut r = XorReader::new(Cursor::new(&obfuscated), Some(MASK));
+        let mut buf = [0u8; 5];
+        r.read_exact(&mut buf).unwrap();
+        // before the start of the stream, outside the buffer.
+        assert!(r.seek(SeekFrom::Current(-100)).is_err());
+        assert_eq!(r.stream_position().unwrap(), 5);
+        r.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..], plain[5..10]);
+        let mut rest = Vec::new();
+        r.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, plain[10..]);
+    }
+
+    #[test]
+    fn test_stream_position_sync() {
+        // the inner reader is not at the start of the stream.
+        let (plain, obfuscated) = data(100);
+        let mut inner = Cursor::new(&obfuscated);
+        inner.set_position(21);
+        let mut r = XorReader::new(inner, Some(MASK));
+        assert_eq!(r.stream_position().unwrap(), 21);
+        let mut read = Vec::new();
+        r.read_to_end(&mut read).unwrap();
+        assert_eq!(read, plain[21..]);
+    }
+}
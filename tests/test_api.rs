@@ -5,12 +5,17 @@
 //!
 #[cfg(test)]
 mod iterator_tests {
-    use bitcoin::{Block, Transaction};
+    use bitcoin::{Block, BlockHash, OutPoint, Transaction, TxOut};
+    use bitcoin_explorer::parser::error::Error;
     use bitcoin_explorer::{
-        BitcoinDB, CompactBlock, CompactConnectedBlock, CompactConnectedTransaction,
-        CompactTransaction, FullBlock, FullTransaction,
+        BitcoinDB, BlockContext, BlockProjection, BlockView, Coin, CompactBlock,
+        CompactConnectedBlock, CompactConnectedTransaction, CompactTransaction,
+        ConnectedIterOptions, FullBlock, FullConnectedBlock, FullTransaction, InputType,
+        TxVerbosity, UtxoBackend, UtxoBackendKind,
     };
+    use std::collections::HashMap;
     use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
 
     const END: usize = 700000;
 
@@ -85,6 +90,33 @@ mod iterator_tests {
         }
     }
 
+    #[cfg(feature = "mmap")]
+    #[test]
+    /// memory-mapped blk files return the same data
+    fn test_mmap_blk_files() {
+        let db = get_test_db();
+        let mapped = BitcoinDB::new_mmap(
+            &PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("./resources/tests/Bitcoin"),
+            true,
+            2,
+        )
+        .unwrap();
+
+        for h in (0..100000).step_by(97) {
+            let raw = mapped.get_raw_block(h).unwrap();
+            assert_eq!(raw, db.get_raw_block(h).unwrap());
+            let blk = db.get_block::<Block>(h).unwrap();
+            for tx in blk.txdata {
+                assert_eq!(
+                    mapped
+                        .get_transaction::<Transaction>(tx.compute_txid())
+                        .unwrap(),
+                    tx
+                );
+            }
+        }
+    }
+
     #[test]
     /// iterate through all blocks
     fn test_iter_connected() {
@@ -93,7 +125,10 @@ mod iterator_tests {
         let mut h = 0;
         for blk in db.connected_block_iter::<CompactConnectedBlock>(END) {
             // check that blocks are produced in correct order
-            assert_eq!(blk.header, db.get_block::<CompactBlock>(h).unwrap().header);
+            let mut header = db.get_block::<CompactBlock>(h).unwrap().header;
+            // fees are only known for connected blocks
+            header.total_fees = blk.header.total_fees;
+            assert_eq!(blk.header, header);
             h += 1;
         }
         // assert that all blocks are read
@@ -174,6 +209,668 @@ mod iterator_tests {
         }
     }
 
+    #[test]
+    /// check block sizes and fees against `bitcoin::Block`
+    fn test_block_sizes_and_fees() {
+        let db = get_test_db();
+        let early_end = 100000;
+
+        for (h, blk) in db
+            .connected_block_iter::<FullConnectedBlock>(early_end)
+            .enumerate()
+            .step_by(97)
+        {
+            let block = db.get_block::<Block>(h).unwrap();
+            assert_eq!(blk.header.size, block.total_size() as u64);
+            assert_eq!(blk.header.weight, block.weight().to_wu());
+            assert_eq!(
+                blk.header.weight,
+                blk.header.stripped_size * 3 + blk.header.size
+            );
+            assert_eq!(
+                db.get_block::<FullBlock>(h).unwrap().header.total_fees,
+                None
+            );
+
+            let mut total_fees = 0;
+            for (tx, ref_tx) in blk.txdata.iter().zip(block.txdata.iter()) {
+                let input_value: u64 = tx.input.iter().map(|i| i.prevout.value).sum();
+                let output_value: u64 = tx.output.iter().map(|o| o.value).sum();
+                assert_eq!(tx.is_coinbase, ref_tx.is_coinbase());
+                if ref_tx.is_coinbase() {
+                    assert_eq!(tx.fee, Some(0));
+                } else {
+                    assert_eq!(tx.fee, Some(input_value - output_value));
+                }
+                assert_eq!(tx.weight, ref_tx.weight().to_wu());
+                assert_eq!(tx.vsize, ref_tx.vsize() as u64);
+                total_fees += tx.fee.unwrap();
+            }
+            assert_eq!(blk.header.total_fees, Some(total_fees));
+        }
+    }
+
+    #[test]
+    /// check block statistics against connected blocks
+    fn test_block_stats_iter() {
+        let db = get_test_db();
+        let early_end = 100000;
+
+        let mut h = 0;
+        for (stats, blk) in db
+            .block_stats_iter(0, early_end)
+            .zip(db.connected_block_iter::<FullConnectedBlock>(early_end))
+        {
+            assert_eq!(stats.height, h);
+            assert_eq!(stats.blockhash, blk.header.block_hash);
+            assert_eq!(Some(stats.totalfee), blk.header.total_fees);
+            assert_eq!(stats.txs, blk.txdata.len() as u64);
+            let coinbase_out: u64 = blk.txdata[0].output.iter().map(|o| o.value).sum();
+            assert!(coinbase_out <= stats.subsidy + stats.totalfee);
+            if h % 997 == 0 {
+                assert_eq!(db.get_block_stats(h).unwrap(), stats);
+            }
+            h += 1;
+        }
+        assert_eq!(h, early_end);
+    }
+
+    #[test]
+    /// check sigop counts and standardness of early blocks
+    fn test_sigops_and_standardness() {
+        let db = get_test_db();
+        let early_end = 100000;
+
+        for blk in db
+            .connected_block_iter::<FullConnectedBlock>(early_end)
+            .step_by(97)
+        {
+            let mut block_sigops = 0;
+            for (i, tx) in blk.txdata.iter().enumerate() {
+                // no P2SH nor segwit outputs before BIP16
+                assert_eq!(tx.sigop_cost, tx.legacy_sigop_count as u64 * 4);
+                if i == 0 {
+                    assert!(tx.is_standard());
+                }
+                block_sigops += tx.sigop_cost;
+            }
+            assert!(block_sigops <= 80000);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "verify-scripts")]
+    /// all scripts of the main chain are valid
+    fn test_verify_scripts() {
+        let db = get_test_db();
+        let early_end = 100000;
+
+        let mut h = 0;
+        for result in db.verify_scripts_iter(0, early_end) {
+            assert_eq!(result.height, h);
+            assert!(result.is_valid(), "{:?}", result.failures);
+            h += 1;
+        }
+        assert_eq!(h, early_end);
+    }
+
+    /// height, hash, and values spent by each transaction
+    #[derive(PartialEq, Debug)]
+    struct SpentValues(usize, BlockHash, Option<Vec<u64>>);
+
+    impl BlockProjection for SpentValues {
+        fn project(block: &Block, context: &BlockContext) -> Self {
+            let spent = context.tx_prevouts(block).map(|txs| {
+                txs.map(|(_, coins)| coins.iter().map(|c| c.out.value.to_sat()).sum())
+                    .collect()
+            });
+            SpentValues(context.height, context.block_hash, spent)
+        }
+    }
+
+    #[test]
+    /// projections see the same blocks and prevouts as connected blocks
+    fn test_block_projection() {
+        let db = get_test_db();
+        let end = 20000;
+
+        let projected = db
+            .block_projection_iter::<SpentValues>(0, end)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(projected.len(), end);
+        for (h, p) in projected.iter().enumerate().step_by(997) {
+            let block = db.get_block::<Block>(h).unwrap();
+            assert_eq!(*p, SpentValues(h, block.block_hash(), None));
+            assert_eq!(db.get_block_projection::<SpentValues>(h).unwrap(), *p);
+        }
+
+        let options = ConnectedIterOptions::default();
+        let connected = db
+            .connected_block_projection_iter::<SpentValues>(end, &options)
+            .zip(db.connected_block_iter::<CompactConnectedBlock>(end));
+        for (h, (p, blk)) in connected.enumerate() {
+            let SpentValues(height, hash, spent) = p.unwrap();
+            assert_eq!(height, h);
+            assert_eq!(hash, blk.header.block_hash);
+            let expected: Vec<u64> = blk
+                .txdata
+                .iter()
+                .map(|tx| tx.input.iter().map(|i| i.prevout.value).sum())
+                .collect();
+            assert_eq!(spent, Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_block_view() {
+        let db = get_test_db();
+        let end = 20000;
+
+        for h in (0..end).step_by(997) {
+            let raw = db.get_raw_block(h).unwrap();
+            let view = BlockView::new(&raw).unwrap();
+            let block = db.get_block::<Block>(h).unwrap();
+            assert_eq!(view.block_hash(), block.block_hash());
+            assert_eq!(view.tx_count(), block.txdata.len());
+            assert_eq!(view.decode().unwrap(), block);
+            for (v, tx) in view.transactions().zip(block.txdata.iter()) {
+                let v = v.unwrap();
+                assert_eq!(v.compute_txid(), tx.compute_txid());
+                let outpoints: Vec<OutPoint> = v.inputs().map(|i| i.previous_output).collect();
+                let expected: Vec<OutPoint> = tx.input.iter().map(|i| i.previous_output).collect();
+                assert_eq!(outpoints, expected);
+                let outputs: Vec<TxOut> = v.outputs().map(|o| o.to_tx_out()).collect();
+                assert_eq!(outputs, tx.output);
+            }
+        }
+
+        let values = db.block_view_iter(0, end, |h, block| {
+            let mut value = 0;
+            for tx in block.transactions() {
+                value += tx?.outputs().map(|o| o.value.to_sat()).sum::<u64>();
+            }
+            Ok((h, value))
+        });
+        for (h, item) in values.enumerate() {
+            let block = db.get_block::<CompactBlock>(h).unwrap();
+            let expected = block
+                .txdata
+                .iter()
+                .flat_map(|tx| tx.output.iter())
+                .map(|o| o.value)
+                .sum::<u64>();
+            assert_eq!(item.unwrap(), (h, expected));
+        }
+    }
+
+    #[test]
+    /// try iterators yield the same blocks, and report the failing height
+    fn test_try_iter() {
+        let db = get_test_db();
+        let end = db.get_block_count();
+
+        let blocks: Vec<CompactBlock> =
+            db.try_block_iter(0, end).collect::<Result<_, _>>().unwrap();
+        assert_eq!(
+            blocks,
+            db.block_iter::<CompactBlock>(0, end).collect::<Vec<_>>()
+        );
+
+        let mut try_iter = db.try_block_iter::<CompactBlock>(end - 1, end + 10);
+        assert!(try_iter.next().unwrap().is_ok());
+        match try_iter.next() {
+            Some(Err(Error::AtHeight { height, .. })) => assert_eq!(height, end),
+            other => panic!(
+                "expected an error at height {}, got {:?}",
+                end,
+                other.is_some()
+            ),
+        }
+        assert!(try_iter.next().is_none());
+
+        let early_end = 10000;
+        for (blk, try_blk) in db
+            .connected_block_iter::<CompactConnectedBlock>(early_end)
+            .zip(db.try_connected_block_iter::<CompactConnectedBlock>(early_end))
+        {
+            assert_eq!(blk, try_blk.unwrap());
+        }
+        let mut try_iter = db.try_connected_block_iter::<CompactConnectedBlock>(end + 1);
+        assert!(try_iter.by_ref().take(end).all(|b| b.is_ok()));
+        match try_iter.next() {
+            Some(Err(Error::AtHeight { height, .. })) => assert_eq!(height, end),
+            other => panic!(
+                "expected an error at height {}, got {:?}",
+                end,
+                other.is_some()
+            ),
+        }
+        assert!(try_iter.next().is_none());
+    }
+
+    #[test]
+    /// all UTXO backends connect the same inputs, whether outputs are spilled or not
+    fn test_connected_iter_backends() {
+        let db = get_test_db();
+        let end = 20000;
+        let expected: Vec<CompactConnectedBlock> = db.connected_block_iter(end).collect();
+        #[cfg_attr(not(feature = "on-disk-utxo"), allow(unused_mut))]
+        let mut backends = vec![UtxoBackendKind::InMemory];
+        #[cfg(feature = "on-disk-utxo")]
+        {
+            backends.push(UtxoBackendKind::OnDisk);
+            for memory_budget in [0, 1 << 16, 1 << 30] {
+                backends.push(UtxoBackendKind::Hybrid { memory_budget });
+            }
+        }
+        for backend in backends {
+            let options = ConnectedIterOptions::with_backend(backend);
+            let connected: Vec<CompactConnectedBlock> = db
+                .try_connected_block_iter_with_options(end, &options)
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert_eq!(connected, expected, "{:?}", backend);
+        }
+
+        let custom = Arc::new(HashMapUtxo::default());
+        let connected: Vec<CompactConnectedBlock> = db
+            .try_connected_block_iter_with_backend(end, custom.clone())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(connected, expected);
+        assert!(!custom.0.lock().unwrap().is_empty());
+    }
+
+    /// a third-party UTXO backend
+    #[derive(Default)]
+    struct HashMapUtxo(Mutex<HashMap<OutPoint, Coin>>);
+
+    impl<T: From<TxOut>> UtxoBackend<T> for HashMapUtxo {
+        fn insert_block(&self, block: &Block, height: usize) -> Result<(), Error> {
+            let mut unspent = self.0.lock().unwrap();
+            for tx in block.txdata.iter() {
+                let txid = tx.compute_txid();
+                for (vout, out) in (0_u32..).zip(tx.output.iter()) {
+                    let coin = Coin {
+                        out: out.clone(),
+                        height: height as u32,
+                        is_coinbase: tx.is_coinbase(),
+                    };
+                    unspent.insert(OutPoint::new(txid, vout), coin);
+                }
+            }
+            Ok(())
+        }
+
+        fn spend_block(&self, block: &Block) -> Result<Vec<Coin<T>>, Error> {
+            let mut unspent = self.0.lock().unwrap();
+            block
+                .txdata
+                .iter()
+                .filter(|tx| !tx.is_coinbase())
+                .flat_map(|tx| tx.input.iter())
+                .map(|input| {
+                    let outpoint = input.previous_output;
+                    let coin = unspent.remove(&outpoint);
+                    let coin = coin.ok_or(Error::PrevoutNotFound(outpoint))?;
+                    Ok(coin.map(T::from))
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "on-disk-utxo")]
+    /// resuming from a checkpoint yields the same blocks as a single iteration
+    fn test_connected_iter_checkpoint() {
+        let db = get_test_db();
+        let cache = tempdir::TempDir::new("utxo_cache").unwrap();
+        let (first_end, end) = (3500, 8000);
+
+        let first: Vec<CompactConnectedBlock> = db
+            .connected_block_iter_with_checkpoints(cache.path(), first_end, 1000)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(first.len(), first_end);
+
+        let resumed = db.connected_block_iter_from_checkpoint(cache.path(), end);
+        assert_eq!(resumed.start_height(), first_end);
+        let resumed: Vec<CompactConnectedBlock> = resumed.collect::<Result<_, _>>().unwrap();
+        let expected: Vec<CompactConnectedBlock> =
+            db.connected_block_iter(end).skip(first_end).collect();
+        assert_eq!(resumed, expected);
+
+        let missing = tempdir::TempDir::new("utxo_cache").unwrap();
+        let mut iter =
+            db.connected_block_iter_from_checkpoint::<CompactConnectedBlock>(missing.path(), end);
+        assert!(matches!(
+            iter.next(),
+            Some(Err(Error::CheckpointNotFound(_)))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "on-disk-utxo")]
+    /// an iteration stopped in the middle of a segment resumes from the last checkpoint
+    fn test_connected_iter_interrupted() {
+        let db = get_test_db();
+        let cache = tempdir::TempDir::new("utxo_cache").unwrap();
+        let (stopped, end) = (2500, 5000);
+
+        let mut iter = db.connected_block_iter_with_checkpoints::<CompactConnectedBlock>(
+            cache.path(),
+            end,
+            1000,
+        );
+        assert!(iter.by_ref().take(stopped).all(|b| b.is_ok()));
+        // the working cache has spent outputs of blocks after `stopped`.
+        drop(iter);
+
+        let resumed = db.connected_block_iter_from_checkpoint(cache.path(), end);
+        assert_eq!(resumed.start_height(), 2000);
+        let resumed: Vec<CompactConnectedBlock> = resumed.collect::<Result<_, _>>().unwrap();
+        let expected: Vec<CompactConnectedBlock> =
+            db.connected_block_iter(end).skip(2000).collect();
+        assert_eq!(resumed, expected);
+    }
+
+    #[test]
+    /// every block of the test chain passes block-level consensus checks
+    fn test_verify_block() {
+        let db = get_test_db();
+        let mut h = 0;
+        for result in db.verify_block_iter(0, db.get_block_count()) {
+            assert_eq!(result.height, h);
+            assert!(result.is_valid(), "block {}: {:?}", h, result.errors);
+            h += 1;
+        }
+        assert_eq!(h, db.get_block_count());
+        assert_eq!(
+            db.verify_block(0).unwrap().block_hash,
+            db.get_hash_from_height(0).unwrap()
+        );
+    }
+
+    #[test]
+    /// the test chain follows proof of work and timestamp rules
+    fn test_validate_header_chain() {
+        let db = get_test_db();
+        let report = db.validate_header_chain();
+        assert_eq!(report.headers, db.get_block_count());
+        assert!(report.is_valid(), "{:?}", report.violations);
+        let first_retarget = 32256;
+        if db.get_block_count() > first_retarget {
+            let difficulty = db.block_index.difficulty(first_retarget).unwrap();
+            assert!(difficulty > 1.0);
+        }
+        assert_eq!(db.block_index.difficulty(0), Some(1.0));
+    }
+
+    #[test]
+    /// check RPC JSON rendering against connected blocks
+    fn test_block_json() {
+        let db = get_test_db();
+        let early_end = 100000;
+
+        for (h, blk) in db
+            .connected_block_iter::<FullConnectedBlock>(early_end)
+            .enumerate()
+            .step_by(997)
+        {
+            let json = db
+                .get_block_json(h, TxVerbosity::ShowDetailsAndPrevout)
+                .unwrap();
+            assert_eq!(json.height, h);
+            assert_eq!(json.hash, blk.header.block_hash);
+            assert_eq!(json.n_tx, blk.txdata.len());
+            assert_eq!(json.weight, blk.header.weight);
+            assert_eq!(json.previousblockhash.is_some(), h > 0);
+            for (tx, ref_tx) in json.tx.iter().zip(blk.txdata.iter()) {
+                assert_eq!(tx.txid, ref_tx.txid);
+                assert_eq!(tx.vin.len(), ref_tx.input.len().max(1));
+                for (vin, input) in tx.vin.iter().zip(ref_tx.input.iter()) {
+                    let prevout = vin.prevout.as_ref().unwrap();
+                    assert_eq!(
+                        bitcoin::Amount::from_btc(prevout.value).unwrap().to_sat(),
+                        input.prevout.value
+                    );
+                }
+                if tx.vin[0].coinbase.is_none() {
+                    let fee = bitcoin::Amount::from_btc(tx.fee.unwrap()).unwrap();
+                    assert_eq!(Some(fee.to_sat()), ref_tx.fee);
+                }
+                let rpc_tx = db.get_transaction_json(tx.txid).unwrap();
+                assert_eq!(rpc_tx.blockhash, Some(json.hash));
+                assert_eq!(rpc_tx.vout, tx.vout);
+            }
+        }
+    }
+
+    #[test]
+    /// input types guessed without prevouts agree with those using prevouts
+    fn test_input_types() {
+        let db = get_test_db();
+        let early_end = 100000;
+
+        for (h, blk) in db
+            .connected_block_iter::<FullConnectedBlock>(early_end)
+            .enumerate()
+            .step_by(97)
+        {
+            let unconnected = db.get_block::<FullBlock>(h).unwrap();
+            for (tx, ref_tx) in blk.txdata.iter().zip(unconnected.txdata.iter()) {
+                assert_eq!(ref_tx.input.len(), tx.input.len());
+                for (input, tx_in) in tx.input.iter().zip(ref_tx.input.iter()) {
+                    assert_eq!(input.previous_output, tx_in.previous_output);
+                    assert_eq!(input.script_sig, tx_in.script_sig);
+                    assert_eq!(input.sequence, tx_in.sequence);
+                    assert_eq!(input.witness, tx_in.witness);
+                    assert_ne!(input.input_type, InputType::NotRecognised);
+                    if input.input_type == InputType::Pay2ScriptHash {
+                        assert!(input.revealed_scripts.redeem_script.is_some());
+                    }
+                    if input.input_type == InputType::Pay2PublicKeyHash {
+                        assert_eq!(tx_in.input_type, InputType::Pay2PublicKeyHash);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    /// ensure that undo data agrees with outputs connected using txindex
+    fn test_get_block_undo() {
+        let db = get_test_db();
+        let early_end = 100000;
+
+        for h in (1..early_end).step_by(997) {
+            let blk = db.get_connected_block::<FullConnectedBlock>(h).unwrap();
+            let undo = db.get_block_undo(h).unwrap();
+            assert_eq!(undo.txdata.len() + 1, blk.txdata.len());
+            for (tx, tx_undo) in blk.txdata.iter().skip(1).zip(undo.txdata.iter()) {
+                assert_eq!(tx.input.len(), tx_undo.prevouts.len());
+                for (input, coin) in tx.input.iter().zip(tx_undo.prevouts.iter()) {
+                    assert_eq!(input.prevout.value, coin.out.value.to_sat());
+                    assert_eq!(input.prevout.script_pubkey, coin.out.script_pubkey);
+                    assert_eq!(input.prevout_height, coin.height);
+                    assert_eq!(input.prevout_is_coinbase, coin.is_coinbase);
+                    let prevout_header = db.get_header(coin.height as usize).unwrap();
+                    assert_eq!(input.prevout_time, prevout_header.block_header.time);
+                }
+            }
+        }
+    }
+
+    /// Serialize a coin as Bitcoin Core does when hashing the UTXO set.
+    fn utxo_hash_preimage(outpoint: &OutPoint, coin: &Coin) -> Vec<u8> {
+        use bitcoin::consensus::Encodable;
+        let mut bytes = Vec::new();
+        outpoint.consensus_encode(&mut bytes).unwrap();
+        let code = (coin.height << 1) + coin.is_coinbase as u32;
+        code.consensus_encode(&mut bytes).unwrap();
+        coin.out.consensus_encode(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    /// check UTXO set summary against a UTXO set built from the blocks
+    fn test_utxo_set_hash_iter() {
+        use bitcoin_explorer::{duplicate_coinbases, MuHash3072};
+
+        let db = get_test_db();
+        let early_end = 100000;
+        let duplicates = duplicate_coinbases();
+        // recompute the hash from the whole set at these heights,
+        // after each BIP30 overwrite and at the end.
+        let mut check_muhash: Vec<usize> = duplicates.iter().map(|d| d.height).collect();
+        check_muhash.push(early_end - 1);
+
+        let mut h = 0;
+        let mut utxos: HashMap<OutPoint, Coin> = HashMap::new();
+        let mut total_amount = 0;
+        for (info, blk) in db
+            .utxo_set_hash_iter(early_end)
+            .zip(db.block_iter::<Block>(0, early_end))
+        {
+            assert_eq!(info.height, h);
+            assert_eq!(info.block_hash, blk.block_hash());
+            // outputs of the genesis block are not spendable.
+            for tx in blk.txdata.iter().filter(|_| h > 0) {
+                if !tx.is_coinbase() {
+                    for input in tx.input.iter() {
+                        let coin = utxos.remove(&input.previous_output).unwrap();
+                        total_amount -= coin.out.value.to_sat();
+                    }
+                }
+                let txid = tx.compute_txid();
+                for (vout, out) in tx.output.iter().enumerate() {
+                    let script = &out.script_pubkey;
+                    if script.is_op_return() || script.len() > 10000 {
+                        continue;
+                    }
+                    let outpoint = OutPoint::new(txid, vout as u32);
+                    let coin = Coin {
+                        out: out.clone(),
+                        height: h as u32,
+                        is_coinbase: tx.is_coinbase(),
+                    };
+                    total_amount += out.value.to_sat();
+                    if let Some(overwritten) = utxos.insert(outpoint, coin) {
+                        // only the duplicated coinbases overwrite unspent outputs.
+                        let duplicate = duplicates.iter().find(|d| d.height == h).unwrap();
+                        assert_eq!(duplicate.outpoint(), outpoint);
+                        assert_eq!(overwritten.height as usize, duplicate.overwritten_height);
+                        total_amount -= overwritten.out.value.to_sat();
+                    }
+                }
+            }
+            assert_eq!(info.txouts, utxos.len() as u64);
+            assert_eq!(info.total_amount, total_amount);
+            if check_muhash.contains(&h) {
+                let mut muhash = MuHash3072::new();
+                for (outpoint, coin) in utxos.iter() {
+                    muhash.insert(&utxo_hash_preimage(outpoint, coin));
+                }
+                assert_eq!(info.muhash.finalize(), muhash.finalize());
+            }
+            h += 1;
+        }
+        assert_eq!(h, early_end)
+    }
+
+    #[test]
+    /// the UTXO set hashes of `gettxoutsetinfo` at the genesis block, whose output is unspendable
+    fn test_utxo_set_hash_genesis() {
+        let db = get_test_db();
+        let info = db.utxo_set_hash_iter(1).next().unwrap();
+        assert_eq!(info.txouts, 0);
+        assert_eq!(info.total_amount, 0);
+        assert_eq!(
+            info.muhash.finalize().to_string(),
+            "dd5ad2a105c2d29495f577245c357409002329b9f4d6182c0af3dc2f462555c8"
+        );
+        assert_eq!(
+            db.get_hash_serialized_3(0).unwrap().to_string(),
+            "56944c5d3f98413ef45cf54545538103cc9f298e0575820ad3591376e2e0f65d"
+        );
+    }
+
+    #[test]
+    /// mempool inputs are connected to the chain through txindex, and to other mempool transactions
+    fn test_connect_mempool() {
+        use bitcoin::consensus::Encodable;
+        use bitcoin::hashes::Hash;
+        use bitcoin::{Amount, TxIn, Txid};
+        use bitcoin_explorer::MempoolFile;
+
+        let db = get_test_db();
+        let confirmed = db.get_block::<Block>(170).unwrap().txdata.remove(1);
+        let funding = confirmed.output[0].clone();
+        let spend = |outpoints: Vec<OutPoint>, value: u64| {
+            let mut tx = confirmed.clone();
+            tx.input = outpoints
+                .into_iter()
+                .map(|previous_output| TxIn {
+                    previous_output,
+                    ..TxIn::default()
+                })
+                .collect();
+            tx.output = vec![TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: funding.script_pubkey.clone(),
+            }];
+            tx
+        };
+        let parent = spend(vec![OutPoint::new(confirmed.compute_txid(), 0)], 1000);
+        let unknown = OutPoint::new(Txid::from_byte_array([7; 32]), 0);
+        let child = spend(vec![OutPoint::new(parent.compute_txid(), 0), unknown], 100);
+
+        // `mempool.dat` before Bitcoin Core 28.0, without XOR key.
+        let mut data = Vec::new();
+        1_u64.consensus_encode(&mut data).unwrap();
+        2_u64.consensus_encode(&mut data).unwrap();
+        for tx in [&parent, &child] {
+            tx.consensus_encode(&mut data).unwrap();
+            1_700_000_000_i64.consensus_encode(&mut data).unwrap();
+            0_i64.consensus_encode(&mut data).unwrap();
+        }
+        // no fee deltas, no unbroadcast transactions.
+        data.extend([0, 0]);
+        let path = std::env::temp_dir().join(format!("mempool-{}.dat", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        let mempool = MempoolFile::read(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let entries = db.connect_mempool(mempool);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].txid, parent.compute_txid());
+        assert_eq!(entries[0].prevouts, vec![Some(funding.clone())]);
+        assert_eq!(entries[0].fee, Some(funding.value.to_sat() - 1000));
+        assert_eq!(
+            entries[1].prevouts,
+            vec![Some(parent.output[0].clone()), None]
+        );
+        assert_eq!(entries[1].fee, None);
+    }
+
+    #[test]
+    /// the chain work of each block adds its work to the chain work of its parent
+    fn test_chain_work() {
+        let db = get_test_db();
+        let index = &db.block_index;
+        let genesis = &index.records[0].block_header;
+        assert_eq!(index.chain_work(0), Some(genesis.work()));
+        for h in 1..index.records.len() {
+            let work = index.records[h].block_header.work();
+            assert_eq!(
+                index.chain_work(h),
+                Some(index.chain_work(h - 1).unwrap() + work)
+            );
+        }
+        assert_eq!(index.chain_work(index.records.len()), None);
+    }
+
     #[test]
     fn test_iter_block_heights() {
         let db = get_test_db();